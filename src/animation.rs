@@ -0,0 +1,490 @@
+//! Animations (e.g. gifs) of recent camera images.
+//!
+//! Animations are made by an `AnimationEncoder`. The default encoder decodes and encodes images in
+//! pure Rust, so it works everywhere. If atlas is built with the `magick_rust` feature,
+//! ImageMagick can be used instead.
+
+use std::fmt::Debug;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use chrono::{DateTime, Duration, UTC};
+
+use gif::{self, Repeat, SetParameter};
+
+use image::{self, DynamicImage, FilterType, GenericImage};
+
+use iron::{Handler, status};
+use iron::prelude::*;
+use iron::mime::Mime;
+
+use {Error, Result};
+use cam::Camera;
+use overlay::Overlay;
+use scratch::ScratchDir;
+use select::Selection;
+use video::{FfmpegEncoder, VideoFormat};
+use watch::DirectoryWatcher;
+
+/// Common animation configuration values.
+#[derive(Copy, Clone, Debug)]
+pub struct GifConfig {
+    /// The length of time between frames of the animation.
+    pub delay: Duration,
+    /// The height of the animation.
+    pub height: u64,
+    /// The width of the animation.
+    pub width: u64,
+}
+
+impl Default for GifConfig {
+    fn default() -> GifConfig {
+        GifConfig {
+            width: 512,
+            height: 384,
+            delay: Duration::milliseconds(500),
+        }
+    }
+}
+
+/// Turns a sequence of images into an animation.
+pub trait AnimationEncoder: Debug + Send + Sync {
+    /// Encodes the images at the given paths, in order, into an animation.
+    fn encode(&self, paths: &[PathBuf], config: &GifConfig) -> Result<Vec<u8>>;
+
+    /// Returns the content type of the encoded animation, e.g. `"image/gif"`.
+    fn content_type(&self) -> &'static str;
+
+    /// Returns the file extension of the encoded animation, e.g. `"gif"`.
+    fn extension(&self) -> &'static str;
+}
+
+/// Returns the encoder with the given name.
+///
+/// `"gif"` is the pure-Rust gif encoder. `"magick"` is the ImageMagick gif encoder, which is only
+/// available with the `magick_rust` feature. `"mp4"` and `"webm"` make videos with the `ffmpeg`
+/// on the `PATH`.
+///
+/// # Examples
+///
+/// ```
+/// # use atlas::animation;
+/// let encoder = animation::encoder("gif").unwrap();
+/// assert_eq!("image/gif", encoder.content_type());
+/// assert!(animation::encoder("png").is_err());
+/// ```
+pub fn encoder(name: &str) -> Result<Box<AnimationEncoder>> {
+    match name {
+        "gif" => Ok(Box::new(GifEncoder)),
+        "magick" => magick_encoder(),
+        "mp4" => Ok(Box::new(FfmpegEncoder::new(VideoFormat::Mp4))),
+        "webm" => Ok(Box::new(FfmpegEncoder::new(VideoFormat::Webm))),
+        _ => Err(Error::InvalidEncoder(name.to_string())),
+    }
+}
+
+/// Returns the default encoder.
+///
+/// This is ImageMagick if atlas was built with it, else the pure-Rust gif encoder.
+///
+/// # Examples
+///
+/// ```
+/// # use atlas::animation;
+/// let encoder = animation::default_encoder();
+/// ```
+pub fn default_encoder() -> Box<AnimationEncoder> {
+    magick_encoder().unwrap_or(Box::new(GifEncoder))
+}
+
+#[cfg(feature = "magick_rust")]
+fn magick_encoder() -> Result<Box<AnimationEncoder>> {
+    Ok(Box::new(::magick::MagickEncoder))
+}
+
+#[cfg(not(feature = "magick_rust"))]
+fn magick_encoder() -> Result<Box<AnimationEncoder>> {
+    Err(Error::InvalidEncoder("atlas was not built with ImageMagick".to_string()))
+}
+
+/// A gif encoder that doesn't need any system libraries.
+///
+/// Each image is scaled to fit in the configured size and centered on the canvas. Images that
+/// can't be decoded are skipped.
+#[derive(Clone, Copy, Debug)]
+pub struct GifEncoder;
+
+impl AnimationEncoder for GifEncoder {
+    fn encode(&self, paths: &[PathBuf], config: &GifConfig) -> Result<Vec<u8>> {
+        let width = config.width as u16;
+        let height = config.height as u16;
+        let delay = (config.delay.num_milliseconds() / 10) as u16;
+        let mut bytes = Vec::new();
+        {
+            let mut encoder = try!(gif::Encoder::new(&mut bytes, width, height, &[]));
+            try!(encoder.set(Repeat::Infinite));
+            for path in paths {
+                let image = match image::open(path) {
+                    Ok(image) => image,
+                    Err(err) => {
+                        warn!("Skipping {} in gif: {}", path.to_string_lossy(), err);
+                        continue;
+                    }
+                };
+                let mut frame = frame(&image, width, height);
+                frame.delay = delay;
+                try!(encoder.write_frame(&frame));
+            }
+        }
+        Ok(bytes)
+    }
+
+    fn content_type(&self) -> &'static str {
+        "image/gif"
+    }
+
+    fn extension(&self) -> &'static str {
+        "gif"
+    }
+}
+
+fn frame(image: &DynamicImage, width: u16, height: u16) -> gif::Frame<'static> {
+    let image = image.resize(width as u32, height as u32, FilterType::Triangle);
+    let (frame_width, frame_height) = image.dimensions();
+    let mut frame = gif::Frame::from_rgb(frame_width as u16,
+                                         frame_height as u16,
+                                         &image.to_rgb().into_raw());
+    frame.left = (width - frame_width as u16) / 2;
+    frame.top = (height - frame_height as u16) / 2;
+    frame
+}
+
+/// Makes animations of a camera's images.
+#[derive(Debug)]
+pub struct Animator {
+    camera: Camera,
+    config: GifConfig,
+    encoder: Box<AnimationEncoder>,
+    selection: Selection,
+    overlay: Option<Overlay>,
+}
+
+impl Animator {
+    /// Creates a new animator for the given camera that uses the given encoder.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use atlas::animation::{self, Animator};
+    /// let animator = Animator::new(atlas::cam::Camera::new("ATLAS_CAM", "data").unwrap(),
+    ///                              Default::default(),
+    ///                              animation::default_encoder());
+    /// ```
+    pub fn new(camera: Camera, config: GifConfig, encoder: Box<AnimationEncoder>) -> Animator {
+        Animator {
+            camera: camera,
+            config: config,
+            encoder: encoder,
+            selection: Default::default(),
+            overlay: None,
+        }
+    }
+
+    /// Sets the policies used to select which images become frames.
+    ///
+    /// By default every image is used.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use atlas::animation::{self, Animator};
+    /// # use atlas::select::Selection;
+    /// let selection = Selection { max_per_day: Some(24), ..Default::default() };
+    /// let animator = Animator::new(atlas::cam::Camera::new("ATLAS_CAM", "data").unwrap(),
+    ///                              Default::default(),
+    ///                              animation::default_encoder())
+    ///     .selection(selection);
+    /// ```
+    pub fn selection(mut self, selection: Selection) -> Animator {
+        self.selection = selection;
+        self
+    }
+
+    /// Sets a text overlay to draw on every frame.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use atlas::animation::{self, Animator};
+    /// # use atlas::overlay::Overlay;
+    /// let overlay = Overlay::from_path("/usr/share/fonts/dejavu/DejaVuSans.ttf").unwrap();
+    /// let animator = Animator::new(atlas::cam::Camera::new("ATLAS_CAM", "data").unwrap(),
+    ///                              Default::default(),
+    ///                              animation::default_encoder())
+    ///     .overlay(overlay);
+    /// ```
+    pub fn overlay(mut self, overlay: Overlay) -> Animator {
+        self.overlay = Some(overlay);
+        self
+    }
+
+    /// Returns the content type of this animator's animations.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use atlas::animation::{self, Animator};
+    /// let animator = Animator::new(atlas::cam::Camera::new("ATLAS_CAM", "data").unwrap(),
+    ///                              Default::default(),
+    ///                              animation::encoder("gif").unwrap());
+    /// assert_eq!("image/gif", animator.content_type());
+    /// ```
+    pub fn content_type(&self) -> &'static str {
+        self.encoder.content_type()
+    }
+
+    /// Returns an animation of the selected images since the given date time.
+    ///
+    /// ```
+    /// # extern crate chrono;
+    /// # extern crate atlas;
+    /// # use chrono::{UTC, TimeZone};
+    /// # use atlas::animation::{self, Animator};
+    /// # fn main() {
+    /// let animator = Animator::new(atlas::cam::Camera::new("ATLAS_CAM", "data").unwrap(),
+    ///                              Default::default(),
+    ///                              animation::encoder("gif").unwrap());
+    /// let ref datetime = UTC.ymd(2016, 7, 25).and_hms(0, 0, 0);
+    /// let gif = animator.since(datetime).unwrap();
+    /// # }
+    pub fn since(&self, since: &DateTime<UTC>) -> Result<Vec<u8>> {
+        let frames = try!(self.frames_since(since));
+        self.encode(&frames)
+    }
+
+    /// Returns the paths of the selected images since the given date time.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate chrono;
+    /// # extern crate atlas;
+    /// # use chrono::{UTC, TimeZone};
+    /// # use atlas::animation::{self, Animator};
+    /// # fn main() {
+    /// let animator = Animator::new(atlas::cam::Camera::new("ATLAS_CAM", "data").unwrap(),
+    ///                              Default::default(),
+    ///                              animation::encoder("gif").unwrap());
+    /// let frames = animator.frames_since(&UTC.ymd(2016, 7, 25).and_hms(0, 0, 0)).unwrap();
+    /// assert_eq!(2, frames.len());
+    /// # }
+    /// ```
+    pub fn frames_since(&self, since: &DateTime<UTC>) -> Result<Vec<PathBuf>> {
+        let paths = try!(self.camera.paths_since(since));
+        Ok(self.selection.select(&self.camera, paths))
+    }
+
+    /// Returns an animation of the selected images taken at or after `start` and before `end`.
+    ///
+    /// ```
+    /// # extern crate chrono;
+    /// # extern crate atlas;
+    /// # use chrono::{UTC, TimeZone};
+    /// # use atlas::animation::{self, Animator};
+    /// # fn main() {
+    /// let animator = Animator::new(atlas::cam::Camera::new("ATLAS_CAM", "data").unwrap(),
+    ///                              Default::default(),
+    ///                              animation::encoder("gif").unwrap());
+    /// let gif = animator.between(&UTC.ymd(2016, 7, 25).and_hms(0, 0, 0),
+    ///                            &UTC.ymd(2016, 7, 26).and_hms(0, 0, 0))
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub fn between(&self, start: &DateTime<UTC>, end: &DateTime<UTC>) -> Result<Vec<u8>> {
+        let paths = try!(self.camera.paths_between(start, end));
+        self.encode(&self.selection.select(&self.camera, paths))
+    }
+
+    /// Encodes already-selected frames into an animation, drawing the overlay if there is one.
+    pub fn encode(&self, frames: &[PathBuf]) -> Result<Vec<u8>> {
+        match self.overlay {
+            Some(ref overlay) => {
+                let directory = try!(ScratchDir::new("atlas-overlay"));
+                let frames = try!(overlay.apply(&self.camera, frames, directory.path()));
+                self.encoder.encode(&frames, &self.config)
+            }
+            None => self.encoder.encode(frames, &self.config),
+        }
+    }
+}
+
+/// Watches a directory and refreshes an animation.
+#[derive(Debug)]
+pub struct AnimationWatcher {
+    directory: PathBuf,
+    animator: Animator,
+    animation: Arc<RwLock<Vec<u8>>>,
+    duration: Duration,
+}
+
+impl AnimationWatcher {
+    /// Creates a new watcher.
+    ///
+    /// This watcher, when started with `watch`, will react to any changes to the animator's
+    /// camera directory. When it detects a change (e.g. a new image file) it will re-create the
+    /// animation using all images between now and `duration` ago.
+    ///
+    /// The new animation is built off to the side and swapped in when it is done, so the old one
+    /// keeps being served in the meantime. If no frames are selected or encoding fails, the old
+    /// animation is kept.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate chrono;
+    /// # extern crate atlas;
+    /// use chrono::Duration;
+    /// # use std::sync::{RwLock, Arc};
+    /// # use atlas::animation::{self, Animator, AnimationWatcher};
+    /// # fn main() {
+    /// let animator = Animator::new(atlas::cam::Camera::new("ATLAS_CAM", "data").unwrap(),
+    ///                              Default::default(),
+    ///                              animation::default_encoder());
+    /// let gif = Arc::new(RwLock::new(Vec::new()));
+    /// let watcher = AnimationWatcher::new(animator, Duration::days(2), gif);
+    /// # }
+    /// ```
+    pub fn new(animator: Animator,
+               duration: Duration,
+               animation: Arc<RwLock<Vec<u8>>>)
+               -> AnimationWatcher {
+        AnimationWatcher {
+            directory: animator.camera.path().to_path_buf(),
+            animator: animator,
+            animation: animation,
+            duration: duration,
+        }
+    }
+}
+
+impl DirectoryWatcher for AnimationWatcher {
+    fn directory(&self) -> &Path {
+        self.directory.as_path()
+    }
+
+    fn refresh(&mut self) -> Result<()> {
+        let frames = try!(self.animator.frames_since(&(UTC::now() - self.duration)));
+        if frames.is_empty() {
+            warn!("No frames for the animation of {}, keeping the old one",
+                  self.animator.camera.name());
+            return Ok(());
+        }
+        let new_animation = try!(self.animator.encode(&frames));
+        if new_animation.is_empty() {
+            return Ok(());
+        }
+        let old_animation = mem::replace(&mut *self.animation.write().unwrap(), new_animation);
+        // Drop the old buffer after the write lock has been released.
+        drop(old_animation);
+        Ok(())
+    }
+}
+
+/// Iron `Handler` that serves up an animation of the ATLAS system.
+#[derive(Debug)]
+pub struct AnimationHandler {
+    animation: Arc<RwLock<Vec<u8>>>,
+    content_type: &'static str,
+}
+
+impl AnimationHandler {
+    /// Creates a new handler that will serve the provided animation with the given content type.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use std::sync::{Arc, RwLock};
+    /// # use atlas::animation::AnimationHandler;
+    /// let gif = Arc::new(RwLock::new(Vec::new()));
+    /// let handler = AnimationHandler::new(gif.clone(), "image/gif");
+    /// ```
+    pub fn new(animation: Arc<RwLock<Vec<u8>>>, content_type: &'static str) -> AnimationHandler {
+        AnimationHandler {
+            animation: animation,
+            content_type: content_type,
+        }
+    }
+}
+
+impl Handler for AnimationHandler {
+    fn handle(&self, _: &mut Request) -> IronResult<Response> {
+        let animation = self.animation.read().unwrap();
+        if animation.is_empty() {
+            return Ok(Response::with((status::ServiceUnavailable, "animation is empty")));
+        }
+        let content_type = self.content_type.parse::<Mime>().unwrap();
+        Ok(Response::with((content_type, status::Ok, animation.clone())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{Arc, RwLock};
+
+    use chrono::{Duration, TimeZone, UTC};
+
+    use gif;
+
+    use cam::Camera;
+
+    #[test]
+    fn makes_gif() {
+        let animator = Animator::new(Camera::new("ATLAS_CAM", "data").unwrap(),
+                                     GifConfig {
+                                         width: 512,
+                                         height: 282,
+                                         delay: Duration::milliseconds(200),
+                                     },
+                                     Box::new(GifEncoder));
+        let bytes = animator.since(&UTC.ymd(2016, 1, 1).and_hms(0, 0, 0)).unwrap();
+        let mut decoder = gif::Decoder::new(&bytes[..]).read_info().unwrap();
+        assert_eq!(512, decoder.width());
+        assert_eq!(282, decoder.height());
+        let mut frames = 0;
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            assert_eq!(20, frame.delay);
+            frames += 1;
+        }
+        assert_eq!(2, frames);
+    }
+
+    #[test]
+    fn skips_undecodable() {
+        let bytes = GifEncoder.encode(&["data/config.toml".into()], &Default::default()).unwrap();
+        let mut decoder = gif::Decoder::new(&bytes[..]).read_info().unwrap();
+        assert!(decoder.read_next_frame().unwrap().is_none());
+    }
+
+    #[test]
+    fn keeps_old_animation() {
+        let animator = Animator::new(Camera::new("ATLAS_CAM", "data").unwrap(),
+                                     Default::default(),
+                                     Box::new(GifEncoder));
+        let animation = Arc::new(RwLock::new(vec![1, 2, 3]));
+        let mut watcher = AnimationWatcher::new(animator, Duration::days(1), animation.clone());
+        watcher.refresh().unwrap();
+        assert_eq!(vec![1, 2, 3], *animation.read().unwrap());
+
+        let animator = Animator::new(Camera::new("ATLAS_CAM", "data").unwrap(),
+                                     Default::default(),
+                                     Box::new(GifEncoder));
+        let mut watcher = AnimationWatcher::new(animator,
+                                                UTC::now() - UTC.ymd(2016, 1, 1).and_hms(0, 0, 0),
+                                                animation.clone());
+        watcher.refresh().unwrap();
+        assert!(animation.read().unwrap().starts_with(b"GIF"));
+    }
+}
@@ -0,0 +1,311 @@
+//! A versioned JSON API for the heartbeats.
+//!
+//! The web pages and the CSV files are made for people and for our own charts. Partners who want
+//! to integrate with the ATLAS data use this API instead, which serves every heartbeat field with
+//! its units, along with the metadata of the SBD messages that carried the heartbeat and their
+//! Iridium-reported locations. All datetimes are RFC 3339 strings in UTC.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+
+use chrono::{DateTime, UTC};
+
+use iron::{Handler, status};
+use iron::headers::AccessControlAllowOrigin;
+use iron::prelude::*;
+use iron::mime::Mime;
+
+use router::Router;
+
+use rustc_serialize::json::{Json, ToJson};
+
+use heartbeat::HeartbeatV1;
+use iridium::MessageInfo;
+use location;
+use query;
+
+const DEFAULT_PER_PAGE: usize = 100;
+const MAX_PER_PAGE: usize = 1000;
+
+/// Serves the heartbeats as JSON.
+#[derive(Clone, Debug)]
+pub struct Api {
+    heartbeats: Arc<RwLock<Vec<HeartbeatV1>>>,
+    messages: Arc<RwLock<Vec<MessageInfo>>>,
+}
+
+impl Api {
+    /// Creates a new API for the given heartbeats, which must be sorted by datetime.
+    ///
+    /// The message index is used to look up the locations of each heartbeat's messages.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use std::sync::{Arc, RwLock};
+    /// # use atlas::api::Api;
+    /// let api = Api::new(Arc::new(RwLock::new(Vec::new())), Arc::new(RwLock::new(Vec::new())));
+    /// ```
+    pub fn new(heartbeats: Arc<RwLock<Vec<HeartbeatV1>>>,
+               messages: Arc<RwLock<Vec<MessageInfo>>>)
+               -> Api {
+        Api {
+            heartbeats: heartbeats,
+            messages: messages,
+        }
+    }
+
+    /// Returns a handler for a paginated list of heartbeats, oldest first.
+    ///
+    /// Heartbeats can be filtered with the `start` and `end` query parameters, which are either
+    /// dates (`%Y-%m-%d`, inclusive) or RFC 3339 datetimes. Use `page` and `per_page` to
+    /// paginate.
+    pub fn heartbeats_handler(&self) -> HeartbeatsHandler {
+        HeartbeatsHandler { api: self.clone() }
+    }
+
+    /// Returns a handler for the most recent heartbeat.
+    pub fn latest_handler(&self) -> LatestHeartbeatHandler {
+        LatestHeartbeatHandler { api: self.clone() }
+    }
+
+    /// Returns a handler for the heartbeat at the datetime in the `datetime` route parameter.
+    pub fn heartbeat_handler(&self) -> HeartbeatHandler {
+        HeartbeatHandler { api: self.clone() }
+    }
+}
+
+/// Returns the JSON representation of a heartbeat.
+///
+/// Each field is an object with a `value` and its `units`. Messages get a `location` if the
+/// Iridium gateway reported one, as found in `messages`.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate sbd;
+/// # extern crate atlas;
+/// # use sbd::mo::Message;
+/// # use atlas::api;
+/// # use atlas::heartbeat::IntoHeartbeats;
+/// # fn main() {
+/// let messages = vec![Message::from_path("data/150729_020200.sbd").unwrap()];
+/// let heartbeat = messages.into_heartbeats().unwrap().pop().unwrap().unwrap();
+/// let json = api::heartbeat_json(&heartbeat, &[]);
+/// assert_eq!("2015-07-29T02:02:00+00:00", json["datetime"].as_string().unwrap());
+/// # }
+/// ```
+pub fn heartbeat_json(heartbeat: &HeartbeatV1, messages: &[MessageInfo]) -> Json {
+    let mut fields = BTreeMap::new();
+    for measurement in heartbeat.measurements() {
+        let mut field = BTreeMap::new();
+        field.insert("value".to_string(), (measurement.value as f64).to_json());
+        field.insert("units".to_string(), measurement.units.to_json());
+        fields.insert(measurement.name.to_string(), Json::Object(field));
+    }
+    let mut program = BTreeMap::new();
+    program.insert("value".to_string(),
+                   heartbeat.measurement_program.to_string().to_json());
+    program.insert("units".to_string(), Json::Null);
+    fields.insert("measurement_program".to_string(), Json::Object(program));
+
+    let messages = heartbeat.messages
+        .iter()
+        .map(|message| {
+            let mut map = BTreeMap::new();
+            map.insert("imei".to_string(), message.imei().to_json());
+            map.insert("momsn".to_string(), message.momsn().to_json());
+            map.insert("time_of_session".to_string(),
+                       message.time_of_session().to_rfc3339().to_json());
+            let location = location::find(messages,
+                                          message.imei(),
+                                          message.momsn(),
+                                          &message.time_of_session());
+            map.insert("location".to_string(),
+                       location.map(|location| {
+                               let mut map = BTreeMap::new();
+                               map.insert("latitude".to_string(), location.latitude.to_json());
+                               map.insert("longitude".to_string(), location.longitude.to_json());
+                               map.insert("cep_radius".to_string(),
+                                          location.cep_radius.to_json());
+                               Json::Object(map)
+                           })
+                           .unwrap_or(Json::Null));
+            Json::Object(map)
+        })
+        .collect();
+
+    let mut map = BTreeMap::new();
+    map.insert("datetime".to_string(), heartbeat.datetime().to_rfc3339().to_json());
+    map.insert("scan_start_datetime".to_string(),
+               heartbeat.scan_start_datetime.to_rfc3339().to_json());
+    map.insert("fields".to_string(), Json::Object(fields));
+    map.insert("messages".to_string(), Json::Array(messages));
+    Json::Object(map)
+}
+
+fn json_response(data: Json) -> Response {
+    let content_type = "application/json".parse::<Mime>().unwrap();
+    let mut response = Response::with((content_type, status::Ok, data.to_string()));
+    response.headers.set(AccessControlAllowOrigin::Any);
+    response
+}
+
+/// Serves a paginated list of heartbeats.
+#[derive(Debug)]
+pub struct HeartbeatsHandler {
+    api: Api,
+}
+
+impl Handler for HeartbeatsHandler {
+    fn handle(&self, request: &mut Request) -> IronResult<Response> {
+        let params = query::params(request);
+        let start = match params.get("start") {
+            Some(s) => {
+                Some(iexpect!(query::parse_bound(s, false),
+                              (status::BadRequest, "Invalid start.")))
+            }
+            None => None,
+        };
+        let end = match params.get("end") {
+            Some(s) => {
+                Some(iexpect!(query::parse_bound(s, true), (status::BadRequest, "Invalid end.")))
+            }
+            None => None,
+        };
+        let page = match params.get("page") {
+            Some(s) => itry!(s.parse::<usize>(), status::BadRequest),
+            None => 1,
+        };
+        if page == 0 {
+            return Ok(Response::with((status::BadRequest, "Pages start at 1.")));
+        }
+        let per_page = match params.get("per_page") {
+            Some(s) => itry!(s.parse::<usize>(), status::BadRequest),
+            None => DEFAULT_PER_PAGE,
+        };
+        let per_page = if per_page == 0 {
+            1
+        } else if per_page > MAX_PER_PAGE {
+            MAX_PER_PAGE
+        } else {
+            per_page
+        };
+
+        let heartbeats = self.api.heartbeats.read().unwrap();
+        let messages = self.api.messages.read().unwrap();
+        let matching = heartbeats.iter()
+            .filter(|h| in_range(&h.datetime(), start.as_ref(), end.as_ref()))
+            .collect::<Vec<_>>();
+        let mut data = BTreeMap::new();
+        data.insert("page".to_string(), page.to_json());
+        data.insert("per_page".to_string(), per_page.to_json());
+        data.insert("pages".to_string(),
+                    ((matching.len() + per_page - 1) / per_page).to_json());
+        data.insert("total".to_string(), matching.len().to_json());
+        data.insert("heartbeats".to_string(),
+                    Json::Array(matching.iter()
+                        .skip((page - 1).saturating_mul(per_page))
+                        .take(per_page)
+                        .map(|h| heartbeat_json(h, &messages))
+                        .collect()));
+        Ok(json_response(Json::Object(data)))
+    }
+}
+
+fn in_range(datetime: &DateTime<UTC>,
+            start: Option<&DateTime<UTC>>,
+            end: Option<&DateTime<UTC>>)
+            -> bool {
+    start.map(|s| datetime >= s).unwrap_or(true) && end.map(|e| datetime < e).unwrap_or(true)
+}
+
+/// Serves the most recent heartbeat.
+#[derive(Debug)]
+pub struct LatestHeartbeatHandler {
+    api: Api,
+}
+
+impl Handler for LatestHeartbeatHandler {
+    fn handle(&self, _: &mut Request) -> IronResult<Response> {
+        let heartbeats = self.api.heartbeats.read().unwrap();
+        let heartbeat = iexpect!(heartbeats.last(), (status::NotFound, "No heartbeats."));
+        Ok(json_response(heartbeat_json(heartbeat, &self.api.messages.read().unwrap())))
+    }
+}
+
+/// Serves the heartbeat at a datetime.
+#[derive(Debug)]
+pub struct HeartbeatHandler {
+    api: Api,
+}
+
+impl Handler for HeartbeatHandler {
+    fn handle(&self, request: &mut Request) -> IronResult<Response> {
+        let datetime = {
+            let s = iexpect!(request.extensions.get::<Router>().and_then(|p| p.find("datetime")));
+            itry!(DateTime::parse_from_rfc3339(s), status::BadRequest).with_timezone(&UTC)
+        };
+        let heartbeats = self.api.heartbeats.read().unwrap();
+        let heartbeat = iexpect!(heartbeats.iter().find(|h| h.datetime() == datetime),
+                                 (status::NotFound, "No heartbeat at that datetime."));
+        Ok(json_response(heartbeat_json(heartbeat, &self.api.messages.read().unwrap())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::in_range;
+
+    use chrono::{TimeZone, UTC};
+
+    use sbd::mo::Message;
+
+    use heartbeat::IntoHeartbeats;
+    use iridium::{MessageInfo, MoLocation};
+
+    #[test]
+    fn fields_with_units() {
+        let messages = vec![Message::from_path("data/160714_000240.sbd").unwrap(),
+                            Message::from_path("data/160714_000252.sbd").unwrap()];
+        let heartbeat = messages.into_heartbeats().unwrap().pop().unwrap().unwrap();
+        let json = heartbeat_json(&heartbeat, &[]);
+        let fields = json["fields"].as_object().unwrap();
+        assert_eq!(33, fields.len());
+        assert_eq!("°C",
+                   fields["temperature_external"]["units"].as_string().unwrap());
+        assert!((fields["temperature_external"]["value"].as_f64().unwrap() - 10.21).abs() < 1e-3);
+        let messages = json["messages"].as_array().unwrap();
+        assert_eq!(2, messages.len());
+        assert!(messages[0]["imei"].as_string().is_some());
+        assert!(messages[0]["momsn"].as_u64().is_some());
+        assert!(messages[0]["location"].is_null());
+    }
+
+    #[test]
+    fn message_locations() {
+        let messages = vec![Message::from_path("data/160714_000240.sbd").unwrap(),
+                            Message::from_path("data/160714_000252.sbd").unwrap()];
+        let heartbeat = messages.into_heartbeats().unwrap().pop().unwrap().unwrap();
+        let mut infos = vec![MessageInfo::read("data/160714_000240.sbd").unwrap(),
+                             MessageInfo::read("data/160714_000252.sbd").unwrap()];
+        infos[1].location = Some(MoLocation {
+            latitude: -69.5,
+            longitude: 49.25,
+            cep_radius: 4,
+        });
+        let json = heartbeat_json(&heartbeat, &infos);
+        let messages = json["messages"].as_array().unwrap();
+        assert!(messages[0]["location"].is_null());
+        assert_eq!(4, messages[1]["location"]["cep_radius"].as_u64().unwrap());
+    }
+
+    #[test]
+    fn range() {
+        let datetime = UTC.ymd(2016, 7, 14).and_hms(0, 2, 40);
+        assert!(in_range(&datetime, None, None));
+        assert!(in_range(&datetime, Some(&datetime), None));
+        assert!(!in_range(&datetime, None, Some(&datetime)));
+    }
+}
@@ -0,0 +1,386 @@
+//! Archive and thin out old camera images.
+//!
+//! Camera directories grow forever, and listing years of images in one directory gets slow. The
+//! archiver moves images older than a few days into `YYYY/MM/` subdirectories, where `Camera`
+//! still finds them, optionally recompressing them on the way. A retention policy then thins
+//! old images down to one per day, and can delete the oldest images altogether.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time;
+
+use chrono::{DateTime, Duration, FixedOffset, Timelike, UTC};
+
+use image::{self, ColorType, GenericImage};
+use image::jpeg::JPEGEncoder;
+
+use {Error, Result};
+use cam::Camera;
+
+/// How often the archiver runs, in seconds.
+pub const ARCHIVE_INTERVAL_SECS: u64 = 60 * 60;
+
+/// When to archive, thin, and delete images.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Policy {
+    /// Images older than this are moved into the archive.
+    pub archive_after: Duration,
+    /// If set, archived images are recompressed with this JPEG quality, from 1 to 100.
+    ///
+    /// Recompressed images lose their EXIF data. Images that don't get smaller are left alone.
+    pub quality: Option<u8>,
+    /// If set, images older than this are thinned to one per day.
+    pub thin_after: Option<Duration>,
+    /// If set, images older than this are deleted.
+    pub delete_after: Option<Duration>,
+}
+
+impl Default for Policy {
+    fn default() -> Policy {
+        Policy {
+            archive_after: Duration::days(30),
+            quality: None,
+            thin_after: Some(Duration::days(365)),
+            delete_after: None,
+        }
+    }
+}
+
+impl Policy {
+    /// Checks that this policy makes sense.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use atlas::archive::Policy;
+    /// assert!(Policy::default().validate().is_ok());
+    /// assert!(Policy { quality: Some(0), ..Default::default() }.validate().is_err());
+    /// ```
+    pub fn validate(&self) -> Result<()> {
+        let invalid = |message: &str| Err(Error::ServerConfigError(message.to_string()));
+        if self.archive_after < Duration::zero() {
+            return invalid("archive age can't be negative");
+        }
+        if let Some(quality) = self.quality {
+            if quality < 1 || quality > 100 {
+                return invalid("archive quality must be between 1 and 100");
+            }
+        }
+        if let (Some(thin_after), Some(delete_after)) = (self.thin_after, self.delete_after) {
+            if delete_after < thin_after {
+                return invalid("images can't be deleted before they are thinned");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What a single archiving pass did.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Report {
+    /// The number of images moved into the archive.
+    pub archived: usize,
+    /// The number of archived images that were recompressed.
+    pub recompressed: usize,
+    /// The number of images removed while thinning.
+    pub thinned: usize,
+    /// The number of images deleted for being too old.
+    pub deleted: usize,
+}
+
+/// Archives one camera's images according to a policy.
+#[derive(Debug)]
+pub struct Archiver {
+    camera: Camera,
+    policy: Policy,
+    stop: Option<Arc<AtomicBool>>,
+}
+
+impl Archiver {
+    /// Creates a new archiver for a camera.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use atlas::archive::{Archiver, Policy};
+    /// # use atlas::cam::Camera;
+    /// let camera = Camera::new("ATLAS_CAM", "data").unwrap();
+    /// let archiver = Archiver::new(camera, Policy::default());
+    /// ```
+    pub fn new(camera: Camera, policy: Policy) -> Archiver {
+        Archiver {
+            camera: camera,
+            policy: policy,
+            stop: None,
+        }
+    }
+
+    /// Stops running once the flag is set.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use std::sync::Arc;
+    /// # use std::sync::atomic::AtomicBool;
+    /// # use atlas::archive::{Archiver, Policy};
+    /// # use atlas::cam::Camera;
+    /// let camera = Camera::new("ATLAS_CAM", "data").unwrap();
+    /// let stop = Arc::new(AtomicBool::new(false));
+    /// let archiver = Archiver::new(camera, Policy::default()).stop(stop.clone());
+    /// ```
+    pub fn stop(mut self, stop: Arc<AtomicBool>) -> Archiver {
+        self.stop = Some(stop);
+        self
+    }
+
+    /// Archives, thins, and deletes images once every `ARCHIVE_INTERVAL_SECS`, until stopped.
+    pub fn run(self) {
+        while !self.stopped() {
+            match self.archive(&UTC::now()) {
+                Ok(report) => {
+                    if report != Report::default() {
+                        info!("Archived {}: {:?}", self.camera.name(), report);
+                    }
+                }
+                Err(err) => error!("Error while archiving {}: {}", self.camera.name(), err),
+            }
+            for _ in 0..ARCHIVE_INTERVAL_SECS {
+                if self.stopped() {
+                    break;
+                }
+                thread::sleep(time::Duration::from_secs(1));
+            }
+        }
+        info!("Archiver for {} stopped", self.camera.name());
+    }
+
+    fn stopped(&self) -> bool {
+        self.stop.as_ref().map_or(false, |stop| stop.load(Ordering::SeqCst))
+    }
+
+    /// Makes a single archiving pass, as if it were `now`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate chrono;
+    /// # extern crate atlas;
+    /// # use chrono::{Duration, TimeZone, UTC};
+    /// # use atlas::archive::{Archiver, Policy};
+    /// # use atlas::cam::Camera;
+    /// # fn main() {
+    /// let camera = Camera::new("ATLAS_CAM", "data").unwrap();
+    /// let policy = Policy { archive_after: Duration::days(36500), ..Default::default() };
+    /// let archiver = Archiver::new(camera, policy);
+    /// let report = archiver.archive(&UTC.ymd(2016, 8, 1).and_hms(0, 0, 0)).unwrap();
+    /// assert_eq!(0, report.archived);
+    /// # }
+    /// ```
+    pub fn archive(&self, now: &DateTime<UTC>) -> Result<Report> {
+        let mut report = Report::default();
+        let mut images = Vec::new();
+        for path in try!(self.camera.paths()) {
+            let datetime = try!(self.camera.datetime(&path));
+            images.push((datetime, path));
+        }
+
+        if let Some(delete_after) = self.policy.delete_after {
+            let cutoff = *now - delete_after;
+            let (old, rest): (Vec<_>, Vec<_>) = images.into_iter()
+                .partition(|&(ref d, _)| *d < cutoff);
+            for (_, path) in old {
+                try!(fs::remove_file(&path));
+                report.deleted += 1;
+            }
+            images = rest;
+        }
+
+        if let Some(thin_after) = self.policy.thin_after {
+            let cutoff = *now - thin_after;
+            let timezone = self.camera.naming().timezone;
+            let mut days = BTreeMap::new();
+            for &(ref datetime, ref path) in images.iter().filter(|&&(ref d, _)| *d < cutoff) {
+                days.entry(datetime.with_timezone(&timezone).date().naive_local())
+                    .or_insert_with(Vec::new)
+                    .push((datetime.clone(), path.clone()));
+            }
+            for (_, day) in days {
+                let keep = keeper(&day, &timezone);
+                for (datetime, path) in day {
+                    if datetime != keep {
+                        try!(fs::remove_file(&path));
+                        report.thinned += 1;
+                    }
+                }
+            }
+            images.retain(|&(_, ref path)| path.exists());
+        }
+
+        let cutoff = *now - self.policy.archive_after;
+        for (_, path) in images.into_iter().filter(|&(ref d, _)| *d < cutoff) {
+            if path.parent() != Some(self.camera.path()) {
+                continue;
+            }
+            let destination = try!(self.camera.archive_path(&path));
+            if destination.exists() {
+                if try!(read(&destination)) == try!(read(&path)) {
+                    try!(fs::remove_file(&path));
+                } else {
+                    warn!("Not archiving {}, a different image is already at {}",
+                          path.to_string_lossy(),
+                          destination.to_string_lossy());
+                }
+                continue;
+            }
+            try!(fs::create_dir_all(destination.parent().unwrap()));
+            let recompressed = match self.policy.quality {
+                Some(quality) => try!(recompress(&path, &destination, quality)),
+                None => false,
+            };
+            if recompressed {
+                try!(fs::remove_file(&path));
+                report.recompressed += 1;
+            } else {
+                try!(fs::rename(&path, &destination));
+            }
+            report.archived += 1;
+        }
+        Ok(report)
+    }
+}
+
+/// Returns the datetime of the image to keep from a single day: the one closest to local noon.
+fn keeper(day: &[(DateTime<UTC>, PathBuf)], timezone: &FixedOffset) -> DateTime<UTC> {
+    day.iter()
+        .map(|&(ref datetime, _)| {
+            let seconds = datetime.with_timezone(timezone).num_seconds_from_midnight() as i64;
+            ((seconds - 12 * 60 * 60).abs(), datetime.clone())
+        })
+        .min()
+        .map(|(_, datetime)| datetime)
+        .unwrap()
+}
+
+fn read(path: &Path) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    try!(try!(File::open(path)).read_to_end(&mut bytes));
+    Ok(bytes)
+}
+
+/// Writes a recompressed copy of an image to `destination`, if that makes it smaller.
+///
+/// Returns false, and writes nothing, if the image can't be decoded or wouldn't get smaller.
+fn recompress(source: &Path, destination: &Path, quality: u8) -> Result<bool> {
+    let original = try!(read(source));
+    let image = match image::load_from_memory(&original) {
+        Ok(image) => image,
+        Err(err) => {
+            warn!("Not recompressing {}: {}", source.to_string_lossy(), err);
+            return Ok(false);
+        }
+    };
+    let (width, height) = image.dimensions();
+    let mut bytes = Vec::new();
+    try!(JPEGEncoder::new_with_quality(&mut bytes, quality)
+        .encode(&image.to_rgb().into_raw(), width, height, ColorType::RGB(8)));
+    if bytes.len() >= original.len() {
+        return Ok(false);
+    }
+    let temporary = destination.with_file_name(format!(".{}.archive",
+                                                       destination.file_name()
+                                                           .unwrap()
+                                                           .to_string_lossy()));
+    {
+        let mut file = try!(File::create(&temporary));
+        try!(file.write_all(&bytes));
+        try!(file.sync_all());
+    }
+    try!(fs::rename(&temporary, destination));
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::fs::{self, File};
+    use std::path::PathBuf;
+
+    use chrono::{Duration, TimeZone, UTC};
+
+    use cam::Camera;
+
+    fn camera(name: &str, file_names: &[&str]) -> (Camera, PathBuf) {
+        let directory = env::temp_dir().join(format!("atlas-archive-{}", name));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        for file_name in file_names {
+            File::create(directory.join(file_name)).unwrap();
+        }
+        (Camera::new("ATLAS_CAM", &directory).unwrap(), directory)
+    }
+
+    #[test]
+    fn archives() {
+        let (camera, directory) = camera("archives",
+                                         &["ATLAS_CAM_20160601_120000.jpg",
+                                           "ATLAS_CAM_20160725_120000.jpg"]);
+        let policy = Policy { archive_after: Duration::days(30), ..Default::default() };
+        let archiver = Archiver::new(camera.clone(), policy);
+        let report = archiver.archive(&UTC.ymd(2016, 8, 1).and_hms(0, 0, 0)).unwrap();
+        assert_eq!(1, report.archived);
+        assert_eq!(vec![directory.join("2016/06/ATLAS_CAM_20160601_120000.jpg"),
+                        directory.join("ATLAS_CAM_20160725_120000.jpg")],
+                   camera.paths().unwrap());
+        let report = archiver.archive(&UTC.ymd(2016, 8, 1).and_hms(0, 0, 0)).unwrap();
+        assert_eq!(Report::default(), report);
+    }
+
+    #[test]
+    fn thins_and_deletes() {
+        let (camera, directory) = camera("thins",
+                                         &["ATLAS_CAM_20140601_120000.jpg",
+                                           "ATLAS_CAM_20150601_060000.jpg",
+                                           "ATLAS_CAM_20150601_110000.jpg",
+                                           "ATLAS_CAM_20150601_180000.jpg",
+                                           "ATLAS_CAM_20160601_060000.jpg",
+                                           "ATLAS_CAM_20160601_180000.jpg"]);
+        let policy = Policy {
+            archive_after: Duration::days(30),
+            quality: None,
+            thin_after: Some(Duration::days(365)),
+            delete_after: Some(Duration::days(730)),
+        };
+        let archiver = Archiver::new(camera.clone(), policy);
+        let report = archiver.archive(&UTC.ymd(2016, 8, 1).and_hms(0, 0, 0)).unwrap();
+        assert_eq!(1, report.deleted);
+        assert_eq!(2, report.thinned);
+        assert_eq!(3, report.archived);
+        assert_eq!(vec![directory.join("2015/06/ATLAS_CAM_20150601_110000.jpg"),
+                        directory.join("2016/06/ATLAS_CAM_20160601_060000.jpg"),
+                        directory.join("2016/06/ATLAS_CAM_20160601_180000.jpg")],
+                   camera.paths().unwrap());
+    }
+
+    #[test]
+    fn recompresses() {
+        let (camera, directory) = camera("recompresses", &[]);
+        fs::copy("data/ATLAS_CAM_20160725_121500.jpg",
+                 directory.join("ATLAS_CAM_20160725_121500.jpg"))
+            .unwrap();
+        let policy = Policy { quality: Some(10), ..Default::default() };
+        let archiver = Archiver::new(camera.clone(), policy);
+        let report = archiver.archive(&UTC.ymd(2017, 1, 1).and_hms(0, 0, 0)).unwrap();
+        assert_eq!(1, report.archived);
+        assert_eq!(1, report.recompressed);
+        let archived = directory.join("2016/07/ATLAS_CAM_20160725_121500.jpg");
+        assert!(fs::metadata(&archived).unwrap().len() <
+                fs::metadata("data/ATLAS_CAM_20160725_121500.jpg").unwrap().len());
+    }
+}
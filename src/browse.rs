@@ -0,0 +1,335 @@
+//! Browse the historic images from each camera.
+//!
+//! The browser serves a page per camera, plus two JSON endpoints that the page uses: a paginated
+//! listing of the images taken on a given day, and a calendar of which days have images.
+
+use std::ascii::AsciiExt;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, RwLock};
+
+use chrono::NaiveDate;
+
+use handlebars_iron::Template;
+
+use iron::{Handler, status};
+use iron::prelude::*;
+use iron::mime::Mime;
+
+use router::Router;
+
+use rustc_serialize::json::{Json, ToJson};
+
+use url::Url;
+
+use cam::Camera;
+use catalog::ImageInfo;
+use query;
+
+const DEFAULT_PER_PAGE: usize = 48;
+const MAX_PER_PAGE: usize = 500;
+
+/// Browses the images of one or more cameras.
+///
+/// The images come from each camera's catalog, so browsing doesn't touch the filesystem.
+#[derive(Clone, Debug)]
+pub struct Browser {
+    cameras: Arc<HashMap<String, BrowsableCamera>>,
+    url: Url,
+}
+
+#[derive(Debug)]
+struct BrowsableCamera {
+    camera: Camera,
+    images: Arc<RwLock<Vec<ImageInfo>>>,
+}
+
+/// One page of the images taken on a single day.
+#[derive(Debug)]
+pub struct Day<'a> {
+    /// The date.
+    pub date: NaiveDate,
+    /// The one-based page number.
+    pub page: usize,
+    /// The number of images per page.
+    pub per_page: usize,
+    /// The total number of images taken on this day.
+    pub total: usize,
+    /// The images on this page.
+    pub images: Vec<&'a ImageInfo>,
+    /// The closest earlier day with images.
+    pub previous_date: Option<NaiveDate>,
+    /// The closest later day with images.
+    pub next_date: Option<NaiveDate>,
+}
+
+impl Browser {
+    /// Creates a new browser for the given cameras and their catalogs.
+    ///
+    /// Image urls are built from `url` using `Camera::url`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate url;
+    /// # extern crate atlas;
+    /// # use std::sync::{Arc, RwLock};
+    /// # use atlas::browse::Browser;
+    /// use atlas::cam::Camera;
+    /// # fn main() {
+    /// let camera = Camera::new("ATLAS_CAM", "data").unwrap();
+    /// let url = url::Url::parse("http://iridiumcam.lidar.io").unwrap();
+    /// let browser = Browser::new(vec![(camera, Arc::new(RwLock::new(Vec::new())))], url);
+    /// # }
+    /// ```
+    pub fn new(cameras: Vec<(Camera, Arc<RwLock<Vec<ImageInfo>>>)>, url: Url) -> Browser {
+        Browser {
+            cameras: Arc::new(cameras.into_iter()
+                .map(|(camera, images)| {
+                    (camera.name().to_ascii_lowercase(),
+                     BrowsableCamera {
+                         camera: camera,
+                         images: images,
+                     })
+                })
+                .collect()),
+            url: url,
+        }
+    }
+
+    /// Returns a handler for the camera page.
+    pub fn page_handler(&self) -> PageHandler {
+        PageHandler { browser: self.clone() }
+    }
+
+    /// Returns a handler for the JSON listing of a day's images.
+    ///
+    /// The day is set with the `date` query parameter (`%Y-%m-%d`) and defaults to the most
+    /// recent day with images. Use `page` and `per_page` to paginate.
+    pub fn images_handler(&self) -> ImagesHandler {
+        ImagesHandler { browser: self.clone() }
+    }
+
+    /// Returns a handler for the JSON calendar of days with images.
+    pub fn calendar_handler(&self) -> CalendarHandler {
+        CalendarHandler { browser: self.clone() }
+    }
+
+    fn camera(&self, request: &Request) -> Option<&BrowsableCamera> {
+        request.extensions
+            .get::<Router>()
+            .and_then(|p| p.find("name"))
+            .and_then(|name| self.cameras.get(&name.to_ascii_lowercase()))
+    }
+
+    fn image_json(&self, camera: &Camera, image: &ImageInfo) -> Json {
+        let mut map = BTreeMap::new();
+        map.insert("file_name".to_string(), image.file_name.to_json());
+        map.insert("datetime".to_string(), image.datetime.to_rfc3339().to_json());
+        map.insert("url".to_string(),
+                   camera.url(&self.url, &image.file_name)
+                       .map(|u| u.as_str().to_string())
+                       .to_json());
+        map.insert("corrupted".to_string(), image.corrupted.to_json());
+        Json::Object(map)
+    }
+}
+
+/// Counts the images taken on each day.
+///
+/// # Examples
+///
+/// ```
+/// # use atlas::browse::calendar;
+/// let calendar = calendar(&[]);
+/// assert!(calendar.is_empty());
+/// ```
+pub fn calendar(images: &[ImageInfo]) -> BTreeMap<NaiveDate, usize> {
+    let mut calendar = BTreeMap::new();
+    for image in images {
+        *calendar.entry(image.datetime.date().naive_utc()).or_insert(0) += 1;
+    }
+    calendar
+}
+
+/// Returns one page of the images taken on a day.
+///
+/// The images must be sorted by datetime. Pages are one-based, and a page past the end is empty.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate chrono;
+/// # extern crate atlas;
+/// # use chrono::NaiveDate;
+/// # use atlas::browse::day;
+/// # fn main() {
+/// let day = day(&[], NaiveDate::from_ymd(2016, 7, 25), 1, 10);
+/// assert_eq!(0, day.total);
+/// # }
+/// ```
+pub fn day(images: &[ImageInfo], date: NaiveDate, page: usize, per_page: usize) -> Day {
+    let page = if page == 0 { 1 } else { page };
+    let per_page = if per_page == 0 { 1 } else { per_page };
+    let on_day = images.iter()
+        .filter(|i| i.datetime.date().naive_utc() == date)
+        .collect::<Vec<_>>();
+    let calendar = calendar(images);
+    Day {
+        date: date,
+        page: page,
+        per_page: per_page,
+        total: on_day.len(),
+        images: on_day.into_iter()
+            .skip((page - 1).saturating_mul(per_page))
+            .take(per_page)
+            .collect(),
+        previous_date: calendar.keys().rev().find(|&d| *d < date).cloned(),
+        next_date: calendar.keys().find(|&d| *d > date).cloned(),
+    }
+}
+
+/// Serves the HTML page for a camera.
+#[derive(Debug)]
+pub struct PageHandler {
+    browser: Browser,
+}
+
+impl Handler for PageHandler {
+    fn handle(&self, request: &mut Request) -> IronResult<Response> {
+        let camera = &iexpect!(self.browser.camera(request), status::NotFound).camera;
+        let mut data = BTreeMap::<String, Json>::new();
+        data.insert("name".to_string(), camera.name().to_json());
+        let mut response = Response::new();
+        response.set_mut(Template::new("camera", data)).set_mut(status::Ok);
+        Ok(response)
+    }
+}
+
+/// Serves a JSON listing of the images taken on a day.
+#[derive(Debug)]
+pub struct ImagesHandler {
+    browser: Browser,
+}
+
+impl Handler for ImagesHandler {
+    fn handle(&self, request: &mut Request) -> IronResult<Response> {
+        let browsable = iexpect!(self.browser.camera(request), status::NotFound);
+        let params = query::params(request);
+        let page = match params.get("page") {
+            Some(s) => itry!(s.parse::<usize>(), status::BadRequest),
+            None => 1,
+        };
+        let per_page = match params.get("per_page") {
+            Some(s) => itry!(s.parse::<usize>(), status::BadRequest),
+            None => DEFAULT_PER_PAGE,
+        };
+        let images = browsable.images.read().unwrap();
+        let date = match params.get("date") {
+            Some(s) => itry!(query::parse_date(s), status::BadRequest),
+            None => {
+                iexpect!(images.last().map(|i| i.datetime.date().naive_utc()),
+                         (status::NotFound, "No images available."))
+            }
+        };
+        let day = day(&images, date, page, if per_page > MAX_PER_PAGE {
+            MAX_PER_PAGE
+        } else {
+            per_page
+        });
+
+        let mut data = BTreeMap::<String, Json>::new();
+        data.insert("camera".to_string(), browsable.camera.name().to_json());
+        data.insert("date".to_string(), day.date.to_string().to_json());
+        data.insert("page".to_string(), day.page.to_json());
+        data.insert("per_page".to_string(), day.per_page.to_json());
+        data.insert("pages".to_string(),
+                    ((day.total + day.per_page - 1) / day.per_page).to_json());
+        data.insert("total".to_string(), day.total.to_json());
+        data.insert("previous_date".to_string(),
+                    day.previous_date.map(|d| d.to_string()).to_json());
+        data.insert("next_date".to_string(),
+                    day.next_date.map(|d| d.to_string()).to_json());
+        data.insert("images".to_string(),
+                    Json::Array(day.images
+                        .iter()
+                        .map(|i| self.browser.image_json(&browsable.camera, i))
+                        .collect()));
+        let content_type = "application/json".parse::<Mime>().unwrap();
+        Ok(Response::with((content_type, status::Ok, data.to_json().to_string())))
+    }
+}
+
+/// Serves a JSON calendar of the number of images taken on each day.
+#[derive(Debug)]
+pub struct CalendarHandler {
+    browser: Browser,
+}
+
+impl Handler for CalendarHandler {
+    fn handle(&self, request: &mut Request) -> IronResult<Response> {
+        let browsable = iexpect!(self.browser.camera(request), status::NotFound);
+        let days = calendar(&browsable.images.read().unwrap())
+            .into_iter()
+            .map(|(date, count)| (date.to_string(), count.to_json()))
+            .collect::<BTreeMap<String, Json>>();
+        let mut data = BTreeMap::<String, Json>::new();
+        data.insert("camera".to_string(), browsable.camera.name().to_json());
+        data.insert("days".to_string(), Json::Object(days));
+        let content_type = "application/json".parse::<Mime>().unwrap();
+        Ok(Response::with((content_type, status::Ok, data.to_json().to_string())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{Arc, RwLock};
+
+    use chrono::NaiveDate;
+
+    use cam::Camera;
+    use catalog::{CatalogWatcher, ImageInfo};
+    use watch::DirectoryWatcher;
+
+    fn images() -> Vec<ImageInfo> {
+        let images = Arc::new(RwLock::new(Vec::new()));
+        CatalogWatcher::new(Camera::new("ATLAS_CAM", "data").unwrap(), images.clone())
+            .refresh()
+            .unwrap();
+        let images = images.read().unwrap().clone();
+        images
+    }
+
+    #[test]
+    fn calendar_counts() {
+        let calendar = calendar(&images());
+        assert_eq!(1, calendar.len());
+        assert_eq!(Some(&2), calendar.get(&NaiveDate::from_ymd(2016, 7, 25)));
+    }
+
+    #[test]
+    fn day_pages() {
+        let images = images();
+        let date = NaiveDate::from_ymd(2016, 7, 25);
+        let first = day(&images, date, 1, 1);
+        assert_eq!(2, first.total);
+        assert_eq!(1, first.images.len());
+        assert_eq!("ATLAS_CAM_20160725_121500.jpg", first.images[0].file_name);
+        let second = day(&images, date, 2, 1);
+        assert_eq!("ATLAS_CAM_20160725_141500.jpg", second.images[0].file_name);
+        assert!(day(&images, date, 3, 1).images.is_empty());
+    }
+
+    #[test]
+    fn day_navigation() {
+        let images = images();
+        let before = day(&images, NaiveDate::from_ymd(2016, 7, 1), 1, 10);
+        assert_eq!(0, before.total);
+        assert_eq!(None, before.previous_date);
+        assert_eq!(Some(NaiveDate::from_ymd(2016, 7, 25)), before.next_date);
+        let after = day(&images, NaiveDate::from_ymd(2016, 8, 1), 1, 10);
+        assert_eq!(Some(NaiveDate::from_ymd(2016, 7, 25)), after.previous_date);
+        assert_eq!(None, after.next_date);
+    }
+}
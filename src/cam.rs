@@ -1,25 +1,116 @@
 //! Manage images from remote cameras.
 //!
 //! These include the ATLAS cam and other remote cameras e.g. the Helheim terminus cam.
+//!
+//! New images land directly in a camera's directory, and the archiver moves older images into
+//! `YYYY/MM/` subdirectories by their UTC datetime. A camera reads images from both places.
 
 use std::ffi::{OsStr, OsString};
 use std::fs::read_dir;
 use std::path::{Path, PathBuf};
 
-use chrono::{DateTime, TimeZone, UTC};
+use chrono::{DateTime, Datelike, FixedOffset, TimeZone, UTC};
 
-use regex::Regex;
+use regex::{self, Regex};
 
 use url::Url;
 
 use {Error, Result};
 
 /// A remote camera, e.g. `ATLAS_CAM` or `HEL_TERMINUS`.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Camera {
     name: String,
     path: PathBuf,
+    naming: Naming,
     regex: Regex,
+    urls_by_name: bool,
+}
+
+/// How a camera names its image files.
+///
+/// The default naming matches files like `ATLAS_CAM_20160725_121500.jpg`.
+#[derive(Clone, Debug)]
+pub struct Naming {
+    /// The pattern of the file name, without the extension.
+    ///
+    /// `{name}` is replaced with the camera name and `{datetime}` marks the timestamp. Everything
+    /// else is matched literally.
+    pub pattern: String,
+    /// The `strftime` format of the timestamp.
+    pub format: String,
+    /// The allowed file extensions, without the dot. These are matched case-insensitively.
+    pub extensions: Vec<String>,
+    /// The timezone of the timestamps in the file names.
+    pub timezone: FixedOffset,
+}
+
+impl Default for Naming {
+    fn default() -> Naming {
+        Naming {
+            pattern: "{name}_{datetime}".to_string(),
+            format: "%Y%m%d_%H%M%S".to_string(),
+            extensions: vec!["jpg".to_string()],
+            timezone: FixedOffset::east(0),
+        }
+    }
+}
+
+impl Naming {
+    fn regex(&self, name: &str) -> Result<Regex> {
+        if !self.pattern.contains("{datetime}") {
+            return Err(Error::InvalidNaming(format!("pattern has no {{datetime}}: {}",
+                                                    self.pattern)));
+        }
+        if self.extensions.is_empty() {
+            return Err(Error::InvalidNaming("no file extensions".to_string()));
+        }
+        let stem = regex::quote(&self.pattern)
+            .replace(&regex::quote("{name}"), &regex::quote(name))
+            .replace(&regex::quote("{datetime}"), "(?P<datetime>.+)");
+        let extensions = self.extensions
+            .iter()
+            .map(|e| regex::quote(e.trim_left_matches('.')))
+            .collect::<Vec<_>>()
+            .join("|");
+        Regex::new(&format!("^{}\\.(?i:{})$", stem, extensions)).map_err(Error::from)
+    }
+}
+
+/// Parses a timezone, either `UTC` or an offset like `+02:00` or `-0300`.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate chrono;
+/// # extern crate atlas;
+/// use chrono::FixedOffset;
+/// # use atlas::cam::parse_timezone;
+/// # fn main() {
+/// assert_eq!(FixedOffset::east(0), parse_timezone("UTC").unwrap());
+/// assert_eq!(FixedOffset::east(7200), parse_timezone("+02:00").unwrap());
+/// assert_eq!(FixedOffset::west(12600), parse_timezone("-0330").unwrap());
+/// # }
+/// ```
+pub fn parse_timezone(s: &str) -> Result<FixedOffset> {
+    let invalid = || Error::InvalidNaming(format!("invalid timezone: {}", s));
+    if s == "UTC" || s == "Z" {
+        return Ok(FixedOffset::east(0));
+    }
+    let sign = if s.starts_with('+') {
+        1
+    } else if s.starts_with('-') {
+        -1
+    } else {
+        return Err(invalid());
+    };
+    let digits = s[1..].replace(":", "");
+    if digits.len() != 4 || !digits.chars().all(|c| c.is_digit(10)) {
+        return Err(invalid());
+    }
+    let hours: i32 = digits[0..2].parse().unwrap();
+    let minutes: i32 = digits[2..4].parse().unwrap();
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60)).ok_or_else(invalid)
 }
 
 impl Camera {
@@ -32,15 +123,62 @@ impl Camera {
     /// let camera = Camera::new("ATLAS_CAM", "data").unwrap();
     /// ```
     pub fn new<P: AsRef<Path>>(name: &str, path: P) -> Result<Camera> {
-        let mut regex_string = String::from(name);
-        regex_string.push_str(r"_(?P<datetime>\d{4}\d{2}\d{2}_\d{2}\d{2}\d{2}).jpg");
+        Camera::with_naming(name, path, Naming::default())
+    }
+
+    /// Creates a new named camera with a custom file naming scheme.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate chrono;
+    /// # extern crate atlas;
+    /// use chrono::{TimeZone, UTC};
+    /// # use atlas::cam::{Camera, Naming};
+    /// # fn main() {
+    /// let naming = Naming {
+    ///     pattern: "CAM-{datetime}Z".to_string(),
+    ///     format: "%Y-%m-%dT%H-%M-%S".to_string(),
+    ///     ..Default::default()
+    /// };
+    /// let camera = Camera::with_naming("CAM", "data", naming).unwrap();
+    /// assert_eq!(UTC.ymd(2017, 5, 1).and_hms(12, 0, 0),
+    ///            camera.datetime("CAM-2017-05-01T12-00-00Z.JPG").unwrap());
+    /// # }
+    /// ```
+    pub fn with_naming<P: AsRef<Path>>(name: &str, path: P, naming: Naming) -> Result<Camera> {
         Ok(Camera {
             name: name.to_string(),
             path: path.as_ref().to_path_buf(),
-            regex: try!(Regex::new(&regex_string)),
+            regex: try!(naming.regex(name)),
+            naming: naming,
+            urls_by_name: false,
         })
     }
 
+    /// Builds image urls with the camera name instead of the name of the camera's directory.
+    ///
+    /// This is how `ImageHandler` serves images, since different cameras' directories can have
+    /// the same name.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate url;
+    /// # extern crate atlas;
+    /// # use atlas::cam::Camera;
+    /// # fn main() {
+    /// let url = url::Url::parse("http://atlas.lidar.io/images").unwrap();
+    /// let camera = Camera::new("ATLAS_CAM", "data").unwrap().urls_by_name();
+    /// assert_eq!("http://atlas.lidar.io/images/ATLAS_CAM/foobar.jpg",
+    ///            camera.url(&url, "foobar.jpg").unwrap().as_str());
+    /// # }
+    /// ```
+    pub fn urls_by_name(mut self) -> Camera {
+        self.urls_by_name = true;
+        self
+    }
+
     /// Returns the file name of the latest image from this camera.
     ///
     /// This method will ignore all datetime parsing errors, in the interest of
@@ -105,19 +243,47 @@ impl Camera {
     /// # }
     /// ```
     pub fn paths_since(&self, datetime: &DateTime<UTC>) -> Result<Vec<PathBuf>> {
-        self.paths().map(|v| {
+        self.dated_paths(Some(datetime), None).map(|v| {
             v.into_iter()
-                .filter(|f| self.datetime(f).map(|d| &d > datetime).unwrap_or(false))
+                .filter(|&(ref d, _)| d > datetime)
+                .map(|(_, path)| path)
                 .collect()
         })
     }
 
-    /// Returns all paths of images taken by this camera.
+    /// Returns all paths of images taken at or after `start` and before `end`.
     ///
-    /// # Panics
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate chrono;
+    /// # extern crate atlas;
+    /// # use chrono::{UTC, TimeZone};
+    /// # use atlas::cam::Camera;
+    /// # fn main() {
+    /// let camera = Camera::new("ATLAS_CAM", "data").unwrap();
+    /// let paths = camera.paths_between(&UTC.ymd(2016, 7, 25).and_hms(0, 0, 0),
+    ///                          &UTC.ymd(2016, 7, 25).and_hms(13, 0, 0))
+    ///     .unwrap();
+    /// assert_eq!(1, paths.len());
+    /// # }
+    /// ```
+    pub fn paths_between(&self,
+                         start: &DateTime<UTC>,
+                         end: &DateTime<UTC>)
+                         -> Result<Vec<PathBuf>> {
+        self.dated_paths(Some(start), Some(end)).map(|v| {
+            v.into_iter()
+                .filter(|&(ref d, _)| d >= start && d < end)
+                .map(|(_, path)| path)
+                .collect()
+        })
+    }
+
+    /// Returns all paths of images taken by this camera, sorted by datetime.
     ///
-    /// If the regular expression used for matching filenames fails for some reason, this will
-    /// panic.
+    /// This includes archived images in `YYYY/MM/` subdirectories. Files that match the naming
+    /// pattern but whose timestamps can't be parsed are skipped.
     ///
     /// # Examples
     ///
@@ -127,20 +293,89 @@ impl Camera {
     /// let paths = camera.paths().unwrap();
     /// ```
     pub fn paths(&self) -> Result<Vec<PathBuf>> {
-        let mut paths = try!(read_dir(&self.path))
-            .filter_map(|r| {
-                r.ok().and_then(|d| if self.regex
-                    .is_match(&d.file_name().to_string_lossy()) {
-                    Some(d.path())
-                } else {
-                    None
-                })
-            })
-            .collect::<Vec<_>>();
-        paths.sort_by_key(|p| self.datetime(p).unwrap());
+        self.dated_paths(None, None).map(|v| v.into_iter().map(|(_, path)| path).collect())
+    }
+
+    /// Returns the datetimes and paths of this camera's images, sorted by datetime.
+    ///
+    /// Archive months that are entirely before `start` or at or after `end` aren't read, but
+    /// images outside of the bounds may still be returned.
+    fn dated_paths(&self,
+                   start: Option<&DateTime<UTC>>,
+                   end: Option<&DateTime<UTC>>)
+                   -> Result<Vec<(DateTime<UTC>, PathBuf)>> {
+        let mut paths = Vec::new();
+        for path in try!(read_dir(&self.path)).filter_map(|r| r.ok().map(|d| d.path())) {
+            if let Ok(datetime) = self.datetime(&path) {
+                paths.push((datetime, path));
+                continue;
+            }
+            let year = match archive_number(&path, 4) {
+                Some(year) if path.is_dir() => year,
+                _ => continue,
+            };
+            let months = match read_dir(&path) {
+                Ok(months) => months,
+                Err(_) => continue,
+            };
+            for path in months.filter_map(|r| r.ok().map(|d| d.path())) {
+                let (first, next) = match archive_number(&path, 2)
+                    .and_then(|month| month_bounds(year as i32, month)) {
+                    Some(bounds) => bounds,
+                    None => continue,
+                };
+                if start.map(|s| *s >= next).unwrap_or(false) ||
+                   end.map(|e| *e <= first).unwrap_or(false) {
+                    continue;
+                }
+                if let Ok(entries) = read_dir(&path) {
+                    for path in entries.filter_map(|r| r.ok().map(|d| d.path())) {
+                        if let Ok(datetime) = self.datetime(&path) {
+                            paths.push((datetime, path));
+                        }
+                    }
+                }
+            }
+        }
+        paths.sort_by(|a, b| a.0.cmp(&b.0));
         Ok(paths)
     }
 
+    /// Returns the path that an image belongs at in this camera's archive.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use atlas::cam::Camera;
+    /// let camera = Camera::new("ATLAS_CAM", "data").unwrap();
+    /// let path = camera.archive_path("ATLAS_CAM_20160725_121500.jpg").unwrap();
+    /// assert!(path.ends_with("2016/07/ATLAS_CAM_20160725_121500.jpg"));
+    /// ```
+    pub fn archive_path<P: AsRef<Path>>(&self, path: P) -> Result<PathBuf> {
+        let datetime = try!(self.datetime(&path));
+        Ok(self.path.join(archive_directory(&datetime)).join(path.as_ref().file_name().unwrap()))
+    }
+
+    /// Returns the path of an image file, whether or not it has been archived.
+    ///
+    /// Returns `None` if there's no such image.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use atlas::cam::Camera;
+    /// let camera = Camera::new("ATLAS_CAM", "data").unwrap();
+    /// assert!(camera.locate("ATLAS_CAM_20160725_121500.jpg").is_some());
+    /// assert!(camera.locate("ATLAS_CAM_20160725_131500.jpg").is_none());
+    /// ```
+    pub fn locate<S: AsRef<OsStr>>(&self, file_name: S) -> Option<PathBuf> {
+        let path = self.path.join(file_name.as_ref());
+        if path.is_file() {
+            return Some(path);
+        }
+        self.archive_path(&path).ok().and_then(|p| if p.is_file() { Some(p) } else { None })
+    }
+
     /// Returns the path of this camera.
     ///
     /// # Examples
@@ -172,13 +407,59 @@ impl Camera {
     pub fn datetime<P: AsRef<Path>>(&self, path: P) -> Result<DateTime<UTC>> {
         if let Some(f) = path.as_ref().file_name() {
             if let Some(c) = self.regex.captures(&f.to_string_lossy()) {
-                return UTC.datetime_from_str(&c["datetime"], "%Y%m%d_%H%M%S")
+                return self.naming
+                    .timezone
+                    .datetime_from_str(&c["datetime"], &self.naming.format)
+                    .map(|d| d.with_timezone(&UTC))
                     .map_err(|e| Error::from(e));
             }
         }
         Err(Error::InvalidCameraPath(self.name.clone(), path.as_ref().to_path_buf()))
     }
 
+    /// Returns the file name this camera would give an image taken at `datetime`.
+    ///
+    /// The file gets the first of the naming's extensions.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate chrono;
+    /// # extern crate atlas;
+    /// use chrono::{UTC, TimeZone};
+    /// # use atlas::cam::Camera;
+    /// # fn main() {
+    /// let camera = Camera::new("ATLAS_CAM", "data").unwrap();
+    /// assert_eq!("ATLAS_CAM_20160725_121500.jpg",
+    ///            camera.file_name(&UTC.ymd(2016, 7, 25).and_hms(12, 15, 0)));
+    /// # }
+    /// ```
+    pub fn file_name(&self, datetime: &DateTime<UTC>) -> String {
+        let stem = self.naming
+            .pattern
+            .replace("{name}", &self.name)
+            .replace("{datetime}",
+                     &datetime.with_timezone(&self.naming.timezone)
+                         .format(&self.naming.format)
+                         .to_string());
+        format!("{}.{}",
+                stem,
+                self.naming.extensions[0].trim_left_matches('.'))
+    }
+
+    /// Returns this camera's file naming scheme.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use atlas::cam::Camera;
+    /// let camera = Camera::new("ATLAS_CAM", "data").unwrap();
+    /// assert_eq!("%Y%m%d_%H%M%S", camera.naming().format);
+    /// ```
+    pub fn naming(&self) -> &Naming {
+        &self.naming
+    }
+
     /// Returns this camera's name.
     ///
     /// # Examples
@@ -195,7 +476,8 @@ impl Camera {
     /// Constructs a url using the given base url and the filename.
     ///
     /// The url is constructed by taking the base, adding the name of the parent directory of all
-    /// of the images, then appending the image filename.
+    /// of the images (or the camera name, see `urls_by_name`), then appending the image filename.
+    /// Archived images also get their `YYYY/MM` archive directories.
     ///
     /// Returns `None` if the provided url cannot be a base.
     ///
@@ -215,10 +497,23 @@ impl Camera {
     /// ```
     pub fn url<S: AsRef<OsStr>>(&self, url: &Url, file_name: S) -> Option<Url> {
         let mut url = url.clone();
-        self.path.file_name().and_then(|directory| {
+        let archive = self.locate(file_name.as_ref())
+            .and_then(|path| path.parent().map(|p| p.to_path_buf()))
+            .and_then(|parent| parent.strip_prefix(&self.path).ok().map(|p| p.to_path_buf()));
+        let directory = if self.urls_by_name {
+            Some(self.name.clone())
+        } else {
+            self.path.file_name().map(|directory| directory.to_string_lossy().into_owned())
+        };
+        directory.and_then(|directory| {
             match url.path_segments_mut() {
                 Ok(mut segments) => {
-                    segments.push(&directory.to_string_lossy());
+                    segments.push(&directory);
+                    if let Some(ref archive) = archive {
+                        for component in archive.iter() {
+                            segments.push(&component.to_string_lossy());
+                        }
+                    }
                     segments.push(&file_name.as_ref().to_string_lossy());
                 }
                 Err(_) => return None,
@@ -228,10 +523,45 @@ impl Camera {
     }
 }
 
+/// Returns the archive directory of an image taken at `datetime`, relative to its camera.
+fn archive_directory(datetime: &DateTime<UTC>) -> PathBuf {
+    Path::new(&format!("{:04}", datetime.year())).join(format!("{:02}", datetime.month()))
+}
+
+/// Parses the name of an archive directory, which must have exactly `digits` digits.
+fn archive_number(path: &Path, digits: usize) -> Option<u32> {
+    path.file_name()
+        .and_then(|f| f.to_str())
+        .and_then(|f| {
+            if f.len() == digits && f.chars().all(|c| c.is_digit(10)) {
+                f.parse().ok()
+            } else {
+                None
+            }
+        })
+}
+
+/// Returns the start of a month and the start of the next month.
+fn month_bounds(year: i32, month: u32) -> Option<(DateTime<UTC>, DateTime<UTC>)> {
+    if month < 1 || month > 12 {
+        return None;
+    }
+    let (next_year, next_month) = if month == 12 {
+        (year + 1, 1)
+    } else {
+        (year, month + 1)
+    };
+    Some((UTC.ymd(year, month, 1).and_hms(0, 0, 0),
+          UTC.ymd(next_year, next_month, 1).and_hms(0, 0, 0)))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    use std::env;
+    use std::fs::{self, File};
+
     use chrono::{TimeZone, UTC};
 
     use url::Url;
@@ -287,6 +617,90 @@ mod tests {
                    camera.datetime(file_name).unwrap());
     }
 
+    #[test]
+    fn name_is_escaped() {
+        let camera = Camera::new("ATLAS.CAM", "data").unwrap();
+        assert!(camera.datetime("ATLAS_CAM_20160725_141500.jpg").is_err());
+        assert!(camera.datetime("ATLAS.CAM_20160725_141500.jpg").is_ok());
+        assert!(Camera::new("ATLAS(CAM", "data").is_ok());
+    }
+
+    #[test]
+    fn extension_case() {
+        let camera = Camera::new("ATLAS_CAM", "data").unwrap();
+        assert!(camera.datetime("ATLAS_CAM_20160725_141500.JPG").is_ok());
+        assert!(camera.datetime("ATLAS_CAM_20160725_141500.jpg.part").is_err());
+        assert!(camera.datetime("ATLAS_CAM_20160725_141500xjpg").is_err());
+    }
+
+    #[test]
+    fn bad_datetime_is_skipped() {
+        let camera = Camera::new("HEL", "data").unwrap();
+        assert!(camera.datetime("HEL_Terminus_20160803_180000.jpg").is_err());
+        assert!(camera.paths().unwrap().is_empty());
+    }
+
+    #[test]
+    fn custom_naming() {
+        let naming = Naming {
+            pattern: "{name}-{datetime}Z".to_string(),
+            format: "%Y-%m-%dT%H-%M-%S".to_string(),
+            extensions: vec!["jpg".to_string(), "jpeg".to_string()],
+            timezone: parse_timezone("+02:00").unwrap(),
+        };
+        let camera = Camera::with_naming("CAM", "data", naming).unwrap();
+        assert_eq!(UTC.ymd(2017, 5, 1).and_hms(10, 0, 0),
+                   camera.datetime("CAM-2017-05-01T12-00-00Z.JPEG").unwrap());
+        assert!(camera.datetime("CAM_20170501_120000.jpg").is_err());
+        let datetime = UTC.ymd(2017, 5, 1).and_hms(10, 0, 0);
+        assert_eq!("CAM-2017-05-01T12-00-00Z.jpg", camera.file_name(&datetime));
+        assert_eq!(datetime, camera.datetime(camera.file_name(&datetime)).unwrap());
+    }
+
+    #[test]
+    fn naming_without_datetime() {
+        let naming = Naming { pattern: "{name}".to_string(), ..Default::default() };
+        assert!(Camera::with_naming("CAM", "data", naming).is_err());
+    }
+
+    #[test]
+    fn bad_timezone() {
+        assert!(parse_timezone("EST").is_err());
+        assert!(parse_timezone("+2").is_err());
+    }
+
+    #[test]
+    fn archived_paths() {
+        let directory = env::temp_dir().join("atlas-archived-paths").join("CAM");
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(directory.join("2016").join("07")).unwrap();
+        fs::create_dir_all(directory.join("2015").join("12")).unwrap();
+        for path in &["ATLAS_CAM_20160801_120000.jpg",
+                      "2016/07/ATLAS_CAM_20160725_120000.jpg",
+                      "2015/12/ATLAS_CAM_20151231_120000.jpg"] {
+            File::create(directory.join(path)).unwrap();
+        }
+        let camera = Camera::new("ATLAS_CAM", &directory).unwrap();
+        assert_eq!(vec![directory.join("2015/12/ATLAS_CAM_20151231_120000.jpg"),
+                        directory.join("2016/07/ATLAS_CAM_20160725_120000.jpg"),
+                        directory.join("ATLAS_CAM_20160801_120000.jpg")],
+                   camera.paths().unwrap());
+        assert_eq!(2,
+                   camera.paths_since(&UTC.ymd(2016, 1, 1).and_hms(0, 0, 0)).unwrap().len());
+        assert_eq!(1,
+                   camera.paths_between(&UTC.ymd(2015, 12, 1).and_hms(0, 0, 0),
+                                      &UTC.ymd(2016, 1, 1).and_hms(0, 0, 0))
+                       .unwrap()
+                       .len());
+        assert_eq!(Some(directory.join("2016/07/ATLAS_CAM_20160725_120000.jpg")),
+                   camera.locate("ATLAS_CAM_20160725_120000.jpg"));
+        let url = Url::parse("http://iridiumcam.lidar.io").unwrap();
+        assert_eq!("http://iridiumcam.lidar.io/CAM/2016/07/ATLAS_CAM_20160725_120000.jpg",
+                   camera.url(&url, "ATLAS_CAM_20160725_120000.jpg").unwrap().as_str());
+        assert_eq!("http://iridiumcam.lidar.io/CAM/ATLAS_CAM_20160801_120000.jpg",
+                   camera.url(&url, "ATLAS_CAM_20160801_120000.jpg").unwrap().as_str());
+    }
+
     #[test]
     fn url() {
         let url = Url::parse("http://iridiumcam.lidar.io").unwrap();
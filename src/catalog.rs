@@ -0,0 +1,277 @@
+//! A per-camera index of images and their metadata.
+//!
+//! `Camera` only knows about images through their file names. The catalog adds the file size,
+//! image dimensions, EXIF capture time, quality metrics, and whether the image is corrupted
+//! (images transferred over satellite are often partial). Reading that metadata means opening
+//! every image, so the catalog is refreshed incrementally: only new or changed files are read.
+
+use std::ascii::AsciiExt;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+
+use chrono::{DateTime, NaiveDateTime, UTC};
+
+use image;
+
+use iron::{Handler, status};
+use iron::prelude::*;
+use iron::mime::Mime;
+
+use router::Router;
+
+use rustc_serialize::json::{Json, ToJson};
+
+use Result;
+use cam::Camera;
+use jpeg::{self, Dimensions};
+use quality::Quality;
+use watch::DirectoryWatcher;
+
+/// Metadata about a single camera image.
+#[derive(Clone, Debug)]
+pub struct ImageInfo {
+    /// The file name of the image.
+    pub file_name: String,
+    /// The datetime of the image, as coded in its file name.
+    pub datetime: DateTime<UTC>,
+    /// The size of the image file, in bytes.
+    pub size: u64,
+    /// The image dimensions, if they could be read.
+    pub dimensions: Option<Dimensions>,
+    /// The capture time from the image's EXIF data, if there is one.
+    pub exif_datetime: Option<NaiveDateTime>,
+    /// Is this image corrupted or truncated?
+    pub corrupted: bool,
+    /// The image quality metrics, if the image could be decoded.
+    pub quality: Option<Quality>,
+    modified: Option<SystemTime>,
+}
+
+impl ImageInfo {
+    /// Reads the metadata for an image taken by the given camera.
+    ///
+    /// Files that can't be parsed as JPEGs are marked as corrupted rather than returning an error.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use atlas::cam::Camera;
+    /// # use atlas::catalog::ImageInfo;
+    /// let camera = Camera::new("ATLAS_CAM", "data").unwrap();
+    /// let info = ImageInfo::new(&camera, "data/ATLAS_CAM_20160725_121500.jpg").unwrap();
+    /// assert!(!info.corrupted);
+    /// ```
+    pub fn new<P: AsRef<Path>>(camera: &Camera, path: P) -> Result<ImageInfo> {
+        let path = path.as_ref();
+        let datetime = try!(camera.datetime(path));
+        let metadata = try!(fs::metadata(path));
+        let mut bytes = Vec::new();
+        try!(try!(File::open(path)).read_to_end(&mut bytes));
+        let (dimensions, exif_datetime, corrupted) = match jpeg::parse(&bytes) {
+            Ok(info) => (info.dimensions, info.exif_datetime, info.truncated),
+            Err(err) => {
+                debug!("Could not parse {} as a jpeg: {}", path.to_string_lossy(), err);
+                (None, None, true)
+            }
+        };
+        let quality = match image::load_from_memory(&bytes) {
+            Ok(image) => Some(Quality::new(&image)),
+            Err(err) => {
+                debug!("Could not decode {}: {}", path.to_string_lossy(), err);
+                None
+            }
+        };
+        Ok(ImageInfo {
+            file_name: path.file_name()
+                .map(|f| f.to_string_lossy().into_owned())
+                .unwrap_or(String::new()),
+            datetime: datetime,
+            size: metadata.len(),
+            dimensions: dimensions,
+            exif_datetime: exif_datetime,
+            corrupted: corrupted,
+            quality: quality,
+            modified: metadata.modified().ok(),
+        })
+    }
+
+    fn is_current(&self, metadata: &fs::Metadata) -> bool {
+        self.size == metadata.len() && self.modified == metadata.modified().ok()
+    }
+}
+
+impl ToJson for ImageInfo {
+    fn to_json(&self) -> Json {
+        let mut map = BTreeMap::new();
+        map.insert("file_name".to_string(), self.file_name.to_json());
+        map.insert("datetime".to_string(), self.datetime.to_rfc3339().to_json());
+        map.insert("size".to_string(), self.size.to_json());
+        map.insert("width".to_string(), self.dimensions.map(|d| d.width).to_json());
+        map.insert("height".to_string(), self.dimensions.map(|d| d.height).to_json());
+        map.insert("exif_datetime".to_string(),
+                   self.exif_datetime
+                       .map(|d| d.format("%Y-%m-%dT%H:%M:%S").to_string())
+                       .to_json());
+        map.insert("corrupted".to_string(), self.corrupted.to_json());
+        map.insert("brightness".to_string(),
+                   self.quality.map(|q| q.brightness).to_json());
+        map.insert("sharpness".to_string(), self.quality.map(|q| q.sharpness).to_json());
+        map.insert("saturated".to_string(), self.quality.map(|q| q.saturated).to_json());
+        Json::Object(map)
+    }
+}
+
+/// Watches a camera's directory and keeps its catalog up to date.
+#[derive(Debug)]
+pub struct CatalogWatcher {
+    camera: Camera,
+    directory: PathBuf,
+    images: Arc<RwLock<Vec<ImageInfo>>>,
+}
+
+impl CatalogWatcher {
+    /// Creates a new catalog watcher for a camera.
+    ///
+    /// The images are sorted by datetime.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use std::sync::{Arc, RwLock};
+    /// # use atlas::cam::Camera;
+    /// # use atlas::catalog::CatalogWatcher;
+    /// let images = Arc::new(RwLock::new(Vec::new()));
+    /// let watcher = CatalogWatcher::new(Camera::new("ATLAS_CAM", "data").unwrap(), images);
+    /// ```
+    pub fn new(camera: Camera, images: Arc<RwLock<Vec<ImageInfo>>>) -> CatalogWatcher {
+        CatalogWatcher {
+            directory: camera.path().to_path_buf(),
+            camera: camera,
+            images: images,
+        }
+    }
+}
+
+impl DirectoryWatcher for CatalogWatcher {
+    fn directory(&self) -> &Path {
+        self.directory.as_path()
+    }
+
+    fn refresh(&mut self) -> Result<()> {
+        let mut previous = self.images
+            .read()
+            .unwrap()
+            .iter()
+            .map(|i| (i.file_name.clone(), i.clone()))
+            .collect::<HashMap<_, _>>();
+        let mut images = Vec::new();
+        for path in try!(self.camera.paths()) {
+            let metadata = match fs::metadata(&path) {
+                Ok(metadata) => metadata,
+                Err(err) => {
+                    warn!("Could not read metadata for {}: {}", path.to_string_lossy(), err);
+                    continue;
+                }
+            };
+            let file_name = path.file_name()
+                .map(|f| f.to_string_lossy().into_owned())
+                .unwrap_or(String::new());
+            if let Some(info) = previous.remove(&file_name) {
+                if info.is_current(&metadata) {
+                    images.push(info);
+                    continue;
+                }
+            }
+            match ImageInfo::new(&self.camera, &path) {
+                Ok(info) => images.push(info),
+                Err(err) => warn!("Could not catalog {}: {}", path.to_string_lossy(), err),
+            }
+        }
+        *self.images.write().unwrap() = images;
+        Ok(())
+    }
+}
+
+/// Iron handler that serves a camera's catalog as JSON.
+///
+/// The camera name is taken from the `name` route parameter, and is case-insensitive.
+#[derive(Debug)]
+pub struct CatalogHandler {
+    catalogs: HashMap<String, Arc<RwLock<Vec<ImageInfo>>>>,
+}
+
+impl CatalogHandler {
+    /// Creates a new catalog handler for catalogs keyed by camera name.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use std::collections::HashMap;
+    /// # use std::sync::{Arc, RwLock};
+    /// # use atlas::catalog::CatalogHandler;
+    /// let mut catalogs = HashMap::new();
+    /// catalogs.insert("ATLAS_CAM".to_string(), Arc::new(RwLock::new(Vec::new())));
+    /// let handler = CatalogHandler::new(catalogs);
+    /// ```
+    pub fn new(catalogs: HashMap<String, Arc<RwLock<Vec<ImageInfo>>>>) -> CatalogHandler {
+        CatalogHandler {
+            catalogs: catalogs.into_iter()
+                .map(|(name, catalog)| (name.to_ascii_lowercase(), catalog))
+                .collect(),
+        }
+    }
+}
+
+impl Handler for CatalogHandler {
+    fn handle(&self, request: &mut Request) -> IronResult<Response> {
+        let name = iexpect!(request.extensions.get::<Router>().and_then(|p| p.find("name")))
+            .to_ascii_lowercase();
+        let catalog = iexpect!(self.catalogs.get(&name), status::NotFound);
+        let content_type = "application/json".parse::<Mime>().unwrap();
+        let json = catalog.read().unwrap().to_json().to_string();
+        Ok(Response::with((content_type, status::Ok, json)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{Arc, RwLock};
+
+    use cam::Camera;
+    use jpeg::Dimensions;
+    use watch::DirectoryWatcher;
+
+    #[test]
+    fn image_info() {
+        let camera = Camera::new("HEL_Terminus", "data").unwrap();
+        let info = ImageInfo::new(&camera, "data/HEL_Terminus_20160803_180000.jpg").unwrap();
+        assert_eq!("HEL_Terminus_20160803_180000.jpg", info.file_name);
+        assert_eq!(140022, info.size);
+        assert_eq!(Some(Dimensions {
+                       width: 1024,
+                       height: 768,
+                   }),
+                   info.dimensions);
+        assert!(!info.corrupted);
+        assert!(info.quality.is_some());
+    }
+
+    #[test]
+    fn refresh() {
+        let images = Arc::new(RwLock::new(Vec::new()));
+        let mut watcher = CatalogWatcher::new(Camera::new("ATLAS_CAM", "data").unwrap(),
+                                              images.clone());
+        watcher.refresh().unwrap();
+        assert_eq!(2, images.read().unwrap().len());
+        watcher.refresh().unwrap();
+        let images = images.read().unwrap();
+        assert_eq!(2, images.len());
+        assert_eq!("ATLAS_CAM_20160725_141500.jpg", images[1].file_name);
+    }
+}
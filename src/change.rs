@@ -0,0 +1,424 @@
+//! Change detection between consecutive camera images.
+//!
+//! Calving at the terminus shows up as a big change between two consecutive frames. Each image is
+//! compared with the image before it, within a region of interest, and gets a change score from 0
+//! (identical) to 255. Scores above a threshold are flagged as possible calving events.
+//!
+//! Both frames are normalized to the same mean brightness before they are compared, so that the
+//! sun going behind a cloud doesn't look like a calving event.
+
+use std::ascii::AsciiExt;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use chrono::{DateTime, UTC};
+
+use image::{self, FilterType, GenericImage};
+
+use iron::{Handler, status};
+use iron::headers::ContentType;
+use iron::prelude::*;
+use iron::mime::{Mime, SubLevel, TopLevel};
+
+use router::Router;
+
+use {Error, Result};
+use cam::Camera;
+use watch::DirectoryWatcher;
+
+/// The width and height of the downsampled regions that are compared.
+const SAMPLE_WIDTH: u32 = 64;
+const SAMPLE_HEIGHT: u32 = 48;
+
+/// A rectangular region of an image, as fractions of the image's width and height.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Region {
+    /// The left edge, from 0 to 1.
+    pub x: f64,
+    /// The top edge, from 0 to 1.
+    pub y: f64,
+    /// The width, from 0 to 1.
+    pub width: f64,
+    /// The height, from 0 to 1.
+    pub height: f64,
+}
+
+impl Default for Region {
+    fn default() -> Region {
+        Region {
+            x: 0.,
+            y: 0.,
+            width: 1.,
+            height: 1.,
+        }
+    }
+}
+
+impl Region {
+    /// Creates a region from `[x, y, width, height]`, checking that it lies inside the image.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use atlas::change::Region;
+    /// let region = Region::from_slice(&[0., 0.5, 1., 0.5]).unwrap();
+    /// assert!(Region::from_slice(&[0.5, 0., 1., 1.]).is_err());
+    /// assert!(Region::from_slice(&[0., 0.]).is_err());
+    /// ```
+    pub fn from_slice(values: &[f64]) -> Result<Region> {
+        if values.len() != 4 {
+            return Err(Error::ServerConfigError(format!("a region needs four values, got {}",
+                                                        values.len())));
+        }
+        let region = Region {
+            x: values[0],
+            y: values[1],
+            width: values[2],
+            height: values[3],
+        };
+        if region.x < 0. || region.y < 0. || region.width <= 0. || region.height <= 0. ||
+           region.x + region.width > 1. || region.y + region.height > 1. {
+            return Err(Error::ServerConfigError(format!("region is outside of the image: {:?}",
+                                                        values)));
+        }
+        Ok(region)
+    }
+
+    /// Returns the region's `(x, y, width, height)` in pixels, for an image of the given size.
+    ///
+    /// The region is always at least one pixel wide and tall.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use atlas::change::Region;
+    /// let region = Region { x: 0., y: 0.5, width: 1., height: 0.5 };
+    /// assert_eq!((0, 384, 1024, 384), region.pixels(1024, 768));
+    /// ```
+    pub fn pixels(&self, width: u32, height: u32) -> (u32, u32, u32, u32) {
+        let x = ((self.x * width as f64) as u32).min(width.saturating_sub(1));
+        let y = ((self.y * height as f64) as u32).min(height.saturating_sub(1));
+        let w = ((self.width * width as f64) as u32).max(1).min(width - x);
+        let h = ((self.height * height as f64) as u32).max(1).min(height - y);
+        (x, y, w, h)
+    }
+}
+
+/// Scores the change between pairs of images.
+#[derive(Clone, Copy, Debug)]
+pub struct ChangeDetector {
+    region: Region,
+    threshold: f64,
+}
+
+impl ChangeDetector {
+    /// Creates a new detector that flags scores above `threshold`.
+    ///
+    /// By default the whole image is compared.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use atlas::change::ChangeDetector;
+    /// let detector = ChangeDetector::new(20.);
+    /// ```
+    pub fn new(threshold: f64) -> ChangeDetector {
+        ChangeDetector {
+            region: Default::default(),
+            threshold: threshold,
+        }
+    }
+
+    /// Sets the region of interest.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use atlas::change::{ChangeDetector, Region};
+    /// let terminus = Region { x: 0., y: 0.4, width: 1., height: 0.3 };
+    /// let detector = ChangeDetector::new(20.).region(terminus);
+    /// ```
+    pub fn region(mut self, region: Region) -> ChangeDetector {
+        self.region = region;
+        self
+    }
+
+    /// Returns the threshold above which changes are flagged.
+    pub fn threshold(&self) -> f64 {
+        self.threshold
+    }
+
+    /// Returns the change score between two images, from 0 to 255.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use atlas::change::ChangeDetector;
+    /// let detector = ChangeDetector::new(20.);
+    /// let score = detector.score("data/ATLAS_CAM_20160725_121500.jpg",
+    ///                            "data/ATLAS_CAM_20160725_141500.jpg")
+    ///     .unwrap();
+    /// assert!(score > 0.);
+    /// ```
+    pub fn score<P: AsRef<Path>, Q: AsRef<Path>>(&self, before: P, after: Q) -> Result<f64> {
+        let before = try!(self.sample(before.as_ref()));
+        let after = try!(self.sample(after.as_ref()));
+        Ok(difference(&before, &after))
+    }
+
+    /// Returns the region of interest of an image, downsampled and in grayscale.
+    fn sample(&self, path: &Path) -> Result<Vec<u8>> {
+        let mut image = try!(image::open(path));
+        let (width, height) = image.dimensions();
+        let (x, y, w, h) = self.region.pixels(width, height);
+        Ok(image.crop(x, y, w, h)
+            .resize_exact(SAMPLE_WIDTH, SAMPLE_HEIGHT, FilterType::Triangle)
+            .to_luma()
+            .into_raw())
+    }
+}
+
+/// The mean absolute difference between two samples, after removing each sample's mean.
+fn difference(a: &[u8], b: &[u8]) -> f64 {
+    if a.is_empty() || a.len() != b.len() {
+        return 255.;
+    }
+    let mean_a = mean(a);
+    let mean_b = mean(b);
+    a.iter()
+        .zip(b)
+        .fold(0., |sum, (&a, &b)| sum + ((a as f64 - mean_a) - (b as f64 - mean_b)).abs()) /
+    a.len() as f64
+}
+
+fn mean(sample: &[u8]) -> f64 {
+    sample.iter().fold(0., |sum, &v| sum + v as f64) / sample.len() as f64
+}
+
+/// The change between an image and the image before it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChangeScore {
+    /// The file name of the image.
+    pub file_name: String,
+    /// The datetime of the image.
+    pub datetime: DateTime<UTC>,
+    /// The file name of the image it was compared with.
+    pub previous_file_name: String,
+    /// The change score, from 0 to 255.
+    pub score: f64,
+    /// Is the score above the threshold, i.e. is this a possible calving event?
+    pub flagged: bool,
+}
+
+/// Watches a camera's directory and keeps its change scores up to date.
+///
+/// Only new images are scored. Images that can't be decoded are skipped, so the next image is
+/// compared with the last good one.
+#[derive(Debug)]
+pub struct ChangeWatcher {
+    camera: Camera,
+    directory: PathBuf,
+    detector: ChangeDetector,
+    scores: Arc<RwLock<Vec<ChangeScore>>>,
+}
+
+impl ChangeWatcher {
+    /// Creates a new change watcher for a camera.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use std::sync::{Arc, RwLock};
+    /// # use atlas::cam::Camera;
+    /// # use atlas::change::{ChangeDetector, ChangeWatcher};
+    /// let scores = Arc::new(RwLock::new(Vec::new()));
+    /// let watcher = ChangeWatcher::new(Camera::new("ATLAS_CAM", "data").unwrap(),
+    ///                                  ChangeDetector::new(20.),
+    ///                                  scores);
+    /// ```
+    pub fn new(camera: Camera,
+               detector: ChangeDetector,
+               scores: Arc<RwLock<Vec<ChangeScore>>>)
+               -> ChangeWatcher {
+        ChangeWatcher {
+            directory: camera.path().to_path_buf(),
+            camera: camera,
+            detector: detector,
+            scores: scores,
+        }
+    }
+}
+
+impl DirectoryWatcher for ChangeWatcher {
+    fn directory(&self) -> &Path {
+        self.directory.as_path()
+    }
+
+    fn refresh(&mut self) -> Result<()> {
+        let mut previous = self.scores
+            .read()
+            .unwrap()
+            .iter()
+            .map(|s| (s.file_name.clone(), s.clone()))
+            .collect::<HashMap<_, _>>();
+        let mut scores = Vec::new();
+        // The last good image, and its sample if we've already computed it.
+        let mut last: Option<(String, PathBuf, Option<Vec<u8>>)> = None;
+        for path in try!(self.camera.paths()) {
+            let file_name = path.file_name()
+                .map(|f| f.to_string_lossy().into_owned())
+                .unwrap_or(String::new());
+            let datetime = try!(self.camera.datetime(&path));
+            let (last_file_name, last_path, last_sample) = match last.take() {
+                Some(last) => last,
+                None => {
+                    if let Ok(sample) = self.detector.sample(&path) {
+                        last = Some((file_name, path, Some(sample)));
+                    }
+                    continue;
+                }
+            };
+            if let Some(score) = previous.remove(&file_name) {
+                if score.previous_file_name == last_file_name {
+                    scores.push(score);
+                    last = Some((file_name, path, None));
+                    continue;
+                }
+            }
+            let sample = match self.detector.sample(&path) {
+                Ok(sample) => sample,
+                Err(err) => {
+                    warn!("Skipping {} in change detection: {}", path.to_string_lossy(), err);
+                    last = Some((last_file_name, last_path, last_sample));
+                    continue;
+                }
+            };
+            let last_sample = match last_sample {
+                Some(sample) => sample,
+                None => try!(self.detector.sample(&last_path)),
+            };
+            let score = difference(&last_sample, &sample);
+            scores.push(ChangeScore {
+                file_name: file_name.clone(),
+                datetime: datetime,
+                previous_file_name: last_file_name,
+                score: score,
+                flagged: score > self.detector.threshold,
+            });
+            last = Some((file_name, path, Some(sample)));
+        }
+        *self.scores.write().unwrap() = scores;
+        Ok(())
+    }
+}
+
+/// Iron handler that serves a camera's change scores as CSV.
+///
+/// The camera name is taken from the `name` route parameter, and is case-insensitive. The
+/// columns are the image datetime, the score, the threshold, whether the image was flagged, and
+/// the image file name.
+#[derive(Debug)]
+pub struct ChangeCsvHandler {
+    cameras: HashMap<String, (ChangeDetector, Arc<RwLock<Vec<ChangeScore>>>)>,
+}
+
+impl ChangeCsvHandler {
+    /// Creates a new handler for change scores keyed by camera name.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use std::collections::HashMap;
+    /// # use std::sync::{Arc, RwLock};
+    /// # use atlas::change::{ChangeCsvHandler, ChangeDetector};
+    /// let mut scores = HashMap::new();
+    /// scores.insert("ATLAS_CAM".to_string(),
+    ///               (ChangeDetector::new(20.), Arc::new(RwLock::new(Vec::new()))));
+    /// let handler = ChangeCsvHandler::new(scores);
+    /// ```
+    pub fn new(cameras: HashMap<String, (ChangeDetector, Arc<RwLock<Vec<ChangeScore>>>)>)
+               -> ChangeCsvHandler {
+        ChangeCsvHandler {
+            cameras: cameras.into_iter()
+                .map(|(name, scores)| (name.to_ascii_lowercase(), scores))
+                .collect(),
+        }
+    }
+}
+
+impl Handler for ChangeCsvHandler {
+    fn handle(&self, request: &mut Request) -> IronResult<Response> {
+        let &(ref detector, ref scores) = {
+            let name = iexpect!(request.extensions.get::<Router>().and_then(|p| p.find("name")));
+            iexpect!(self.cameras.get(&name.to_ascii_lowercase()), status::NotFound)
+        };
+        let mut data = String::new();
+        writeln!(&mut data, "Datetime,Score,Threshold,Flagged,File name").unwrap();
+        for score in scores.read().unwrap().iter() {
+            writeln!(&mut data,
+                     "{},{:.2},{:.2},{},{}",
+                     score.datetime.to_rfc3339(),
+                     score.score,
+                     detector.threshold,
+                     score.flagged,
+                     score.file_name)
+                .unwrap();
+        }
+        let mut response = Response::with((status::Ok, data));
+        response.headers
+            .set(ContentType(Mime(TopLevel::Text, SubLevel::Ext("csv".to_string()), vec![])));
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::difference;
+
+    use std::sync::{Arc, RwLock};
+
+    use cam::Camera;
+    use watch::DirectoryWatcher;
+
+    #[test]
+    fn normalized_difference() {
+        assert_eq!(0., difference(&[0, 10], &[0, 10]));
+        assert_eq!(0., difference(&[0, 10], &[100, 110]));
+        assert_eq!(5., difference(&[0, 10], &[0, 0]));
+    }
+
+    #[test]
+    fn same_image() {
+        let detector = ChangeDetector::new(20.).region(Region {
+            x: 0.25,
+            y: 0.25,
+            width: 0.5,
+            height: 0.5,
+        });
+        assert_eq!(0.,
+                   detector.score("data/ATLAS_CAM_20160725_121500.jpg",
+                              "data/ATLAS_CAM_20160725_121500.jpg")
+                       .unwrap());
+    }
+
+    #[test]
+    fn refresh() {
+        let scores = Arc::new(RwLock::new(Vec::new()));
+        let mut watcher = ChangeWatcher::new(Camera::new("ATLAS_CAM", "data").unwrap(),
+                                             ChangeDetector::new(-1.),
+                                             scores.clone());
+        watcher.refresh().unwrap();
+        {
+            let scores = scores.read().unwrap();
+            assert_eq!(1, scores.len());
+            assert_eq!("ATLAS_CAM_20160725_141500.jpg", scores[0].file_name);
+            assert_eq!("ATLAS_CAM_20160725_121500.jpg", scores[0].previous_file_name);
+            assert!(scores[0].flagged);
+        }
+        watcher.refresh().unwrap();
+        assert_eq!(1, scores.read().unwrap().len());
+    }
+}
@@ -5,6 +5,7 @@ use std::io;
 use std::path::PathBuf;
 
 use chrono;
+use image;
 use notify;
 use regex;
 use sbd;
@@ -12,17 +13,52 @@ use toml;
 use url;
 
 use heartbeat;
+use iridium;
 use sutron;
 
+/// A problem with a configuration file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConfigProblem {
+    /// The line of the configuration file with the problem, starting at one, if known.
+    pub line: Option<usize>,
+    /// What's wrong.
+    pub message: String,
+}
+
+impl fmt::Display for ConfigProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {}: {}", line, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
 #[derive(Debug)]
 /// Our error enum.
 pub enum Error {
     /// Wrapper around a `chrono::ParseError`.
     ChronoParse(chrono::ParseError),
+    /// `ffmpeg` failed, with the given error output.
+    Ffmpeg(String),
+    /// Wrapper around `image::ImageError`.
+    Image(image::ImageError),
     /// Wrapper around `std::io::Error`.
     Io(io::Error),
+    /// The named animation encoder doesn't exist or isn't available.
+    InvalidEncoder(String),
     /// A camera can't handle the given path.
     InvalidCameraPath(String, PathBuf),
+    /// The server configuration has one or more problems.
+    InvalidConfig(Vec<ConfigProblem>),
+    /// An overlay couldn't be created.
+    InvalidOverlay(String),
+    /// A camera's file naming scheme is invalid.
+    InvalidNaming(String),
+    /// An uploaded image was rejected.
+    InvalidUpload(String),
+    /// A different image is already stored under an uploaded image's name.
+    UploadConflict(String),
     #[cfg(feature = "magick_rust")]
     /// An imagemagick error.
     ///
@@ -31,6 +67,8 @@ pub enum Error {
     Magick(String),
     /// Wrapper around `atlas::heartbeat::ParseHeartbeatError`.
     ParseHeartbeat(heartbeat::ParseHeartbeatError),
+    /// Wrapper around `atlas::iridium::ParseSbdError`.
+    ParseSbd(iridium::ParseSbdError),
     /// Wrapper around `notify::Error`.
     Notify(notify::Error),
     /// Wrapper around `regex::Error`.
@@ -53,11 +91,20 @@ impl error::Error for Error {
     fn description(&self) -> &str {
         match *self {
             Error::ChronoParse(ref err) => err.description(),
+            Error::Ffmpeg(_) => "ffmpeg error",
+            Error::Image(ref err) => err.description(),
             Error::InvalidCameraPath(_, _) => "invalid camera path",
+            Error::InvalidConfig(_) => "invalid configuration",
+            Error::InvalidEncoder(_) => "invalid animation encoder",
+            Error::InvalidNaming(_) => "invalid camera naming",
+            Error::InvalidOverlay(_) => "invalid overlay",
+            Error::InvalidUpload(_) => "invalid upload",
+            Error::UploadConflict(_) => "upload conflict",
             Error::Io(ref err) => err.description(),
             #[cfg(feature = "magick_rust")]
             Error::Magick(_) => "imagemagick error",
             Error::ParseHeartbeat(ref err) => err.description(),
+            Error::ParseSbd(ref err) => err.description(),
             Error::Regex(ref err) => err.description(),
             Error::Notify(ref err) => err.description(),
             Error::Sbd(ref err) => err.description(),
@@ -74,13 +121,29 @@ impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Error::ChronoParse(ref err) => write!(f, "chrono error: {}", err),
+            Error::Ffmpeg(ref s) => write!(f, "ffmpeg error: {}", s),
+            Error::Image(ref err) => write!(f, "image error: {}", err),
             Error::InvalidCameraPath(ref s, ref p) => {
                 write!(f, "camera {} can't handle path: {}", s, p.to_string_lossy())
             }
+            Error::InvalidConfig(ref problems) => {
+                write!(f,
+                       "invalid configuration: {}",
+                       problems.iter()
+                           .map(|p| p.to_string())
+                           .collect::<Vec<_>>()
+                           .join("; "))
+            }
+            Error::InvalidEncoder(ref s) => write!(f, "invalid animation encoder: {}", s),
+            Error::InvalidNaming(ref s) => write!(f, "invalid camera naming: {}", s),
+            Error::InvalidOverlay(ref s) => write!(f, "invalid overlay: {}", s),
+            Error::InvalidUpload(ref s) => write!(f, "invalid upload: {}", s),
+            Error::UploadConflict(ref s) => write!(f, "upload conflict: {}", s),
             Error::Io(ref err) => write!(f, "io error: {}", err),
             #[cfg(feature = "magick_rust")]
             Error::Magick(ref s) => write!(f, "imagemagick error: {}", s),
             Error::ParseHeartbeat(ref err) => write!(f, "heartbeat parsing error: {}", err),
+            Error::ParseSbd(ref err) => write!(f, "sbd parsing error: {}", err),
             Error::Notify(ref err) => write!(f, "notify error: {}", err),
             Error::Sbd(ref err) => write!(f, "sbd error: {}", err),
             Error::ServerConfigError(ref s) => write!(f, "server configuration error: {}", s),
@@ -112,6 +175,12 @@ impl From<chrono::ParseError> for Error {
     }
 }
 
+impl From<image::ImageError> for Error {
+    fn from(err: image::ImageError) -> Error {
+        Error::Image(err)
+    }
+}
+
 impl From<notify::Error> for Error {
     fn from(err: notify::Error) -> Error {
         Error::Notify(err)
@@ -147,3 +216,9 @@ impl From<heartbeat::ParseHeartbeatError> for Error {
         Error::ParseHeartbeat(err)
     }
 }
+
+impl From<iridium::ParseSbdError> for Error {
+    fn from(err: iridium::ParseSbdError) -> Error {
+        Error::ParseSbd(err)
+    }
+}
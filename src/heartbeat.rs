@@ -81,6 +81,18 @@ impl FromStr for MeasurementProgram {
     }
 }
 
+impl fmt::Display for MeasurementProgram {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MeasurementProgram::FiftyKiloHertz => write!(f, "50 kHz"),
+            MeasurementProgram::OneHundredKiloHertz => write!(f, "100 kHz"),
+            MeasurementProgram::TwoHundredKiloHertz => write!(f, "200 kHz"),
+            MeasurementProgram::ThreeHundredKiloHertz => write!(f, "300 kHz"),
+            MeasurementProgram::Reflector => write!(f, "reflector"),
+        }
+    }
+}
+
 /// Newtype for degrees (not radians).
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Degrees(f32);
@@ -91,14 +103,45 @@ impl fmt::Display for Degrees {
     }
 }
 
+/// The output voltage of a Hass current transducer when no current flows.
+const HASS_ZERO_VOLTS: f32 = 2.5;
+
+/// How far a Hass current transducer's output swings from zero at its rated current, in volts.
+const HASS_SPAN_VOLTS: f32 = 0.625;
+
+/// Converts a Hass current transducer's output voltage into amps.
+fn hass_amps(volts: f32, rated_amps: f32) -> f32 {
+    (volts - HASS_ZERO_VOLTS) / HASS_SPAN_VOLTS * rated_amps
+}
+
 /// Newtype for the Hass 50 current transducers.
+///
+/// The reading is the transducer's output voltage, which is 2.5 V with no current and swings by
+/// 0.625 V at 50 A.
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Hass50Amps(f32);
 
+impl Hass50Amps {
+    /// Returns this reading as a current in amps.
+    pub fn amps(&self) -> f32 {
+        hass_amps(self.0, 50.0)
+    }
+}
+
 /// Newtype for the Hass 100 current transducers.
+///
+/// The reading is the transducer's output voltage, which is 2.5 V with no current and swings by
+/// 0.625 V at 100 A.
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Hass100Amps(f32);
 
+impl Hass100Amps {
+    /// Returns this reading as a current in amps.
+    pub fn amps(&self) -> f32 {
+        hass_amps(self.0, 100.0)
+    }
+}
+
 /// Newtype for the Orion BMS percentages.
 ///
 /// Orion BMS readings are voltages from zero to five that map onto a zero to one hundred percent
@@ -203,6 +246,19 @@ pub struct HeartbeatV1 {
     pub dcl4: OrionPercentage,
 }
 
+/// A single numeric heartbeat field, with its units.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Measurement {
+    /// The name of the heartbeat field, e.g. `temperature_external`.
+    pub name: &'static str,
+    /// The value of the field.
+    ///
+    /// Orion BMS readings are converted to percentages, and Hass current transducer readings are
+    /// converted to amps.
+    pub value: f32,
+    /// The units of the value, e.g. `°C`.
+    pub units: &'static str,
+}
 
 /// Trait for converting something into a vector of heartbeats.
 pub trait IntoHeartbeats {
@@ -239,6 +295,88 @@ impl IntoHeartbeats for Vec<Message> {
 }
 
 impl HeartbeatV1 {
+    /// Returns the time of the Iridium session of the first message in this heartbeat.
+    ///
+    /// This is the time a heartbeat is known by.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate chrono;
+    /// # extern crate sbd;
+    /// # extern crate atlas;
+    /// # use chrono::{TimeZone, UTC};
+    /// # use sbd::mo::Message;
+    /// # use atlas::heartbeat::IntoHeartbeats;
+    /// # fn main() {
+    /// let messages = vec![Message::from_path("data/150729_020200.sbd").unwrap()];
+    /// let heartbeat = messages.into_heartbeats().unwrap().pop().unwrap().unwrap();
+    /// assert_eq!(UTC.ymd(2015, 7, 29).and_hms(2, 2, 0), heartbeat.datetime());
+    /// # }
+    /// ```
+    pub fn datetime(&self) -> DateTime<UTC> {
+        self.messages[0].time_of_session()
+    }
+
+    /// Returns every numeric field of this heartbeat, in message order, with units.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate sbd;
+    /// # extern crate atlas;
+    /// # use sbd::mo::Message;
+    /// # use atlas::heartbeat::IntoHeartbeats;
+    /// # fn main() {
+    /// let messages = vec![Message::from_path("data/150729_020200.sbd").unwrap()];
+    /// let heartbeat = messages.into_heartbeats().unwrap().pop().unwrap().unwrap();
+    /// let measurements = heartbeat.measurements();
+    /// assert_eq!("temperature_external", measurements[0].name);
+    /// assert_eq!("°C", measurements[0].units);
+    /// # }
+    /// ```
+    pub fn measurements(&self) -> Vec<Measurement> {
+        let measurement = |name, value, units| {
+            Measurement {
+                name: name,
+                value: value,
+                units: units,
+            }
+        };
+        vec![measurement("temperature_external", self.temperature_external.0, "°C"),
+             measurement("pressure", self.pressure.0, "mbar"),
+             measurement("humidity", self.humidity.0, "%"),
+             measurement("phi_start", self.phi_start.0, "°"),
+             measurement("phi_stop", self.phi_stop.0, "°"),
+             measurement("phi_step", self.phi_step.0, "°"),
+             measurement("theta_start", self.theta_start.0, "°"),
+             measurement("theta_stop", self.theta_stop.0, "°"),
+             measurement("theta_step", self.theta_step.0, "°"),
+             measurement("temperature_mount", self.temperature_mount.0, "°C"),
+             measurement("solar1", self.solar1.amps(), "A"),
+             measurement("wind1", self.wind1.amps(), "A"),
+             measurement("wind2", self.wind2.amps(), "A"),
+             measurement("solar2", self.solar2.amps(), "A"),
+             measurement("efoy1", self.efoy1.amps(), "A"),
+             measurement("efoy2", self.efoy2.amps(), "A"),
+             measurement("b1", self.b1.amps(), "A"),
+             measurement("b2", self.b2.amps(), "A"),
+             measurement("b3", self.b3.amps(), "A"),
+             measurement("b4", self.b4.amps(), "A"),
+             measurement("soc1", self.soc1.percentage(), "%"),
+             measurement("ccl1", self.ccl1.percentage(), "%"),
+             measurement("dcl1", self.dcl1.percentage(), "%"),
+             measurement("soc2", self.soc2.percentage(), "%"),
+             measurement("ccl2", self.ccl2.percentage(), "%"),
+             measurement("dcl2", self.dcl2.percentage(), "%"),
+             measurement("soc3", self.soc3.percentage(), "%"),
+             measurement("ccl3", self.ccl3.percentage(), "%"),
+             measurement("dcl3", self.dcl3.percentage(), "%"),
+             measurement("soc4", self.soc4.percentage(), "%"),
+             measurement("ccl4", self.ccl4.percentage(), "%"),
+             measurement("dcl4", self.dcl4.percentage(), "%")]
+    }
+
     fn new(s: &str, messages: Vec<Message>) -> result::Result<HeartbeatV1, ParseHeartbeatError> {
         let d = s.split(',').collect::<Vec<_>>();
         if d.len() != HEARTBEAT_FIELD_COUNT {
@@ -401,6 +539,22 @@ mod tests {
         assert_eq!(OrionPercentage(-0.344048), heartbeat.dcl4);
     }
 
+    #[test]
+    fn hass_current() {
+        assert_eq!(0., Hass50Amps(2.5).amps());
+        assert_eq!(50., Hass50Amps(3.125).amps());
+        assert_eq!(-100., Hass100Amps(1.875).amps());
+
+        let messages = messages_from_paths(&vec!["data/150729_020200.sbd"]);
+        let heartbeat = messages.into_heartbeats().unwrap().pop().unwrap().unwrap();
+        assert!(heartbeat.efoy2.amps().abs() < 1.);
+        assert!(heartbeat.b1.amps().abs() < 2.);
+        let measurements = heartbeat.measurements();
+        let efoy2 = measurements.iter().find(|m| m.name == "efoy2").unwrap();
+        assert_eq!("A", efoy2.units);
+        assert!(efoy2.value.abs() < 1.);
+    }
+
     #[test]
     fn two_messages_one_heartbeat() {
         let messages = messages_from_paths(&vec!["data/160714_000240.sbd",
@@ -0,0 +1,303 @@
+//! Serve camera images straight from each camera's directory.
+//!
+//! By default images are hosted by a separate web server at `img_url`. When `serve_images` is
+//! set, this server can host them itself, so everything can run on a single machine. Images are
+//! served at the paths that `Camera::url` builds for cameras with `urls_by_name`, i.e.
+//! `<camera name>/<file name>` or `<camera name>/YYYY/MM/<file name>` for archived images, so
+//! `img_url` should point at wherever the image handler is mounted.
+
+use std::collections::HashMap;
+use std::fs::{self, File, Metadata};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::PathBuf;
+use std::time::UNIX_EPOCH;
+
+use iron::{Handler, status};
+use iron::headers::{AcceptRanges, ByteRangeSpec, CacheControl, CacheDirective, ContentRange,
+                    ContentRangeSpec, ContentType, ETag, EntityTag, HttpDate, IfModifiedSince,
+                    IfNoneMatch, LastModified, Range, RangeUnit};
+use iron::prelude::*;
+use iron::mime::{Mime, SubLevel, TopLevel};
+
+use time;
+
+use url::percent_encoding::percent_decode;
+
+use cam::Camera;
+
+/// How long clients may cache an image before revalidating, in seconds.
+///
+/// Images are named by their capture time and never change once they're completely written, but
+/// they do arrive over a slow link, so we don't cache forever.
+const MAX_AGE: u32 = 24 * 60 * 60;
+
+/// Iron handler that serves camera images from disk.
+///
+/// Only files that live directly inside a camera's directory or its archive and match that
+/// camera's naming scheme are served, so requests can't escape the camera directories.
+#[derive(Debug)]
+pub struct ImageHandler {
+    cameras: HashMap<String, Camera>,
+}
+
+impl ImageHandler {
+    /// Creates a new image handler for the given cameras.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use atlas::cam::Camera;
+    /// # use atlas::images::ImageHandler;
+    /// let handler = ImageHandler::new(vec![Camera::new("ATLAS_CAM", "data").unwrap()]);
+    /// ```
+    pub fn new(cameras: Vec<Camera>) -> ImageHandler {
+        ImageHandler {
+            cameras: cameras.into_iter()
+                .map(|camera| (camera.name().to_string(), camera))
+                .collect(),
+        }
+    }
+
+    /// Resolves url path segments to an image path.
+    ///
+    /// Returns `None` if the segments don't name an image of one of our cameras.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use atlas::cam::Camera;
+    /// # use atlas::images::ImageHandler;
+    /// let handler = ImageHandler::new(vec![Camera::new("ATLAS_CAM", "data").unwrap()]);
+    /// assert!(handler.resolve(&["ATLAS_CAM", "ATLAS_CAM_20160725_121500.jpg"]).is_some());
+    /// assert!(handler.resolve(&["ATLAS_CAM", "..", "Cargo.toml"]).is_none());
+    /// ```
+    pub fn resolve<S: AsRef<str>>(&self, segments: &[S]) -> Option<PathBuf> {
+        let segments = segments.iter()
+            .map(|s| percent_decode(s.as_ref().as_bytes()).decode_utf8_lossy().into_owned())
+            .collect::<Vec<_>>();
+        if (segments.len() != 2 && segments.len() != 4) ||
+           segments.iter().any(|s| {
+            s.is_empty() || s == "." || s == ".." || s.contains('/') || s.contains('\\') ||
+            s.contains('\0')
+        }) {
+            return None;
+        }
+        let camera = match self.cameras.get(&segments[0]) {
+            Some(camera) => camera,
+            None => return None,
+        };
+        let relative = segments[1..].iter().collect::<PathBuf>();
+        let path = camera.path().join(&relative);
+        if camera.datetime(&path).is_err() {
+            return None;
+        }
+        if segments.len() == 4 && camera.archive_path(&path).ok().as_ref() != Some(&path) {
+            return None;
+        }
+        match (fs::canonicalize(camera.path()), fs::canonicalize(&path)) {
+            (Ok(directory), Ok(canonical)) => {
+                if canonical == directory.join(&relative) {
+                    Some(path)
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Handler for ImageHandler {
+    fn handle(&self, request: &mut Request) -> IronResult<Response> {
+        let path = iexpect!(self.resolve(&request.url.path), status::NotFound);
+        let metadata = itry!(fs::metadata(&path), status::NotFound);
+        if !metadata.is_file() {
+            return Ok(Response::with(status::NotFound));
+        }
+        let length = metadata.len();
+        let etag = entity_tag(&metadata);
+        let last_modified = last_modified(&metadata);
+
+        let mut response = Response::new();
+        response.headers.set(ETag(etag.clone()));
+        response.headers.set(CacheControl(vec![CacheDirective::Public,
+                                               CacheDirective::MaxAge(MAX_AGE)]));
+        response.headers.set(AcceptRanges(vec![RangeUnit::Bytes]));
+        if let Some(last_modified) = last_modified {
+            response.headers.set(LastModified(HttpDate(last_modified)));
+        }
+
+        let not_modified = match request.headers.get::<IfNoneMatch>() {
+            Some(&IfNoneMatch::Any) => true,
+            Some(&IfNoneMatch::Items(ref tags)) => tags.iter().any(|t| t.weak_eq(&etag)),
+            None => {
+                match (request.headers.get::<IfModifiedSince>(), last_modified) {
+                    (Some(&IfModifiedSince(HttpDate(since))), Some(last_modified)) => {
+                        last_modified.to_timespec() <= since.to_timespec()
+                    }
+                    _ => false,
+                }
+            }
+        };
+        if not_modified {
+            response.status = Some(status::NotModified);
+            return Ok(response);
+        }
+
+        let range = match request.headers.get::<Range>() {
+            Some(&Range::Bytes(ref specs)) if specs.len() == 1 => {
+                match byte_range(&specs[0], length) {
+                    Some(range) => Some(range),
+                    None => {
+                        response.headers.set(ContentRange(ContentRangeSpec::Bytes {
+                            range: None,
+                            instance_length: Some(length),
+                        }));
+                        response.status = Some(status::RangeNotSatisfiable);
+                        return Ok(response);
+                    }
+                }
+            }
+            _ => None,
+        };
+
+        let mut file = itry!(File::open(&path));
+        let mut bytes = Vec::new();
+        match range {
+            Some((start, end)) => {
+                itry!(file.seek(SeekFrom::Start(start)));
+                itry!(file.take(end - start + 1).read_to_end(&mut bytes));
+                response.headers.set(ContentRange(ContentRangeSpec::Bytes {
+                    range: Some((start, end)),
+                    instance_length: Some(length),
+                }));
+                response.status = Some(status::PartialContent);
+            }
+            None => {
+                itry!(file.read_to_end(&mut bytes));
+                response.status = Some(status::Ok);
+            }
+        }
+        response.headers.set(ContentType(Mime(TopLevel::Image, SubLevel::Jpeg, vec![])));
+        response.body = Some(Box::new(bytes));
+        Ok(response)
+    }
+}
+
+/// Returns a strong entity tag built from a file's modification time and length.
+fn entity_tag(metadata: &Metadata) -> EntityTag {
+    let modified = metadata.modified()
+        .ok()
+        .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    EntityTag::strong(format!("{:x}-{:x}", modified, metadata.len()))
+}
+
+fn last_modified(metadata: &Metadata) -> Option<time::Tm> {
+    metadata.modified()
+        .ok()
+        .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+        .map(|d| time::at_utc(time::Timespec::new(d.as_secs() as i64, 0)))
+}
+
+/// Converts a byte range spec into inclusive start and end offsets for a file of `length` bytes.
+///
+/// Returns `None` if the range can't be satisfied.
+fn byte_range(spec: &ByteRangeSpec, length: u64) -> Option<(u64, u64)> {
+    if length == 0 {
+        return None;
+    }
+    match *spec {
+        ByteRangeSpec::FromTo(start, end) => {
+            if start > end || start >= length {
+                None
+            } else if end >= length {
+                Some((start, length - 1))
+            } else {
+                Some((start, end))
+            }
+        }
+        ByteRangeSpec::AllFrom(start) => {
+            if start >= length {
+                None
+            } else {
+                Some((start, length - 1))
+            }
+        }
+        ByteRangeSpec::Last(0) => None,
+        ByteRangeSpec::Last(n) => {
+            if n >= length {
+                Some((0, length - 1))
+            } else {
+                Some((length - n, length - 1))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::{byte_range, entity_tag};
+
+    use std::fs;
+
+    use iron::headers::ByteRangeSpec;
+
+    use cam::Camera;
+
+    fn handler() -> ImageHandler {
+        ImageHandler::new(vec![Camera::new("ATLAS_CAM", "data").unwrap()])
+    }
+
+    #[test]
+    fn resolve() {
+        let handler = handler();
+        assert!(handler.resolve(&["ATLAS_CAM", "ATLAS_CAM_20160725_121500.jpg"]).is_some());
+        assert!(handler.resolve(&["ATLAS_CAM", "ATLAS_CAM_20160725_000000.jpg"]).is_none());
+        assert!(handler.resolve(&["other", "ATLAS_CAM_20160725_121500.jpg"]).is_none());
+        assert!(handler.resolve(&["data", "ATLAS_CAM_20160725_121500.jpg"]).is_none());
+        assert!(handler.resolve(&["ATLAS_CAM", "config.toml"]).is_none());
+        assert!(handler.resolve(&["ATLAS_CAM", "2016", "07", "ATLAS_CAM_20160725_121500.jpg"])
+            .is_none());
+    }
+
+    #[test]
+    fn same_directory_names() {
+        let handler = ImageHandler::new(vec![Camera::new("ATLAS_CAM", "data").unwrap(),
+                                             Camera::new("HEL_Terminus", "data").unwrap()]);
+        assert!(handler.resolve(&["ATLAS_CAM", "ATLAS_CAM_20160725_121500.jpg"]).is_some());
+        assert!(handler.resolve(&["HEL_Terminus", "HEL_Terminus_20160803_180000.jpg"])
+            .is_some());
+        assert!(handler.resolve(&["HEL_Terminus", "ATLAS_CAM_20160725_121500.jpg"]).is_none());
+    }
+
+    #[test]
+    fn resolve_traversal() {
+        let handler = handler();
+        assert!(handler.resolve(&["ATLAS_CAM", ".."]).is_none());
+        assert!(handler.resolve(&["..", "ATLAS_CAM", "ATLAS_CAM_20160725_121500.jpg"]).is_none());
+        assert!(handler.resolve(&["ATLAS_CAM", "..%2FATLAS_CAM_20160725_121500.jpg"]).is_none());
+        assert!(handler.resolve(&["ATLAS_CAM", "%2E%2E"]).is_none());
+    }
+
+    #[test]
+    fn ranges() {
+        assert_eq!(Some((0, 9)), byte_range(&ByteRangeSpec::FromTo(0, 9), 100));
+        assert_eq!(Some((90, 99)), byte_range(&ByteRangeSpec::FromTo(90, 200), 100));
+        assert_eq!(None, byte_range(&ByteRangeSpec::FromTo(100, 200), 100));
+        assert_eq!(None, byte_range(&ByteRangeSpec::FromTo(10, 9), 100));
+        assert_eq!(Some((50, 99)), byte_range(&ByteRangeSpec::AllFrom(50), 100));
+        assert_eq!(Some((90, 99)), byte_range(&ByteRangeSpec::Last(10), 100));
+        assert_eq!(Some((0, 99)), byte_range(&ByteRangeSpec::Last(1000), 100));
+        assert_eq!(None, byte_range(&ByteRangeSpec::Last(0), 100));
+    }
+
+    #[test]
+    fn etag_changes_with_file() {
+        let a = entity_tag(&fs::metadata("data/ATLAS_CAM_20160725_121500.jpg").unwrap());
+        let b = entity_tag(&fs::metadata("data/HEL_Terminus_20160803_180000.jpg").unwrap());
+        assert!(!a.strong_eq(&b));
+    }
+}
@@ -0,0 +1,612 @@
+//! Browse the raw Iridium SBD messages.
+//!
+//! Heartbeats are built from the payloads of mobile-originated (MO) SBD messages, but the rest of
+//! each message is useful too when debugging the link: the MOMSN sequence, the session status,
+//! and the location estimate that the Iridium gateway adds to each message. We read the DirectIP
+//! format ourselves so that every information element is available.
+//!
+//! Reading every message in the storage on every request gets slow, so messages are kept in an
+//! in-memory index that a `MessageWatcher` refreshes when new messages arrive.
+
+use std::ascii::AsciiExt;
+use std::cmp;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::result;
+use std::sync::{Arc, RwLock};
+
+use chrono::{DateTime, TimeZone, UTC};
+
+use handlebars_iron::Template;
+
+use iron::{Handler, status};
+use iron::headers::{Charset, ContentDisposition, ContentType, DispositionParam, DispositionType};
+use iron::prelude::*;
+use iron::mime::{Mime, SubLevel, TopLevel};
+
+use router::Router;
+
+use rustc_serialize::json::{Json, ToJson};
+
+use url::form_urlencoded;
+
+use Result;
+use heartbeat::HeartbeatV1;
+use query;
+use watch::DirectoryWatcher;
+
+const DEFAULT_PER_PAGE: usize = 50;
+const MAX_PER_PAGE: usize = 500;
+
+/// The longest payload preview shown on the message page, in bytes.
+const PAYLOAD_PREVIEW: usize = 80;
+
+const MO_HEADER: u8 = 0x01;
+const MO_PAYLOAD: u8 = 0x02;
+const MO_LOCATION: u8 = 0x03;
+const MO_HEADER_LENGTH: usize = 28;
+const MO_LOCATION_LENGTH: usize = 11;
+
+/// The location estimate that the Iridium gateway attaches to an MO message.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MoLocation {
+    /// Latitude, positive north.
+    pub latitude: f64,
+    /// Longitude, positive east.
+    pub longitude: f64,
+    /// The radius of the circle with an 80% chance of containing the modem, in kilometers.
+    pub cep_radius: u32,
+}
+
+/// A single mobile-originated SBD message and where it's stored.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MessageInfo {
+    /// The path of the `.sbd` file.
+    pub path: PathBuf,
+    /// The gateway's call detail record reference.
+    pub cdr_reference: u32,
+    /// The IMEI of the sending modem.
+    pub imei: String,
+    /// The session status code. Codes below ten mean the session succeeded.
+    pub session_status: u8,
+    /// The mobile-originated message sequence number.
+    pub momsn: u16,
+    /// The mobile-terminated message sequence number.
+    pub mtmsn: u16,
+    /// The time of the Iridium session.
+    pub time_of_session: DateTime<UTC>,
+    /// The message payload.
+    pub payload: Vec<u8>,
+    /// The location estimate, if the gateway sent one.
+    pub location: Option<MoLocation>,
+}
+
+impl MessageInfo {
+    /// Reads a message from a `.sbd` file.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use atlas::iridium::MessageInfo;
+    /// let message = MessageInfo::read("data/150729_020200.sbd").unwrap();
+    /// assert_eq!("300234063909200", message.imei);
+    /// assert_eq!(47, message.momsn);
+    /// ```
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<MessageInfo> {
+        let mut bytes = Vec::new();
+        try!(try!(File::open(&path)).read_to_end(&mut bytes));
+        MessageInfo::parse(path, &bytes).map_err(From::from)
+    }
+
+    /// Parses the bytes of a message stored at `path`.
+    pub fn parse<P: AsRef<Path>>(path: P,
+                                 bytes: &[u8])
+                                 -> result::Result<MessageInfo, ParseSbdError> {
+        if bytes.len() < 3 {
+            return Err(ParseSbdError::Truncated(0));
+        }
+        if bytes[0] != 1 {
+            return Err(ParseSbdError::ProtocolRevision(bytes[0]));
+        }
+        let end = 3 + be_u16(&bytes[1..3]) as usize;
+        if bytes.len() < end {
+            return Err(ParseSbdError::Truncated(bytes.len()));
+        }
+        let mut header = None;
+        let mut payload = Vec::new();
+        let mut location = None;
+        let mut offset = 3;
+        while offset < end {
+            if offset + 3 > end {
+                return Err(ParseSbdError::Truncated(offset));
+            }
+            let id = bytes[offset];
+            let length = be_u16(&bytes[offset + 1..offset + 3]) as usize;
+            let start = offset + 3;
+            if start + length > end {
+                return Err(ParseSbdError::Truncated(offset));
+            }
+            let data = &bytes[start..start + length];
+            match id {
+                MO_HEADER if length == MO_HEADER_LENGTH => header = Some(data),
+                MO_HEADER => return Err(ParseSbdError::InvalidElement(id, length)),
+                MO_PAYLOAD => payload = data.to_vec(),
+                MO_LOCATION if length == MO_LOCATION_LENGTH => {
+                    location = Some(parse_location(data))
+                }
+                MO_LOCATION => return Err(ParseSbdError::InvalidElement(id, length)),
+                _ => {}
+            }
+            offset = start + length;
+        }
+        let header = try!(header.ok_or(ParseSbdError::MissingHeader));
+        Ok(MessageInfo {
+            path: path.as_ref().to_path_buf(),
+            cdr_reference: be_u32(&header[0..4]),
+            imei: String::from_utf8_lossy(&header[4..19]).into_owned(),
+            session_status: header[19],
+            momsn: be_u16(&header[20..22]),
+            mtmsn: be_u16(&header[22..24]),
+            time_of_session: UTC.timestamp(be_u32(&header[24..28]) as i64, 0),
+            payload: payload,
+            location: location,
+        })
+    }
+
+    /// Returns an identifier for this message that's unique across modems and MOMSN rollovers.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use atlas::iridium::MessageInfo;
+    /// let message = MessageInfo::read("data/150729_020200.sbd").unwrap();
+    /// assert_eq!("300234063909200-1438135320-47", message.id());
+    /// ```
+    pub fn id(&self) -> String {
+        format!("{}-{}-{}", self.imei, self.time_of_session.timestamp(), self.momsn)
+    }
+
+    /// Returns a description of the session status.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use atlas::iridium::MessageInfo;
+    /// let message = MessageInfo::read("data/150729_020200.sbd").unwrap();
+    /// assert_eq!("success", message.session_status_description());
+    /// ```
+    pub fn session_status_description(&self) -> &'static str {
+        match self.session_status {
+            0 => "success",
+            1 => "success, but the MT message was too large",
+            2 => "success, but the location is unacceptable",
+            10 => "timed out",
+            12 => "MO message too large",
+            13 => "RF link lost",
+            14 => "IMEI protocol anomaly",
+            15 => "IMEI prohibited from the gateway",
+            _ => "unknown",
+        }
+    }
+}
+
+fn parse_location(data: &[u8]) -> MoLocation {
+    let south = data[0] & 0b10 != 0;
+    let west = data[0] & 0b01 != 0;
+    let latitude = data[1] as f64 + be_u16(&data[2..4]) as f64 / 60000.;
+    let longitude = data[4] as f64 + be_u16(&data[5..7]) as f64 / 60000.;
+    MoLocation {
+        latitude: if south { -latitude } else { latitude },
+        longitude: if west { -longitude } else { longitude },
+        cep_radius: be_u32(&data[7..11]),
+    }
+}
+
+fn be_u16(bytes: &[u8]) -> u16 {
+    (bytes[0] as u16) << 8 | bytes[1] as u16
+}
+
+fn be_u32(bytes: &[u8]) -> u32 {
+    (bytes[0] as u32) << 24 | (bytes[1] as u32) << 16 | (bytes[2] as u32) << 8 | bytes[3] as u32
+}
+
+/// Error returned when some bytes can't be parsed as an MO SBD message.
+#[derive(Clone, Copy, Debug)]
+pub enum ParseSbdError {
+    /// We only know protocol revision one.
+    ProtocolRevision(u8),
+    /// The message ends early, at the given byte offset.
+    Truncated(usize),
+    /// An information element, given by id and length, has the wrong length.
+    InvalidElement(u8, usize),
+    /// There's no MO header.
+    MissingHeader,
+}
+
+impl error::Error for ParseSbdError {
+    fn description(&self) -> &str {
+        match *self {
+            ParseSbdError::ProtocolRevision(_) => "unsupported protocol revision",
+            ParseSbdError::Truncated(_) => "truncated message",
+            ParseSbdError::InvalidElement(_, _) => "invalid information element",
+            ParseSbdError::MissingHeader => "missing MO header",
+        }
+    }
+}
+
+impl fmt::Display for ParseSbdError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseSbdError::ProtocolRevision(n) => write!(f, "unsupported protocol revision {}", n),
+            ParseSbdError::Truncated(n) => write!(f, "message truncated at byte {}", n),
+            ParseSbdError::InvalidElement(id, length) => {
+                write!(f, "information element {} has invalid length {}", id, length)
+            }
+            ParseSbdError::MissingHeader => write!(f, "missing MO header"),
+        }
+    }
+}
+
+/// Watches the Iridium storage directory and keeps an index of its messages.
+///
+/// The index is sorted by time of session. Only new files are read on each refresh.
+#[derive(Debug)]
+pub struct MessageWatcher {
+    directory: PathBuf,
+    messages: Arc<RwLock<Vec<MessageInfo>>>,
+}
+
+impl MessageWatcher {
+    /// Creates a new watcher for the messages under a directory.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use std::sync::{Arc, RwLock};
+    /// # use atlas::iridium::MessageWatcher;
+    /// let watcher = MessageWatcher::new("data", Arc::new(RwLock::new(Vec::new())));
+    /// ```
+    pub fn new<P: AsRef<Path>>(directory: P,
+                               messages: Arc<RwLock<Vec<MessageInfo>>>)
+                               -> MessageWatcher {
+        MessageWatcher {
+            directory: directory.as_ref().to_path_buf(),
+            messages: messages,
+        }
+    }
+}
+
+impl DirectoryWatcher for MessageWatcher {
+    fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    fn refresh(&mut self) -> Result<()> {
+        let mut paths = Vec::new();
+        try!(sbd_paths(&self.directory, &mut paths));
+        let paths = paths.into_iter().collect::<HashSet<_>>();
+        let mut messages = self.messages
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|m| paths.contains(&m.path))
+            .cloned()
+            .collect::<Vec<_>>();
+        let known = messages.iter().map(|m| m.path.clone()).collect::<HashSet<_>>();
+        for path in paths.difference(&known) {
+            match MessageInfo::read(path) {
+                Ok(message) => messages.push(message),
+                Err(err) => {
+                    warn!("Could not read SBD message {}: {}", path.to_string_lossy(), err)
+                }
+            }
+        }
+        messages.sort_by(|a, b| {
+            (&a.time_of_session, &a.imei, a.momsn).cmp(&(&b.time_of_session, &b.imei, b.momsn))
+        });
+        *self.messages.write().unwrap() = messages;
+        Ok(())
+    }
+}
+
+fn sbd_paths(directory: &Path, paths: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in try!(fs::read_dir(directory)) {
+        let path = try!(entry).path();
+        if path.is_dir() {
+            try!(sbd_paths(&path, paths));
+        } else if path.extension().map(|e| e.to_ascii_lowercase() == "sbd").unwrap_or(false) {
+            paths.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Browses the indexed SBD messages.
+#[derive(Clone, Debug)]
+pub struct MessageBrowser {
+    messages: Arc<RwLock<Vec<MessageInfo>>>,
+    heartbeats: Vec<Arc<RwLock<Vec<HeartbeatV1>>>>,
+}
+
+impl MessageBrowser {
+    /// Creates a new browser for the given message index and heartbeats.
+    ///
+    /// The heartbeats are used to link each message to the heartbeat it's part of.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use std::sync::{Arc, RwLock};
+    /// # use atlas::iridium::MessageBrowser;
+    /// let browser = MessageBrowser::new(Arc::new(RwLock::new(Vec::new())),
+    ///                                   Arc::new(RwLock::new(Vec::new())));
+    /// ```
+    pub fn new(messages: Arc<RwLock<Vec<MessageInfo>>>,
+               heartbeats: Arc<RwLock<Vec<HeartbeatV1>>>)
+               -> MessageBrowser {
+        MessageBrowser {
+            messages: messages,
+            heartbeats: vec![heartbeats],
+        }
+    }
+
+    /// Also links messages to these heartbeats, e.g. the heartbeats of another station.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use std::sync::{Arc, RwLock};
+    /// # use atlas::iridium::MessageBrowser;
+    /// let browser = MessageBrowser::new(Arc::new(RwLock::new(Vec::new())),
+    ///                                   Arc::new(RwLock::new(Vec::new())))
+    ///     .heartbeats(Arc::new(RwLock::new(Vec::new())));
+    /// ```
+    pub fn heartbeats(mut self, heartbeats: Arc<RwLock<Vec<HeartbeatV1>>>) -> MessageBrowser {
+        self.heartbeats.push(heartbeats);
+        self
+    }
+
+    /// Returns a handler for the message page.
+    ///
+    /// Messages are shown newest first. They can be filtered with the `imei`, `start`, and `end`
+    /// query parameters, where `start` and `end` are dates (`%Y-%m-%d`, inclusive) or RFC 3339
+    /// datetimes. Use `page` and `per_page` to paginate.
+    pub fn page_handler(&self) -> MessagesHandler {
+        MessagesHandler { browser: self.clone() }
+    }
+
+    /// Returns a handler that downloads the raw `.sbd` file of the message in the `id` route
+    /// parameter.
+    pub fn raw_handler(&self) -> RawMessageHandler {
+        RawMessageHandler { browser: self.clone() }
+    }
+
+    /// Returns the datetime of the heartbeat that each message is part of, keyed by message id.
+    fn heartbeat_datetimes(&self) -> HashMap<String, DateTime<UTC>> {
+        let mut datetimes = HashMap::new();
+        for heartbeats in &self.heartbeats {
+            for heartbeat in heartbeats.read().unwrap().iter() {
+                for message in &heartbeat.messages {
+                    datetimes.insert(format!("{}-{}-{}",
+                                             message.imei(),
+                                             message.time_of_session().timestamp(),
+                                             message.momsn()),
+                                     heartbeat.datetime());
+                }
+            }
+        }
+        datetimes
+    }
+}
+
+/// Serves the message page.
+#[derive(Debug)]
+pub struct MessagesHandler {
+    browser: MessageBrowser,
+}
+
+impl Handler for MessagesHandler {
+    fn handle(&self, request: &mut Request) -> IronResult<Response> {
+        let params = query::params(request);
+        let start = match params.get("start") {
+            Some(s) => {
+                Some(iexpect!(query::parse_bound(s, false),
+                              (status::BadRequest, "Invalid start.")))
+            }
+            None => None,
+        };
+        let end = match params.get("end") {
+            Some(s) => {
+                Some(iexpect!(query::parse_bound(s, true), (status::BadRequest, "Invalid end.")))
+            }
+            None => None,
+        };
+        let imei = params.get("imei");
+        let page = match params.get("page") {
+            Some(s) => itry!(s.parse::<usize>(), status::BadRequest),
+            None => 1,
+        };
+        if page == 0 {
+            return Ok(Response::with((status::BadRequest, "Pages start at 1.")));
+        }
+        let per_page = match params.get("per_page") {
+            Some(s) => itry!(s.parse::<usize>(), status::BadRequest),
+            None => DEFAULT_PER_PAGE,
+        };
+        let per_page = if per_page == 0 || per_page > MAX_PER_PAGE {
+            MAX_PER_PAGE
+        } else {
+            per_page
+        };
+
+        let heartbeats = self.browser.heartbeat_datetimes();
+        let messages = self.browser.messages.read().unwrap();
+        let matching = messages.iter()
+            .rev()
+            .filter(|m| imei.map(|i| &m.imei == i).unwrap_or(true))
+            .filter(|m| start.as_ref().map(|s| &m.time_of_session >= s).unwrap_or(true))
+            .filter(|m| end.as_ref().map(|e| &m.time_of_session < e).unwrap_or(true))
+            .collect::<Vec<_>>();
+        let pages = (matching.len() + per_page - 1) / per_page;
+        let page = if page > pages && pages > 0 { pages } else { page };
+        let rows = matching.iter()
+            .skip((page - 1).saturating_mul(per_page))
+            .take(per_page)
+            .map(|m| message_json(m, heartbeats.get(&m.id())))
+            .collect::<Vec<_>>();
+
+        let mut data = BTreeMap::<String, Json>::new();
+        for key in &["imei", "start", "end"] {
+            data.insert(key.to_string(), params.get(*key).cloned().to_json());
+        }
+        data.insert("messages".to_string(), Json::Array(rows));
+        data.insert("total".to_string(), matching.len().to_json());
+        data.insert("page".to_string(), page.to_json());
+        data.insert("pages".to_string(), pages.to_json());
+        if page > 1 {
+            data.insert("previous".to_string(), page_url(&params, page - 1).to_json());
+        }
+        if page < pages {
+            data.insert("next".to_string(), page_url(&params, page + 1).to_json());
+        }
+        let mut response = Response::new();
+        response.set_mut(Template::new("messages", data)).set_mut(status::Ok);
+        Ok(response)
+    }
+}
+
+fn message_json(message: &MessageInfo, heartbeat: Option<&DateTime<UTC>>) -> Json {
+    let mut map = BTreeMap::new();
+    map.insert("id".to_string(), message.id().to_json());
+    map.insert("imei".to_string(), message.imei.to_json());
+    map.insert("momsn".to_string(), message.momsn.to_json());
+    map.insert("mtmsn".to_string(), message.mtmsn.to_json());
+    map.insert("cdr_reference".to_string(), message.cdr_reference.to_json());
+    map.insert("session_status".to_string(), message.session_status.to_json());
+    map.insert("session_status_description".to_string(),
+               message.session_status_description().to_json());
+    map.insert("time_of_session".to_string(),
+               message.time_of_session.to_rfc3339().to_json());
+    map.insert("size".to_string(), message.payload.len().to_json());
+    let preview = &message.payload[..cmp::min(message.payload.len(), PAYLOAD_PREVIEW)];
+    let preview = String::from_utf8_lossy(preview).into_owned();
+    map.insert("payload".to_string(), preview.to_json());
+    if let Some(location) = message.location {
+        map.insert("location".to_string(),
+                   format!("{:.4}, {:.4} ± {} km",
+                           location.latitude,
+                           location.longitude,
+                           location.cep_radius)
+                       .to_json());
+    }
+    if let Some(heartbeat) = heartbeat {
+        map.insert("heartbeat".to_string(), heartbeat.to_rfc3339().to_json());
+    }
+    Json::Object(map)
+}
+
+fn page_url(params: &HashMap<String, String>, page: usize) -> String {
+    let mut serializer = form_urlencoded::Serializer::new(String::new());
+    let mut keys = params.keys().filter(|k| *k != "page").collect::<Vec<_>>();
+    keys.sort();
+    for key in keys {
+        serializer.append_pair(key, &params[key]);
+    }
+    serializer.append_pair("page", &page.to_string());
+    format!("?{}", serializer.finish())
+}
+
+/// Serves the raw `.sbd` file of a message.
+#[derive(Debug)]
+pub struct RawMessageHandler {
+    browser: MessageBrowser,
+}
+
+impl Handler for RawMessageHandler {
+    fn handle(&self, request: &mut Request) -> IronResult<Response> {
+        let id = iexpect!(request.extensions.get::<Router>().and_then(|p| p.find("id")))
+            .to_string();
+        let path = {
+            let messages = self.browser.messages.read().unwrap();
+            iexpect!(messages.iter().find(|m| m.id() == id), status::NotFound).path.clone()
+        };
+        let mut bytes = Vec::new();
+        itry!(itry!(File::open(&path), status::NotFound).read_to_end(&mut bytes));
+        let mut response = Response::with((status::Ok, bytes));
+        response.headers
+            .set(ContentType(Mime(TopLevel::Application, SubLevel::OctetStream, vec![])));
+        response.headers.set(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(Charset::Ext("UTF-8".to_string()),
+                                                        None,
+                                                        format!("{}.sbd", id).into_bytes())],
+        });
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::page_url;
+
+    use std::collections::HashMap;
+    use std::sync::{Arc, RwLock};
+
+    use chrono::{TimeZone, UTC};
+
+    use watch::DirectoryWatcher;
+
+    #[test]
+    fn header() {
+        let message = MessageInfo::read("data/160714_000252.sbd").unwrap();
+        assert_eq!("300234063909200", message.imei);
+        assert_eq!(0x91119b0d, message.cdr_reference);
+        assert_eq!(0, message.session_status);
+        assert_eq!(5958, message.momsn);
+        assert_eq!(0, message.mtmsn);
+        assert_eq!(UTC.ymd(2016, 7, 14).and_hms(0, 2, 52), message.time_of_session);
+        assert_eq!(b"05,-0.340767".to_vec(), message.payload);
+        assert_eq!(None, message.location);
+    }
+
+    #[test]
+    fn location() {
+        let mut bytes = vec![1, 0, 45];
+        bytes.extend(&[1, 0, 28, 0, 0, 0, 1]);
+        bytes.extend(b"300234063909200");
+        bytes.extend(&[0, 0, 1, 0, 0, 0x57, 0x86, 0xd6, 0xac]);
+        bytes.extend(&[3, 0, 11, 0b10, 69, 0x75, 0x30, 49, 0x3a, 0x98, 0, 0, 0, 4]);
+        let message = MessageInfo::parse("test.sbd", &bytes).unwrap();
+        let location = message.location.unwrap();
+        assert!((location.latitude + 69.5).abs() < 1e-9);
+        assert!((location.longitude - 49.25).abs() < 1e-9);
+        assert_eq!(4, location.cep_radius);
+        assert!(MessageInfo::parse("test.sbd", &bytes[..40]).is_err());
+        assert!(MessageInfo::parse("test.sbd", b"not an sbd message").is_err());
+    }
+
+    #[test]
+    fn index() {
+        let messages = Arc::new(RwLock::new(Vec::new()));
+        let mut watcher = MessageWatcher::new("data", messages.clone());
+        watcher.refresh().unwrap();
+        watcher.refresh().unwrap();
+        let messages = messages.read().unwrap();
+        assert_eq!(3, messages.len());
+        assert_eq!(47, messages[0].momsn);
+        assert_eq!(5958, messages[2].momsn);
+    }
+
+    #[test]
+    fn pages() {
+        let mut params = HashMap::new();
+        params.insert("imei".to_string(), "300234063909200".to_string());
+        params.insert("page".to_string(), "2".to_string());
+        assert_eq!("?imei=300234063909200&page=3", page_url(&params, 3));
+    }
+}
@@ -0,0 +1,363 @@
+//! Just enough JPEG parsing to index our camera images.
+//!
+//! We want the dimensions and EXIF capture time of each image, and whether the file made it
+//! across the satellite link in one piece. None of that requires decoding the image data, so we
+//! walk the JPEG segment headers ourselves instead of pulling in a full decoder.
+
+use std::error;
+use std::fmt;
+use std::result;
+use std::str;
+
+use chrono::NaiveDateTime;
+
+const EXIF_DATETIME_FORMAT: &'static str = "%Y:%m:%d %H:%M:%S";
+const TAG_DATETIME: u16 = 0x0132;
+const TAG_EXIF_IFD: u16 = 0x8769;
+const TAG_DATETIME_ORIGINAL: u16 = 0x9003;
+
+macro_rules! try_opt {
+    ($x:expr) => {{
+        match $x {
+            Some(value) => value,
+            None => return None,
+        }
+    }};
+}
+
+/// The width and height of an image, in pixels.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Dimensions {
+    /// The width of the image.
+    pub width: u16,
+    /// The height of the image.
+    pub height: u16,
+}
+
+/// Information read from the headers of a JPEG file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct JpegInfo {
+    /// The image dimensions, if we found a frame header.
+    pub dimensions: Option<Dimensions>,
+    /// The capture time recorded in the EXIF data, if there is one.
+    ///
+    /// EXIF times don't have a timezone, so this is whatever the camera's clock said.
+    pub exif_datetime: Option<NaiveDateTime>,
+    /// Is the file missing its end-of-image marker, or are its headers cut short?
+    pub truncated: bool,
+}
+
+/// Parses the headers of a JPEG file.
+///
+/// Returns an error if the bytes don't look like a JPEG at all. Partial files are not errors, but
+/// are flagged as truncated.
+///
+/// # Examples
+///
+/// ```
+/// # use std::fs::File;
+/// # use std::io::Read;
+/// # use atlas::jpeg;
+/// let mut bytes = Vec::new();
+/// File::open("data/ATLAS_CAM_20160725_121500.jpg").unwrap().read_to_end(&mut bytes).unwrap();
+/// let info = jpeg::parse(&bytes).unwrap();
+/// assert_eq!(1024, info.dimensions.unwrap().width);
+/// assert!(!info.truncated);
+/// ```
+pub fn parse(bytes: &[u8]) -> result::Result<JpegInfo, ParseJpegError> {
+    if bytes.len() < 2 || bytes[0] != 0xFF || bytes[1] != 0xD8 {
+        return Err(ParseJpegError::MissingStartOfImage);
+    }
+    let mut info = JpegInfo {
+        dimensions: None,
+        exif_datetime: None,
+        truncated: !has_end_of_image(bytes),
+    };
+    let mut position = 2;
+    while position < bytes.len() {
+        if bytes[position] != 0xFF {
+            return Err(ParseJpegError::InvalidMarker(position));
+        }
+        // Markers can be padded with any number of fill bytes.
+        while position < bytes.len() && bytes[position] == 0xFF {
+            position += 1;
+        }
+        if position >= bytes.len() {
+            info.truncated = true;
+            break;
+        }
+        let marker = bytes[position];
+        position += 1;
+        match marker {
+            // End of image or start of scan, so there are no more headers to read.
+            0xD9 | 0xDA => break,
+            // Standalone markers don't have a length.
+            0x01 | 0xD0...0xD7 => continue,
+            _ => {}
+        }
+        if position + 2 > bytes.len() {
+            info.truncated = true;
+            break;
+        }
+        let length = be_u16(&bytes[position..]) as usize;
+        if length < 2 {
+            return Err(ParseJpegError::InvalidSegmentLength(position));
+        }
+        let end = position + length;
+        if end > bytes.len() {
+            info.truncated = true;
+            break;
+        }
+        let segment = &bytes[position + 2..end];
+        match marker {
+            0xC0...0xC3 | 0xC5...0xC7 | 0xC9...0xCB | 0xCD...0xCF => {
+                if segment.len() >= 5 {
+                    info.dimensions = Some(Dimensions {
+                        height: be_u16(&segment[1..]),
+                        width: be_u16(&segment[3..]),
+                    });
+                }
+            }
+            0xE1 => {
+                if segment.starts_with(b"Exif\0\0") {
+                    info.exif_datetime = exif_datetime(&segment[6..]);
+                }
+            }
+            _ => {}
+        }
+        position = end;
+    }
+    Ok(info)
+}
+
+/// Error returned when some bytes can't be parsed as a JPEG.
+#[derive(Clone, Copy, Debug)]
+pub enum ParseJpegError {
+    /// The bytes don't start with the start-of-image marker.
+    MissingStartOfImage,
+    /// We expected a marker at the given byte offset but didn't find one.
+    InvalidMarker(usize),
+    /// The segment at the given byte offset has an impossible length.
+    InvalidSegmentLength(usize),
+}
+
+impl error::Error for ParseJpegError {
+    fn description(&self) -> &str {
+        match *self {
+            ParseJpegError::MissingStartOfImage => "missing start of image marker",
+            ParseJpegError::InvalidMarker(_) => "invalid marker",
+            ParseJpegError::InvalidSegmentLength(_) => "invalid segment length",
+        }
+    }
+}
+
+impl fmt::Display for ParseJpegError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseJpegError::MissingStartOfImage => write!(f, "missing start of image marker"),
+            ParseJpegError::InvalidMarker(n) => write!(f, "invalid marker at byte {}", n),
+            ParseJpegError::InvalidSegmentLength(n) => {
+                write!(f, "invalid segment length at byte {}", n)
+            }
+        }
+    }
+}
+
+fn be_u16(bytes: &[u8]) -> u16 {
+    (bytes[0] as u16) << 8 | bytes[1] as u16
+}
+
+fn has_end_of_image(bytes: &[u8]) -> bool {
+    // Some cameras pad their files with zeros after the end-of-image marker.
+    let end = bytes.iter().rposition(|&b| b != 0).map(|i| i + 1).unwrap_or(0);
+    end >= 4 && bytes[end - 2] == 0xFF && bytes[end - 1] == 0xD9
+}
+
+fn exif_datetime(data: &[u8]) -> Option<NaiveDateTime> {
+    let tiff = try_opt!(Tiff::new(data));
+    let ifd0 = try_opt!(tiff.u32(4)) as usize;
+    let original = tiff.entry(ifd0, TAG_EXIF_IFD)
+        .and_then(|entry| tiff.u32(entry + 8))
+        .and_then(|exif| tiff.entry(exif as usize, TAG_DATETIME_ORIGINAL))
+        .and_then(|entry| tiff.datetime(entry));
+    original.or_else(|| tiff.entry(ifd0, TAG_DATETIME).and_then(|entry| tiff.datetime(entry)))
+}
+
+/// The TIFF structure that holds EXIF data.
+struct Tiff<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+impl<'a> Tiff<'a> {
+    fn new(data: &'a [u8]) -> Option<Tiff<'a>> {
+        if data.len() < 8 {
+            return None;
+        }
+        let big_endian = if &data[0..2] == b"MM" {
+            true
+        } else if &data[0..2] == b"II" {
+            false
+        } else {
+            return None;
+        };
+        Some(Tiff {
+            data: data,
+            big_endian: big_endian,
+        })
+    }
+
+    fn u16(&self, offset: usize) -> Option<u16> {
+        if offset + 2 > self.data.len() {
+            return None;
+        }
+        let (a, b) = (self.data[offset] as u16, self.data[offset + 1] as u16);
+        Some(if self.big_endian { a << 8 | b } else { b << 8 | a })
+    }
+
+    fn u32(&self, offset: usize) -> Option<u32> {
+        let a = try_opt!(self.u16(offset)) as u32;
+        let b = try_opt!(self.u16(offset + 2)) as u32;
+        Some(if self.big_endian { a << 16 | b } else { b << 16 | a })
+    }
+
+    /// Returns the offset of the entry for `tag` in the IFD at `ifd`.
+    fn entry(&self, ifd: usize, tag: u16) -> Option<usize> {
+        let count = try_opt!(self.u16(ifd)) as usize;
+        (0..count).map(|i| ifd + 2 + 12 * i).find(|&entry| self.u16(entry) == Some(tag))
+    }
+
+    fn datetime(&self, entry: usize) -> Option<NaiveDateTime> {
+        let count = try_opt!(self.u32(entry + 4)) as usize;
+        let offset = if count <= 4 {
+            entry + 8
+        } else {
+            try_opt!(self.u32(entry + 8)) as usize
+        };
+        if offset + count > self.data.len() {
+            return None;
+        }
+        str::from_utf8(&self.data[offset..offset + count])
+            .ok()
+            .and_then(|s| {
+                NaiveDateTime::parse_from_str(s.trim_right_matches('\0'), EXIF_DATETIME_FORMAT)
+                    .ok()
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs::File;
+    use std::io::Read;
+
+    use chrono::NaiveDate;
+
+    fn read(path: &str) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        File::open(path).unwrap().read_to_end(&mut bytes).unwrap();
+        bytes
+    }
+
+    /// Builds a tiny JPEG with an EXIF DateTimeOriginal and a frame header, but no image data.
+    fn exif_jpeg(big_endian: bool) -> Vec<u8> {
+        let short = |n: u16| if big_endian {
+            vec![(n >> 8) as u8, n as u8]
+        } else {
+            vec![n as u8, (n >> 8) as u8]
+        };
+        let long = |n: u32| if big_endian {
+            vec![(n >> 24) as u8, (n >> 16) as u8, (n >> 8) as u8, n as u8]
+        } else {
+            vec![n as u8, (n >> 8) as u8, (n >> 16) as u8, (n >> 24) as u8]
+        };
+        let mut tiff = Vec::new();
+        tiff.extend_from_slice(if big_endian { b"MM" } else { b"II" });
+        tiff.extend(short(42));
+        tiff.extend(long(8));
+        // IFD0, with a pointer to the EXIF IFD at offset 26.
+        tiff.extend(short(1));
+        tiff.extend(short(0x8769));
+        tiff.extend(short(4));
+        tiff.extend(long(1));
+        tiff.extend(long(26));
+        tiff.extend(long(0));
+        // EXIF IFD, with the datetime at offset 44.
+        tiff.extend(short(1));
+        tiff.extend(short(0x9003));
+        tiff.extend(short(2));
+        tiff.extend(long(20));
+        tiff.extend(long(44));
+        tiff.extend(long(0));
+        tiff.extend_from_slice(b"2017:05:01 12:00:00\0");
+
+        let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xE1];
+        let length = tiff.len() + 8;
+        jpeg.push((length >> 8) as u8);
+        jpeg.push(length as u8);
+        jpeg.extend_from_slice(b"Exif\0\0");
+        jpeg.extend(tiff);
+        jpeg.extend_from_slice(&[0xFF, 0xC0, 0x00, 0x0B, 0x08, 0x00, 0x10, 0x00, 0x20, 0x01, 0x01,
+                                 0x11, 0x00]);
+        jpeg.extend_from_slice(&[0xFF, 0xD9]);
+        jpeg
+    }
+
+    #[test]
+    fn dimensions() {
+        let info = parse(&read("data/HEL_Terminus_20160803_120000.jpg")).unwrap();
+        assert_eq!(Some(Dimensions {
+                       width: 1024,
+                       height: 768,
+                   }),
+                   info.dimensions);
+        assert_eq!(None, info.exif_datetime);
+        assert!(!info.truncated);
+    }
+
+    #[test]
+    fn truncated() {
+        let bytes = read("data/ATLAS_CAM_20160725_121500.jpg");
+        let info = parse(&bytes[..bytes.len() / 2]).unwrap();
+        assert!(info.truncated);
+        assert!(info.dimensions.is_some());
+        let info = parse(&bytes[..300]).unwrap();
+        assert!(info.truncated);
+        assert!(info.dimensions.is_none());
+    }
+
+    #[test]
+    fn zero_padded() {
+        let mut bytes = read("data/ATLAS_CAM_20160725_121500.jpg");
+        bytes.extend_from_slice(&[0, 0, 0, 0]);
+        assert!(!parse(&bytes).unwrap().truncated);
+    }
+
+    #[test]
+    fn not_a_jpeg() {
+        assert!(parse(b"GIF89a").is_err());
+        assert!(parse(b"").is_err());
+    }
+
+    #[test]
+    fn exif_little_endian() {
+        let info = parse(&exif_jpeg(false)).unwrap();
+        assert_eq!(Some(NaiveDate::from_ymd(2017, 5, 1).and_hms(12, 0, 0)),
+                   info.exif_datetime);
+        assert_eq!(Some(Dimensions {
+                       width: 32,
+                       height: 16,
+                   }),
+                   info.dimensions);
+        assert!(!info.truncated);
+    }
+
+    #[test]
+    fn exif_big_endian() {
+        let info = parse(&exif_jpeg(true)).unwrap();
+        assert_eq!(Some(NaiveDate::from_ymd(2017, 5, 1).and_hms(12, 0, 0)),
+                   info.exif_datetime);
+    }
+}
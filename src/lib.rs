@@ -5,7 +5,9 @@
         unused_import_braces, unused_qualifications)]
 
 extern crate chrono;
+extern crate gif;
 extern crate handlebars_iron;
+extern crate image;
 #[macro_use]
 extern crate iron;
 #[macro_use]
@@ -15,24 +17,47 @@ extern crate mount;
 extern crate notify;
 extern crate regex;
 extern crate router;
+extern crate rusttype;
 extern crate rustc_serialize;
 extern crate sbd;
 extern crate staticfile;
+extern crate time;
 extern crate toml;
 extern crate url;
 #[cfg(feature = "magick_rust")]
 extern crate magick_rust;
 
+pub mod animation;
+pub mod api;
+pub mod archive;
+pub mod browse;
 pub mod cam;
+pub mod catalog;
+pub mod change;
 pub mod error;
 pub mod heartbeat;
+pub mod images;
+pub mod iridium;
+pub mod jpeg;
+pub mod link;
+pub mod location;
+pub mod overlay;
+pub mod quality;
+mod query;
+mod scratch;
+pub mod select;
 pub mod server;
+pub mod station;
 pub mod sutron;
+pub mod thumbnail;
+pub mod timelapse;
+pub mod upload;
+pub mod video;
 pub mod watch;
 #[cfg(feature = "magick_rust")]
 pub mod magick;
 
-pub use error::Error;
+pub use error::{ConfigProblem, Error};
 
 /// Our custom result type.
 pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,407 @@
+//! Iridium link statistics.
+//!
+//! Every MO message carries the modem's MOMSN, a sequence number that goes up by one for each
+//! message the modem sends. A jump in the MOMSN between two received messages means messages were
+//! lost on the way. Together with session statuses and payload sizes, this tells us how healthy
+//! the satellite link is and roughly what it costs.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, RwLock};
+
+use chrono::{Datelike, NaiveDate};
+
+use handlebars_iron::Template;
+
+use iron::{Handler, status};
+use iron::headers::ContentType;
+use iron::prelude::*;
+use iron::mime::{Mime, SubLevel, TopLevel};
+
+use rustc_serialize::json::{Json, ToJson};
+
+use iridium::MessageInfo;
+
+/// MOMSN jumps larger than this are treated as modem resets, not lost messages.
+const MAX_MOMSN_GAP: u16 = 1000;
+
+/// Statistics for a single period, either a day or a month.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Usage {
+    /// The number of messages received.
+    pub messages: usize,
+    /// The number of messages lost, as counted by gaps in the MOMSN.
+    pub lost: usize,
+    /// The number of messages with a failed session status.
+    pub failures: usize,
+    /// The number of payload bytes received.
+    pub bytes: usize,
+    /// The number of bytes billed, with each message rounded up to the billing minimum.
+    pub billed_bytes: usize,
+}
+
+impl Usage {
+    fn add(&mut self, message: &MessageInfo, lost: usize, pricing: &Pricing) {
+        self.messages += 1;
+        self.lost += lost;
+        if message.session_status >= 10 {
+            self.failures += 1;
+        }
+        self.bytes += message.payload.len();
+        self.billed_bytes += if message.payload.len() < pricing.minimum_message_bytes {
+            pricing.minimum_message_bytes
+        } else {
+            message.payload.len()
+        };
+    }
+
+    /// Returns the percentage of sent messages that were lost.
+    pub fn loss(&self) -> f64 {
+        if self.messages + self.lost == 0 {
+            0.
+        } else {
+            100. * self.lost as f64 / (self.messages + self.lost) as f64
+        }
+    }
+}
+
+/// How airtime is billed, used to estimate monthly costs.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pricing {
+    /// The fixed monthly fee per modem.
+    pub monthly_fee: f64,
+    /// The number of bytes included in the monthly fee.
+    pub included_bytes: usize,
+    /// The price of each thousand bytes over the included bytes.
+    pub price_per_kilobyte: f64,
+    /// Each message is billed as at least this many bytes.
+    pub minimum_message_bytes: usize,
+}
+
+impl Default for Pricing {
+    fn default() -> Pricing {
+        Pricing {
+            monthly_fee: 15.,
+            included_bytes: 12000,
+            price_per_kilobyte: 1.5,
+            minimum_message_bytes: 10,
+        }
+    }
+}
+
+impl Pricing {
+    /// Estimates the cost of a month's usage.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use atlas::link::{Pricing, Usage};
+    /// let pricing = Pricing::default();
+    /// let usage = Usage { billed_bytes: 22000, ..Default::default() };
+    /// assert_eq!(30., pricing.cost(&usage));
+    /// ```
+    pub fn cost(&self, usage: &Usage) -> f64 {
+        let overage = if usage.billed_bytes > self.included_bytes {
+            usage.billed_bytes - self.included_bytes
+        } else {
+            0
+        };
+        self.monthly_fee + overage as f64 / 1000. * self.price_per_kilobyte
+    }
+}
+
+/// Link statistics for one modem.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LinkStats {
+    /// The IMEI of the modem.
+    pub imei: String,
+    /// The usage over all time.
+    pub total: Usage,
+    /// The usage on each day with messages.
+    pub days: BTreeMap<NaiveDate, Usage>,
+    /// The usage in each month with messages, keyed by year and month.
+    pub months: BTreeMap<(i32, u32), Usage>,
+}
+
+/// Computes link statistics for each modem, ordered by IMEI.
+///
+/// The messages must be sorted by time of session. Each modem's messages are then put in MOMSN
+/// order, so messages that arrive out of order or twice aren't counted as lost. Lost messages are
+/// counted on the day of the next message by MOMSN.
+///
+/// # Examples
+///
+/// ```
+/// # use atlas::iridium::MessageInfo;
+/// # use atlas::link::{self, Pricing};
+/// let messages = vec![MessageInfo::read("data/160714_000240.sbd").unwrap(),
+///                     MessageInfo::read("data/160714_000252.sbd").unwrap()];
+/// let stats = link::statistics(&messages, &Pricing::default());
+/// assert_eq!(2, stats[0].total.messages);
+/// assert_eq!(0, stats[0].total.lost);
+/// ```
+pub fn statistics(messages: &[MessageInfo], pricing: &Pricing) -> Vec<LinkStats> {
+    let mut modems = BTreeMap::new();
+    for message in messages {
+        modems.entry(message.imei.clone()).or_insert_with(Vec::new).push(message);
+    }
+    modems.into_iter()
+        .map(|(imei, messages)| {
+            let mut link = LinkStats {
+                imei: imei,
+                total: Usage::default(),
+                days: BTreeMap::new(),
+                months: BTreeMap::new(),
+            };
+            let mut previous = None;
+            for (sequence, message) in momsn_order(&messages) {
+                let lost = match previous {
+                    Some((run, position)) if run == sequence.0 && sequence.1 > position + 1 => {
+                        (sequence.1 - position - 1) as usize
+                    }
+                    _ => 0,
+                };
+                previous = Some(sequence);
+                let date = message.time_of_session.date().naive_utc();
+                link.total.add(message, lost, pricing);
+                link.days.entry(date).or_insert_with(Usage::default).add(message, lost, pricing);
+                link.months
+                    .entry((date.year(), date.month()))
+                    .or_insert_with(Usage::default)
+                    .add(message, lost, pricing);
+            }
+            link
+        })
+        .collect()
+}
+
+/// Sorts one modem's messages, given in time order, by MOMSN.
+///
+/// The MOMSN wraps around at 65535, so each message gets a position counted from the previous
+/// message instead. A jump larger than `MAX_MOMSN_GAP` either way starts a new run, because the
+/// modem was reset. Messages are sorted by run and then by position.
+fn momsn_order<'a>(messages: &[&'a MessageInfo]) -> Vec<((usize, i64), &'a MessageInfo)> {
+    let mut ordered = Vec::with_capacity(messages.len());
+    let mut run = 0;
+    let mut position = 0;
+    let mut previous: Option<u16> = None;
+    for &message in messages {
+        if let Some(momsn) = previous {
+            let step = message.momsn.wrapping_sub(momsn) as i16 as i64;
+            if step.abs() > MAX_MOMSN_GAP as i64 {
+                run += 1;
+                position = 0;
+            } else {
+                position += step;
+            }
+        }
+        previous = Some(message.momsn);
+        ordered.push(((run, position), message));
+    }
+    ordered.sort_by_key(|&(sequence, _)| sequence);
+    ordered
+}
+
+/// Serves the link statistics page.
+#[derive(Debug)]
+pub struct LinkHandler {
+    messages: Arc<RwLock<Vec<MessageInfo>>>,
+    pricing: Pricing,
+}
+
+impl LinkHandler {
+    /// Creates a new handler for the given message index.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use std::sync::{Arc, RwLock};
+    /// # use atlas::link::{LinkHandler, Pricing};
+    /// let handler = LinkHandler::new(Arc::new(RwLock::new(Vec::new())), Pricing::default());
+    /// ```
+    pub fn new(messages: Arc<RwLock<Vec<MessageInfo>>>, pricing: Pricing) -> LinkHandler {
+        LinkHandler {
+            messages: messages,
+            pricing: pricing,
+        }
+    }
+}
+
+fn usage_json(usage: &Usage) -> BTreeMap<String, Json> {
+    let mut map = BTreeMap::new();
+    map.insert("messages".to_string(), usage.messages.to_json());
+    map.insert("lost".to_string(), usage.lost.to_json());
+    map.insert("loss".to_string(), format!("{:.1}", usage.loss()).to_json());
+    map.insert("failures".to_string(), usage.failures.to_json());
+    map.insert("bytes".to_string(), usage.bytes.to_json());
+    map.insert("billed_bytes".to_string(), usage.billed_bytes.to_json());
+    map
+}
+
+/// Returns the number of calendar days from the first to the last message, inclusive.
+fn calendar_days(link: &LinkStats) -> i64 {
+    match (link.days.keys().next(), link.days.keys().next_back()) {
+        (Some(&first), Some(&last)) => (last - first).num_days() + 1,
+        _ => 0,
+    }
+}
+
+impl Handler for LinkHandler {
+    fn handle(&self, _: &mut Request) -> IronResult<Response> {
+        let stats = statistics(&self.messages.read().unwrap(), &self.pricing);
+        let modems = stats.iter()
+            .map(|link| {
+                let mut modem = usage_json(&link.total);
+                modem.insert("imei".to_string(), link.imei.to_json());
+                let days = calendar_days(link);
+                modem.insert("days".to_string(), days.to_json());
+                modem.insert("messages_per_day".to_string(),
+                             format!("{:.1}", link.total.messages as f64 / days as f64).to_json());
+                let months = link.months
+                    .iter()
+                    .rev()
+                    .map(|(&(year, month), usage)| {
+                        let mut map = usage_json(usage);
+                        map.insert("month".to_string(),
+                                   format!("{}-{:02}", year, month).to_json());
+                        map.insert("cost".to_string(),
+                                   format!("{:.2}", self.pricing.cost(usage)).to_json());
+                        map
+                    })
+                    .collect::<Vec<_>>();
+                modem.insert("months".to_string(), months.to_json());
+                modem
+            })
+            .collect::<Vec<_>>();
+        let mut data = BTreeMap::<String, Json>::new();
+        data.insert("modems".to_string(), modems.to_json());
+        let mut response = Response::new();
+        response.set_mut(Template::new("link", data)).set_mut(status::Ok);
+        Ok(response)
+    }
+}
+
+/// Serves daily link statistics for every modem as CSV.
+#[derive(Debug)]
+pub struct LinkCsvHandler {
+    messages: Arc<RwLock<Vec<MessageInfo>>>,
+    pricing: Pricing,
+}
+
+impl LinkCsvHandler {
+    /// Creates a new handler for the given message index.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use std::sync::{Arc, RwLock};
+    /// # use atlas::link::{LinkCsvHandler, Pricing};
+    /// let handler = LinkCsvHandler::new(Arc::new(RwLock::new(Vec::new())), Pricing::default());
+    /// ```
+    pub fn new(messages: Arc<RwLock<Vec<MessageInfo>>>, pricing: Pricing) -> LinkCsvHandler {
+        LinkCsvHandler {
+            messages: messages,
+            pricing: pricing,
+        }
+    }
+}
+
+impl Handler for LinkCsvHandler {
+    fn handle(&self, _: &mut Request) -> IronResult<Response> {
+        let mut data = String::new();
+        writeln!(&mut data, "Date,IMEI,Messages,Lost,Failures,Bytes,Billed bytes").unwrap();
+        for link in statistics(&self.messages.read().unwrap(), &self.pricing) {
+            for (date, usage) in &link.days {
+                writeln!(&mut data,
+                         "{},{},{},{},{},{},{}",
+                         date,
+                         link.imei,
+                         usage.messages,
+                         usage.lost,
+                         usage.failures,
+                         usage.bytes,
+                         usage.billed_bytes)
+                    .unwrap();
+            }
+        }
+        let mut response = Response::with((status::Ok, data));
+        response.headers
+            .set(ContentType(Mime(TopLevel::Text, SubLevel::Ext("csv".to_string()), vec![])));
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::Duration;
+
+    use iridium::MessageInfo;
+
+    fn message(path: &str, momsn: u16, status: u8) -> MessageInfo {
+        let mut message = MessageInfo::read(path).unwrap();
+        message.momsn = momsn;
+        message.session_status = status;
+        message
+    }
+
+    #[test]
+    fn lost_messages() {
+        let messages = vec![message("data/160714_000240.sbd", 65534, 0),
+                            message("data/160714_000240.sbd", 65534, 0),
+                            message("data/160714_000240.sbd", 1, 0),
+                            message("data/160714_000252.sbd", 2, 13),
+                            message("data/160714_000252.sbd", 40000, 0)];
+        let stats = statistics(&messages, &Pricing::default());
+        assert_eq!(1, stats.len());
+        let total = stats[0].total;
+        assert_eq!(5, total.messages);
+        assert_eq!(2, total.lost);
+        assert_eq!(1, total.failures);
+        assert_eq!(1, stats[0].days.len());
+        assert_eq!(1, stats[0].months.len());
+        assert!((total.loss() - 100. * 2. / 7.).abs() < 1e-9);
+    }
+
+    #[test]
+    fn reordered_messages() {
+        let messages = vec![message("data/160714_000240.sbd", 10, 0),
+                            message("data/160714_000240.sbd", 12, 0),
+                            message("data/160714_000240.sbd", 11, 0),
+                            message("data/160714_000252.sbd", 12, 0),
+                            message("data/160714_000252.sbd", 15, 0)];
+        let stats = statistics(&messages, &Pricing::default());
+        assert_eq!(5, stats[0].total.messages);
+        assert_eq!(2, stats[0].total.lost);
+    }
+
+    #[test]
+    fn modem_reset() {
+        let messages = vec![message("data/160714_000240.sbd", 5000, 0),
+                            message("data/160714_000240.sbd", 3, 0),
+                            message("data/160714_000252.sbd", 5, 0)];
+        let stats = statistics(&messages, &Pricing::default());
+        assert_eq!(1, stats[0].total.lost);
+    }
+
+    #[test]
+    fn days_between_messages() {
+        let mut first = message("data/160714_000240.sbd", 1, 0);
+        let last = message("data/160714_000252.sbd", 2, 0);
+        first.time_of_session = first.time_of_session - Duration::days(9);
+        let stats = statistics(&[first, last], &Pricing::default());
+        assert_eq!(2, stats[0].days.len());
+        assert_eq!(10, calendar_days(&stats[0]));
+    }
+
+    #[test]
+    fn billed_bytes() {
+        let messages = vec![message("data/160714_000252.sbd", 1, 0)];
+        let pricing = Pricing { minimum_message_bytes: 50, ..Default::default() };
+        let stats = statistics(&messages, &pricing);
+        assert_eq!(12, stats[0].total.bytes);
+        assert_eq!(50, stats[0].total.billed_bytes);
+        assert_eq!(15., pricing.cost(&stats[0].total));
+    }
+}
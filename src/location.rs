@@ -0,0 +1,304 @@
+//! Iridium-reported locations.
+//!
+//! The Iridium gateway estimates where each modem is when it sends a message, and attaches that
+//! estimate to the message along with a CEP radius. The estimates are coarse, but over many
+//! messages they're good enough to notice if the station tower has moved or tilted, and to see
+//! where any portable units have gone. Locations are exported as GeoJSON or KML so they can be
+//! opened in any mapping tool.
+//!
+//! Heartbeats are built from messages that don't keep the location element, so a heartbeat's
+//! locations are looked up in the message index when it is served.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, RwLock};
+
+use chrono::{DateTime, UTC};
+
+use iron::{Handler, status};
+use iron::headers::AccessControlAllowOrigin;
+use iron::prelude::*;
+use iron::mime::Mime;
+
+use rustc_serialize::json::{Json, ToJson};
+
+use iridium::{MessageInfo, MoLocation};
+use query;
+
+/// Criteria for selecting located messages.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Filter {
+    /// Only messages from this modem.
+    pub imei: Option<String>,
+    /// Only messages sent at or after this time.
+    pub start: Option<DateTime<UTC>>,
+    /// Only messages sent before this time.
+    pub end: Option<DateTime<UTC>>,
+    /// Only locations with a CEP radius of at most this many kilometers.
+    pub max_cep_radius: Option<u32>,
+}
+
+impl Filter {
+    /// Returns true if the message has a location that matches this filter.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use atlas::iridium::MessageInfo;
+    /// # use atlas::location::Filter;
+    /// let message = MessageInfo::read("data/160714_000252.sbd").unwrap();
+    /// assert!(!Filter::default().matches(&message));
+    /// ```
+    pub fn matches(&self, message: &MessageInfo) -> bool {
+        let location = match message.location {
+            Some(location) => location,
+            None => return false,
+        };
+        self.imei.as_ref().map(|i| &message.imei == i).unwrap_or(true) &&
+        self.start.as_ref().map(|s| &message.time_of_session >= s).unwrap_or(true) &&
+        self.end.as_ref().map(|e| &message.time_of_session < e).unwrap_or(true) &&
+        self.max_cep_radius.map(|r| location.cep_radius <= r).unwrap_or(true)
+    }
+}
+
+/// Finds the location of a message in the message index.
+///
+/// Heartbeats only keep the header fields of their messages, so this looks the message up by
+/// IMEI, MOMSN, and time of session. The messages must be sorted by time of session.
+///
+/// # Examples
+///
+/// ```
+/// # use atlas::iridium::MessageInfo;
+/// # use atlas::location;
+/// let message = MessageInfo::read("data/160714_000252.sbd").unwrap();
+/// let messages = vec![message.clone()];
+/// assert_eq!(None,
+///            location::find(&messages, &message.imei, message.momsn, &message.time_of_session));
+/// ```
+pub fn find(messages: &[MessageInfo],
+            imei: &str,
+            momsn: u16,
+            time_of_session: &DateTime<UTC>)
+            -> Option<MoLocation> {
+    let start = match messages.binary_search_by(|m| m.time_of_session.cmp(time_of_session)) {
+        Ok(i) | Err(i) => i,
+    };
+    let start = messages[..start]
+        .iter()
+        .rposition(|m| &m.time_of_session != time_of_session)
+        .map(|i| i + 1)
+        .unwrap_or(0);
+    messages[start..]
+        .iter()
+        .take_while(|m| &m.time_of_session == time_of_session)
+        .find(|m| m.imei == imei && m.momsn == momsn)
+        .and_then(|m| m.location)
+}
+
+/// Returns the located messages as a GeoJSON feature collection.
+///
+/// Each message is a point feature, with the IMEI, MOMSN, time of session, and CEP radius (in
+/// kilometers) as properties.
+///
+/// # Examples
+///
+/// ```
+/// # use atlas::location;
+/// let json = location::geojson(&[]);
+/// assert_eq!("FeatureCollection", json["type"].as_string().unwrap());
+/// ```
+pub fn geojson(messages: &[&MessageInfo]) -> Json {
+    let features = messages.iter()
+        .filter_map(|message| {
+            message.location.map(|location| {
+                let mut geometry = BTreeMap::new();
+                geometry.insert("type".to_string(), "Point".to_json());
+                geometry.insert("coordinates".to_string(),
+                                vec![location.longitude, location.latitude].to_json());
+                let mut properties = BTreeMap::new();
+                properties.insert("imei".to_string(), message.imei.to_json());
+                properties.insert("momsn".to_string(), message.momsn.to_json());
+                properties.insert("time_of_session".to_string(),
+                                  message.time_of_session.to_rfc3339().to_json());
+                properties.insert("cep_radius".to_string(), location.cep_radius.to_json());
+                let mut feature = BTreeMap::new();
+                feature.insert("type".to_string(), "Feature".to_json());
+                feature.insert("geometry".to_string(), Json::Object(geometry));
+                feature.insert("properties".to_string(), Json::Object(properties));
+                Json::Object(feature)
+            })
+        })
+        .collect();
+    let mut map = BTreeMap::new();
+    map.insert("type".to_string(), "FeatureCollection".to_json());
+    map.insert("features".to_string(), Json::Array(features));
+    Json::Object(map)
+}
+
+/// Returns the located messages as a KML document.
+///
+/// Each message is a timestamped placemark, so the track can be played back in Google Earth.
+///
+/// # Examples
+///
+/// ```
+/// # use atlas::location;
+/// let kml = location::kml(&[]);
+/// assert!(kml.starts_with("<?xml"));
+/// ```
+pub fn kml(messages: &[&MessageInfo]) -> String {
+    let mut kml = String::new();
+    writeln!(&mut kml, r#"<?xml version="1.0" encoding="UTF-8"?>"#).unwrap();
+    writeln!(&mut kml, r#"<kml xmlns="http://www.opengis.net/kml/2.2">"#).unwrap();
+    writeln!(&mut kml, "<Document>").unwrap();
+    writeln!(&mut kml, "<name>ATLAS Iridium locations</name>").unwrap();
+    for message in messages {
+        if let Some(location) = message.location {
+            writeln!(&mut kml,
+                     "<Placemark><name>{} #{}</name><description>CEP radius {} km</description>\
+                      <TimeStamp><when>{}</when></TimeStamp>\
+                      <Point><coordinates>{},{},0</coordinates></Point></Placemark>",
+                     escape(&message.imei),
+                     message.momsn,
+                     location.cep_radius,
+                     message.time_of_session.to_rfc3339(),
+                     location.longitude,
+                     location.latitude)
+                .unwrap();
+        }
+    }
+    writeln!(&mut kml, "</Document>").unwrap();
+    writeln!(&mut kml, "</kml>").unwrap();
+    kml
+}
+
+/// Escapes the characters that can't appear as-is in XML text or attribute values.
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Serves the Iridium-reported locations as GeoJSON or KML.
+#[derive(Debug)]
+pub struct LocationsHandler {
+    messages: Arc<RwLock<Vec<MessageInfo>>>,
+}
+
+impl LocationsHandler {
+    /// Creates a new handler for the given message index.
+    ///
+    /// Locations can be filtered with the `imei`, `start`, `end`, and `max_cep` (in kilometers)
+    /// query parameters. Set `format` to `kml` to get KML instead of GeoJSON.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use std::sync::{Arc, RwLock};
+    /// # use atlas::location::LocationsHandler;
+    /// let handler = LocationsHandler::new(Arc::new(RwLock::new(Vec::new())));
+    /// ```
+    pub fn new(messages: Arc<RwLock<Vec<MessageInfo>>>) -> LocationsHandler {
+        LocationsHandler { messages: messages }
+    }
+}
+
+impl Handler for LocationsHandler {
+    fn handle(&self, request: &mut Request) -> IronResult<Response> {
+        let params = query::params(request);
+        let mut filter = Filter::default();
+        filter.imei = params.get("imei").cloned();
+        if let Some(s) = params.get("start") {
+            filter.start = Some(iexpect!(query::parse_bound(s, false),
+                                         (status::BadRequest, "Invalid start.")));
+        }
+        if let Some(s) = params.get("end") {
+            filter.end = Some(iexpect!(query::parse_bound(s, true),
+                                       (status::BadRequest, "Invalid end.")));
+        }
+        if let Some(s) = params.get("max_cep") {
+            filter.max_cep_radius = Some(itry!(s.parse::<u32>(), status::BadRequest));
+        }
+
+        let messages = self.messages.read().unwrap();
+        let matching = messages.iter().filter(|m| filter.matches(m)).collect::<Vec<_>>();
+        let (content_type, body) = match params.get("format").map(|s| s.as_str()) {
+            None | Some("geojson") => ("application/geo+json", geojson(&matching).to_string()),
+            Some("kml") => ("application/vnd.google-earth.kml+xml", kml(&matching)),
+            Some(_) => return Ok(Response::with((status::BadRequest, "Unknown format."))),
+        };
+        let content_type = content_type.parse::<Mime>().unwrap();
+        let mut response = Response::with((content_type, status::Ok, body));
+        response.headers.set(AccessControlAllowOrigin::Any);
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::{TimeZone, UTC};
+
+    use rustc_serialize::json::Json;
+
+    use iridium::{MessageInfo, MoLocation};
+
+    fn located(momsn: u16, second: u32, cep_radius: u32) -> MessageInfo {
+        let mut message = MessageInfo::read("data/160714_000252.sbd").unwrap();
+        message.momsn = momsn;
+        message.time_of_session = UTC.ymd(2016, 7, 14).and_hms(0, 2, second);
+        message.location = Some(MoLocation {
+            latitude: -69.5,
+            longitude: 49.25,
+            cep_radius: cep_radius,
+        });
+        message
+    }
+
+    #[test]
+    fn cep_filter() {
+        let messages = vec![located(1, 0, 2), located(2, 10, 10), located(3, 20, 200)];
+        let filter = Filter { max_cep_radius: Some(10), ..Default::default() };
+        let matching = messages.iter().filter(|m| filter.matches(m)).collect::<Vec<_>>();
+        assert_eq!(2, matching.len());
+        let json = geojson(&matching);
+        let features = json["features"].as_array().unwrap();
+        assert_eq!(2, features.len());
+        assert_eq!(vec![Json::F64(49.25), Json::F64(-69.5)],
+                   *features[0]["geometry"]["coordinates"].as_array().unwrap());
+        assert_eq!(10, features[1]["properties"]["cep_radius"].as_u64().unwrap());
+        let kml = kml(&matching);
+        assert_eq!(2, kml.matches("<Placemark>").count());
+        assert!(kml.contains("<coordinates>49.25,-69.5,0</coordinates>"));
+    }
+
+    #[test]
+    fn find_by_header() {
+        let messages = vec![located(1, 0, 2), located(2, 10, 5), located(3, 10, 7)];
+        let time_of_session = UTC.ymd(2016, 7, 14).and_hms(0, 2, 10);
+        assert_eq!(7, find(&messages, "300234063909200", 3, &time_of_session).unwrap().cep_radius);
+        assert_eq!(5, find(&messages, "300234063909200", 2, &time_of_session).unwrap().cep_radius);
+        assert_eq!(None, find(&messages, "300234063909200", 1, &time_of_session));
+        assert_eq!(None, find(&messages, "300234063909201", 2, &time_of_session));
+    }
+
+    #[test]
+    fn kml_escapes_text() {
+        let mut message = located(1, 0, 2);
+        message.imei = "<a&b>".to_string();
+        let kml = kml(&[&message]);
+        assert!(kml.contains("<name>&lt;a&amp;b&gt; #1</name>"));
+        assert_eq!("&quot;&apos;", escape("\"'"));
+    }
+}
@@ -4,15 +4,12 @@ extern crate chrono;
 extern crate env_logger;
 extern crate rustc_serialize;
 
-#[cfg(feature = "magick_rust")]
 use std::io::Write;
 
-#[cfg(feature = "magick_rust")]
+use atlas::animation::{self, Animator, GifConfig};
 use atlas::cam::Camera;
 use atlas::server::Server;
 use docopt::Docopt;
-#[cfg(feature = "magick_rust")]
-use atlas::magick::{GifConfig, GifMaker};
 
 const USAGE: &'static str =
     "
@@ -20,7 +17,8 @@ ATLAS command-line utility.
 
 Usage:
     atlas serve <config-file>
-    atlas gif <img-dir> [--gif-days=<n>] [--gif-delay=<n>] [--gif-width=<n>] [--gif-height=<n>]
+    atlas check-config <config-file>
+    atlas gif <img-dir> [options]
     atlas (-h | --help)
     atlas --version
 
@@ -31,11 +29,13 @@ Options:
      --gif-delay=<n>        The number of milliseconds between gif frames [default: 500].
      --gif-width=<n>        The width of the gif [default: 256].
      --gif-height=<n>       The height of the gif [default: 192].
+     --gif-encoder=<name>   The gif encoder, "gif" (pure Rust) or "magick" (ImageMagick).
 ";
 
 #[derive(Debug, RustcDecodable)]
 struct Args {
     cmd_serve: bool,
+    cmd_check_config: bool,
     cmd_gif: bool,
     arg_img_dir: String,
     arg_config_file: String,
@@ -43,6 +43,7 @@ struct Args {
     flag_gif_delay: i64,
     flag_gif_width: u64,
     flag_gif_height: u64,
+    flag_gif_encoder: Option<String>,
 }
 
 fn main() {
@@ -55,31 +56,64 @@ fn main() {
 
     if args.cmd_serve {
         serve(args);
+    } else if args.cmd_check_config {
+        check_config(args);
     } else if args.cmd_gif {
         gif(args);
     }
 }
 
-#[cfg(feature = "magick_rust")]
 fn gif(args: Args) {
     let config = GifConfig {
         width: args.flag_gif_width,
         height: args.flag_gif_height,
         delay: chrono::Duration::milliseconds(args.flag_gif_delay),
     };
-    let maker = GifMaker::new(Camera::new("HEL_ATLAS", args.arg_img_dir).unwrap(), config);
-    let gif = maker.since(&(chrono::UTC::now() - chrono::Duration::days(args.flag_gif_days)))
+    let encoder = match args.flag_gif_encoder {
+        Some(ref name) => animation::encoder(name).unwrap(),
+        None => animation::default_encoder(),
+    };
+    let animator = Animator::new(Camera::new("HEL_ATLAS", args.arg_img_dir).unwrap(),
+                                 config,
+                                 encoder);
+    let gif = animator.since(&(chrono::UTC::now() - chrono::Duration::days(args.flag_gif_days)))
         .unwrap();
     std::io::stdout().write(&gif).unwrap();
 }
 
-#[cfg(not(feature = "magick_rust"))]
-fn gif(_: Args) {
-    println!("ERROR: atlas not built with ImageMagick, cannot create gif");
-    std::process::exit(1);
+fn check_config(args: Args) {
+    if report_problems(&args.arg_config_file) {
+        std::process::exit(1);
+    }
+    println!("{}: ok", args.arg_config_file);
+}
+
+/// Prints any problems with a configuration file, and returns true if there were some.
+///
+/// A file that can't be read is reported as a problem too.
+fn report_problems(config_file: &str) -> bool {
+    let mut stderr = std::io::stderr();
+    let problems = match Server::check_config(config_file) {
+        Ok(problems) => problems,
+        Err(err) => {
+            writeln!(stderr, "{}: {}", config_file, err).unwrap();
+            return true;
+        }
+    };
+    for problem in &problems {
+        match problem.line {
+            Some(line) => writeln!(stderr, "{}:{}: {}", config_file, line, problem.message),
+            None => writeln!(stderr, "{}: {}", config_file, problem.message),
+        }
+        .unwrap();
+    }
+    !problems.is_empty()
 }
 
 fn serve(args: Args) {
-    let mut server = Server::new(args.arg_config_file).unwrap();
+    if report_problems(&args.arg_config_file) {
+        std::process::exit(1);
+    }
+    let server = Server::new(args.arg_config_file).unwrap();
     server.serve().unwrap().unwrap();
 }
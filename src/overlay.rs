@@ -0,0 +1,458 @@
+//! Text overlays on animation frames.
+//!
+//! An overlay writes a line of text, e.g. the camera name and the frame's datetime, into a corner
+//! of each frame so that an animation can be understood on its own. The text is a template with
+//! these placeholders:
+//!
+//! - `{name}`: the camera name.
+//! - `{datetime}`: the frame's datetime, formatted with the overlay's datetime format.
+//! - `{temperature}`: the external temperature from the latest heartbeat at or before the frame.
+
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+
+use chrono::{DateTime, UTC};
+
+use image::{self, ImageFormat, Rgb, RgbImage};
+
+use rusttype::{Font, FontCollection, Scale, point};
+
+use {Error, Result};
+use cam::Camera;
+use heartbeat::HeartbeatV1;
+
+const MARGIN: u32 = 4;
+
+/// The corner of the frame where the text is drawn.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Position {
+    /// The top left corner.
+    TopLeft,
+    /// The top right corner.
+    TopRight,
+    /// The bottom left corner.
+    BottomLeft,
+    /// The bottom right corner.
+    BottomRight,
+}
+
+impl FromStr for Position {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Position> {
+        match s {
+            "top-left" => Ok(Position::TopLeft),
+            "top-right" => Ok(Position::TopRight),
+            "bottom-left" => Ok(Position::BottomLeft),
+            "bottom-right" => Ok(Position::BottomRight),
+            _ => Err(Error::InvalidOverlay(format!("unknown position: {}", s))),
+        }
+    }
+}
+
+/// Draws text onto frames.
+pub struct Overlay {
+    font: Font<'static>,
+    size: f32,
+    position: Position,
+    text: String,
+    datetime_format: String,
+    heartbeats: Option<Arc<RwLock<Vec<HeartbeatV1>>>>,
+}
+
+impl fmt::Debug for Overlay {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Overlay")
+            .field("size", &self.size)
+            .field("position", &self.position)
+            .field("text", &self.text)
+            .field("datetime_format", &self.datetime_format)
+            .finish()
+    }
+}
+
+impl Overlay {
+    /// Creates a new overlay that uses the TrueType font at the given path.
+    ///
+    /// By default the overlay writes `"{name} {datetime}"` in 16 pixel text in the bottom left
+    /// corner, with datetimes formatted like `2016-07-25 12:15 UTC`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use atlas::overlay::Overlay;
+    /// assert!(Overlay::from_path("data/config.toml").is_err());
+    /// ```
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Overlay> {
+        let mut bytes = Vec::new();
+        try!(try!(File::open(path)).read_to_end(&mut bytes));
+        let font = try!(FontCollection::from_bytes(bytes)
+            .into_font()
+            .ok_or(Error::InvalidOverlay("could not read font".to_string())));
+        Ok(Overlay {
+            font: font,
+            size: 16.,
+            position: Position::BottomLeft,
+            text: "{name} {datetime}".to_string(),
+            datetime_format: "%Y-%m-%d %H:%M UTC".to_string(),
+            heartbeats: None,
+        })
+    }
+
+    /// Sets the text height, in pixels.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use atlas::overlay::Overlay;
+    /// let overlay = Overlay::from_path("/usr/share/fonts/dejavu/DejaVuSansMono.ttf")
+    ///     .unwrap()
+    ///     .size(24.);
+    /// ```
+    pub fn size(mut self, size: f32) -> Overlay {
+        self.size = size;
+        self
+    }
+
+    /// Sets the corner where the text is drawn.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use atlas::overlay::{Overlay, Position};
+    /// let overlay = Overlay::from_path("/usr/share/fonts/dejavu/DejaVuSansMono.ttf")
+    ///     .unwrap()
+    ///     .position(Position::TopRight);
+    /// ```
+    pub fn position(mut self, position: Position) -> Overlay {
+        self.position = position;
+        self
+    }
+
+    /// Sets the text template.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use atlas::overlay::Overlay;
+    /// let overlay = Overlay::from_path("/usr/share/fonts/dejavu/DejaVuSansMono.ttf")
+    ///     .unwrap()
+    ///     .text("{name}");
+    /// ```
+    pub fn text<S: Into<String>>(mut self, text: S) -> Overlay {
+        self.text = text.into();
+        self
+    }
+
+    /// Sets the `strftime`-style format of `{datetime}`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use atlas::overlay::Overlay;
+    /// let overlay = Overlay::from_path("/usr/share/fonts/dejavu/DejaVuSansMono.ttf")
+    ///     .unwrap()
+    ///     .datetime_format("%d %b %Y");
+    /// ```
+    pub fn datetime_format<S: Into<String>>(mut self, datetime_format: S) -> Overlay {
+        self.datetime_format = datetime_format.into();
+        self
+    }
+
+    /// Sets the heartbeats used to fill in `{temperature}`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use std::sync::{Arc, RwLock};
+    /// # use atlas::overlay::Overlay;
+    /// let overlay = Overlay::from_path("/usr/share/fonts/dejavu/DejaVuSansMono.ttf")
+    ///     .unwrap()
+    ///     .heartbeats(Arc::new(RwLock::new(Vec::new())));
+    /// ```
+    pub fn heartbeats(mut self, heartbeats: Arc<RwLock<Vec<HeartbeatV1>>>) -> Overlay {
+        self.heartbeats = Some(heartbeats);
+        self
+    }
+
+    /// Returns the overlay text for a frame taken by `camera` at `datetime`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # extern crate chrono;
+    /// # extern crate atlas;
+    /// # use chrono::{TimeZone, UTC};
+    /// # use atlas::cam::Camera;
+    /// # use atlas::overlay::Overlay;
+    /// # fn main() {
+    /// let overlay = Overlay::from_path("/usr/share/fonts/dejavu/DejaVuSansMono.ttf")
+    ///     .unwrap();
+    /// let camera = Camera::new("ATLAS_CAM", "data").unwrap();
+    /// assert_eq!("ATLAS_CAM 2016-07-25 12:15 UTC",
+    ///            overlay.render_text(&camera, &UTC.ymd(2016, 7, 25).and_hms(12, 15, 0)));
+    /// # }
+    /// ```
+    pub fn render_text(&self, camera: &Camera, datetime: &DateTime<UTC>) -> String {
+        let temperature = self.heartbeats
+            .as_ref()
+            .and_then(|heartbeats| {
+                heartbeats.read()
+                    .unwrap()
+                    .iter()
+                    .rev()
+                    .find(|h| {
+                        h.messages
+                            .first()
+                            .map(|m| m.time_of_session() <= *datetime)
+                            .unwrap_or(false)
+                    })
+                    .map(|h| h.temperature_external.to_string())
+            })
+            .unwrap_or(String::new());
+        self.text
+            .replace("{name}", camera.name())
+            .replace("{datetime}",
+                     &datetime.format(&self.datetime_format).to_string())
+            .replace("{temperature}", &temperature)
+            .trim()
+            .to_string()
+    }
+
+    /// Draws text onto an image.
+    ///
+    /// The text is white on a dark box so that it can be read on any background.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # extern crate image;
+    /// # extern crate atlas;
+    /// # use image::{Rgb, RgbImage};
+    /// # use atlas::overlay::Overlay;
+    /// # fn main() {
+    /// let overlay = Overlay::from_path("/usr/share/fonts/dejavu/DejaVuSansMono.ttf")
+    ///     .unwrap();
+    /// let mut image = RgbImage::from_pixel(200, 100, Rgb { data: [128, 128, 128] });
+    /// overlay.draw(&mut image, "ATLAS");
+    /// # }
+    /// ```
+    pub fn draw(&self, image: &mut RgbImage, text: &str) {
+        let scale = Scale::uniform(self.size);
+        let ascent = self.font.v_metrics(scale).ascent;
+        let glyphs = self.font.layout(text, scale, point(0., ascent)).collect::<Vec<_>>();
+        let text_width = glyphs.iter()
+            .filter_map(|g| g.pixel_bounding_box())
+            .map(|b| b.max.x)
+            .max()
+            .unwrap_or(0)
+            .max(0) as u32;
+        let text_height = self.size.ceil() as u32;
+        let (width, height) = image.dimensions();
+        let box_width = (text_width + 2 * MARGIN).min(width);
+        let box_height = (text_height + 2 * MARGIN).min(height);
+        let (left, top) = match self.position {
+            Position::TopLeft => (0, 0),
+            Position::TopRight => (width - box_width, 0),
+            Position::BottomLeft => (0, height - box_height),
+            Position::BottomRight => (width - box_width, height - box_height),
+        };
+        for y in top..top + box_height {
+            for x in left..left + box_width {
+                let pixel = image.get_pixel_mut(x, y);
+                for channel in pixel.data.iter_mut() {
+                    *channel /= 3;
+                }
+            }
+        }
+        for glyph in glyphs {
+            if let Some(bounds) = glyph.pixel_bounding_box() {
+                glyph.draw(|gx, gy, coverage| {
+                    let x = left as i32 + MARGIN as i32 + bounds.min.x + gx as i32;
+                    let y = top as i32 + MARGIN as i32 + bounds.min.y + gy as i32;
+                    if x >= 0 && y >= 0 && (x as u32) < width && (y as u32) < height {
+                        let pixel = image.get_pixel_mut(x as u32, y as u32);
+                        blend(pixel, coverage);
+                    }
+                });
+            }
+        }
+    }
+
+    /// Writes a copy of each image, with the overlay, into `directory`.
+    ///
+    /// Returns the paths to the copies. Images that can't be read are skipped.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use std::env;
+    /// # use std::path::PathBuf;
+    /// # use atlas::cam::Camera;
+    /// # use atlas::overlay::Overlay;
+    /// let overlay = Overlay::from_path("/usr/share/fonts/dejavu/DejaVuSansMono.ttf")
+    ///     .unwrap();
+    /// let camera = Camera::new("ATLAS_CAM", "data").unwrap();
+    /// let paths = vec![PathBuf::from("data/ATLAS_CAM_20160725_121500.jpg")];
+    /// let overlaid = overlay.apply(&camera, &paths, &env::temp_dir()).unwrap();
+    /// assert_eq!(1, overlaid.len());
+    /// ```
+    pub fn apply(&self,
+                 camera: &Camera,
+                 paths: &[PathBuf],
+                 directory: &Path)
+                 -> Result<Vec<PathBuf>> {
+        let mut overlaid = Vec::new();
+        for (i, path) in paths.iter().enumerate() {
+            let datetime = try!(camera.datetime(path));
+            let mut image = match image::open(path) {
+                Ok(image) => image.to_rgb(),
+                Err(err) => {
+                    warn!("Skipping {} in overlay: {}", path.to_string_lossy(), err);
+                    continue;
+                }
+            };
+            self.draw(&mut image, &self.render_text(camera, &datetime));
+            let output = directory.join(format!("{:06}.png", i));
+            let mut file = try!(File::create(&output));
+            try!(image::ImageRgb8(image).save(&mut file, ImageFormat::PNG));
+            overlaid.push(output);
+        }
+        Ok(overlaid)
+    }
+}
+
+fn blend(pixel: &mut Rgb<u8>, coverage: f32) {
+    for channel in pixel.data.iter_mut() {
+        *channel = (*channel as f32 * (1. - coverage) + 255. * coverage) as u8;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::blend;
+
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+
+    use chrono::{TimeZone, UTC};
+
+    use image::{self, Rgb, RgbImage};
+
+    use cam::Camera;
+
+    const GRAY: [u8; 3] = [128, 128, 128];
+
+    /// Fonts that are often installed, since no font is bundled with the tests.
+    const FONTS: &'static [&'static str] = &["/usr/share/fonts/truetype/dejavu/DejaVuSansMono.ttf",
+                                             "/usr/share/fonts/dejavu/DejaVuSansMono.ttf",
+                                             "/Library/Fonts/Courier New.ttf",
+                                             "C:\\Windows\\Fonts\\cour.ttf"];
+
+    /// Returns an overlay with the first installed font, or `None` to skip the test.
+    fn overlay() -> Option<Overlay> {
+        let overlay = FONTS.iter().filter_map(|path| Overlay::from_path(path).ok()).next();
+        if overlay.is_none() {
+            println!("No font found, skipping the test");
+        }
+        overlay
+    }
+
+    /// Returns the number of pixels that differ between the images, inside and outside of the
+    /// rectangle with its top left corner at the origin.
+    fn changed(a: &RgbImage, b: &RgbImage, width: u32, height: u32) -> (usize, usize) {
+        let (mut inside, mut outside) = (0, 0);
+        for (x, y, pixel) in a.enumerate_pixels() {
+            if pixel != b.get_pixel(x, y) {
+                if x < width && y < height {
+                    inside += 1;
+                } else {
+                    outside += 1;
+                }
+            }
+        }
+        (inside, outside)
+    }
+
+    #[test]
+    fn render_text() {
+        let camera = Camera::new("ATLAS_CAM", "data").unwrap();
+        let overlay = match overlay() {
+            Some(overlay) => overlay,
+            None => return,
+        };
+        let overlay = overlay.text("{name} at {datetime} {temperature}")
+            .datetime_format("%H:%M");
+        assert_eq!("ATLAS_CAM at 12:15",
+                   overlay.render_text(&camera, &UTC.ymd(2016, 7, 25).and_hms(12, 15, 0)));
+    }
+
+    #[test]
+    fn draw() {
+        let original = RgbImage::from_pixel(300, 100, Rgb { data: GRAY });
+        let mut image = original.clone();
+        let overlay = match overlay() {
+            Some(overlay) => overlay,
+            None => return,
+        };
+        overlay.position(Position::TopLeft).draw(&mut image, "ATLAS");
+        let box_height = 16 + 2 * MARGIN;
+        let (inside, outside) = changed(&original, &image, 100, box_height);
+        assert!(inside > 0);
+        assert_eq!(0, outside);
+        assert_eq!(GRAY[0] / 3, image.get_pixel(0, 0).data[0]);
+        assert!(image.pixels().any(|p| p.data[0] > GRAY[0]));
+        assert_eq!(GRAY, image.get_pixel(0, box_height).data);
+        assert_eq!(GRAY, image.get_pixel(299, 99).data);
+    }
+
+    #[test]
+    fn apply() {
+        let overlay = match overlay() {
+            Some(overlay) => overlay,
+            None => return,
+        };
+        let camera = Camera::new("ATLAS_CAM", "data").unwrap();
+        let directory = env::temp_dir().join("atlas-overlay-apply");
+        fs::create_dir_all(&directory).unwrap();
+        let path = PathBuf::from("data/ATLAS_CAM_20160725_121500.jpg");
+        let overlaid = overlay.apply(&camera, &[path.clone()], &directory).unwrap();
+        assert_eq!(1, overlaid.len());
+        let original = image::open(&path).unwrap().to_rgb();
+        let image = image::open(&overlaid[0]).unwrap().to_rgb();
+        assert_eq!(original.dimensions(), image.dimensions());
+        let (width, height) = image.dimensions();
+        let box_height = 16 + 2 * MARGIN;
+        let (inside, outside) = changed(&flip(&original), &flip(&image), width, box_height);
+        assert!(inside > 0);
+        assert_eq!(0, outside);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    /// Flips an image upside down, so the bottom left box is at the origin.
+    fn flip(image: &RgbImage) -> RgbImage {
+        let (width, height) = image.dimensions();
+        RgbImage::from_fn(width, height, |x, y| *image.get_pixel(x, height - 1 - y))
+    }
+
+    #[test]
+    fn position() {
+        assert_eq!(Position::TopLeft, "top-left".parse().unwrap());
+        assert_eq!(Position::BottomRight, "bottom-right".parse().unwrap());
+        assert!("middle".parse::<Position>().is_err());
+    }
+
+    #[test]
+    fn blends() {
+        let mut pixel = Rgb { data: [0, 100, 255] };
+        blend(&mut pixel, 0.);
+        assert_eq!([0, 100, 255], pixel.data);
+        blend(&mut pixel, 1.);
+        assert_eq!([255, 255, 255], pixel.data);
+    }
+}
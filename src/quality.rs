@@ -0,0 +1,361 @@
+//! Image quality metrics and camera health.
+//!
+//! Remote cameras frost over, get snowed on, or get knocked to point at the wrong spot. Each
+//! cataloged image gets a few cheap quality metrics, and a camera whose recent images all have
+//! problems is flagged as degraded. Images taken at night are dark, so they don't count.
+
+use std::ascii::AsciiExt;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::{Arc, RwLock};
+
+use chrono::Duration;
+
+use image::{DynamicImage, FilterType, GenericImage};
+
+use iron::{Handler, status};
+use iron::headers::ContentType;
+use iron::prelude::*;
+use iron::mime::{Mime, SubLevel, TopLevel};
+
+use router::Router;
+
+use catalog::ImageInfo;
+
+/// Images are shrunk to this width before measuring, so metrics are comparable across cameras.
+const MEASURE_WIDTH: u32 = 512;
+
+/// Luma values at or above this count as saturated.
+const SATURATED: u8 = 250;
+
+/// How far from a day earlier an image can be taken and still tell us whether it's daytime.
+const DAYLIGHT_TOLERANCE_MINUTES: i64 = 90;
+
+/// Quality metrics for a single image.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quality {
+    /// The mean brightness, from 0 to 255.
+    pub brightness: f64,
+    /// The variance of the Laplacian of the image. Blurry or frosted images have low values.
+    pub sharpness: f64,
+    /// The fraction of pixels that are saturated, from 0 to 1.
+    pub saturated: f64,
+}
+
+impl Quality {
+    /// Measures an image.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate image;
+    /// # extern crate atlas;
+    /// # use atlas::quality::Quality;
+    /// # fn main() {
+    /// let image = image::open("data/ATLAS_CAM_20160725_121500.jpg").unwrap();
+    /// let quality = Quality::new(&image);
+    /// assert!(quality.brightness > 0.);
+    /// # }
+    /// ```
+    pub fn new(image: &DynamicImage) -> Quality {
+        let (width, height) = image.dimensions();
+        let luma = if width > MEASURE_WIDTH {
+            let height = (height as u64 * MEASURE_WIDTH as u64 / width as u64).max(1) as u32;
+            image.resize_exact(MEASURE_WIDTH, height, FilterType::Triangle).to_luma()
+        } else {
+            image.to_luma()
+        };
+        let (width, height) = luma.dimensions();
+        let pixels = luma.into_raw();
+        if pixels.is_empty() {
+            return Quality {
+                brightness: 0.,
+                sharpness: 0.,
+                saturated: 0.,
+            };
+        }
+        let brightness = pixels.iter().fold(0., |sum, &v| sum + v as f64) / pixels.len() as f64;
+        let saturated = pixels.iter().filter(|&&v| v >= SATURATED).count() as f64 /
+                        pixels.len() as f64;
+        Quality {
+            brightness: brightness,
+            sharpness: laplacian_variance(&pixels, width as usize, height as usize),
+            saturated: saturated,
+        }
+    }
+
+    /// Returns a description of each of this image's problems, if it has any.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use atlas::quality::{Quality, Thresholds};
+    /// let quality = Quality { brightness: 2., sharpness: 500., saturated: 0. };
+    /// assert_eq!(vec!["too dark"], quality.problems(&Thresholds::default()));
+    /// ```
+    pub fn problems(&self, thresholds: &Thresholds) -> Vec<&'static str> {
+        let mut problems = Vec::new();
+        if self.brightness < thresholds.min_brightness {
+            problems.push("too dark");
+        }
+        if self.sharpness < thresholds.min_sharpness {
+            problems.push("blurry");
+        }
+        if self.saturated > thresholds.max_saturated {
+            problems.push("saturated");
+        }
+        problems
+    }
+}
+
+/// The variance of the 4-neighbor Laplacian over the interior of a grayscale image.
+fn laplacian_variance(pixels: &[u8], width: usize, height: usize) -> f64 {
+    if width < 3 || height < 3 {
+        return 0.;
+    }
+    let mut sum = 0.;
+    let mut sum_of_squares = 0.;
+    for y in 1..height - 1 {
+        for x in 1..width - 1 {
+            let i = y * width + x;
+            let laplacian = pixels[i - width] as f64 + pixels[i + width] as f64 +
+                            pixels[i - 1] as f64 +
+                            pixels[i + 1] as f64 - 4. * pixels[i] as f64;
+            sum += laplacian;
+            sum_of_squares += laplacian * laplacian;
+        }
+    }
+    let n = ((width - 2) * (height - 2)) as f64;
+    let mean = sum / n;
+    sum_of_squares / n - mean * mean
+}
+
+/// The limits past which an image has a problem.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Thresholds {
+    /// Images darker than this are too dark.
+    pub min_brightness: f64,
+    /// Images with a lower sharpness are blurry.
+    pub min_sharpness: f64,
+    /// Images with more saturated pixels than this fraction are saturated.
+    pub max_saturated: f64,
+    /// A camera is degraded if this many of its most recent images all have problems.
+    pub window: usize,
+}
+
+impl Default for Thresholds {
+    fn default() -> Thresholds {
+        Thresholds {
+            min_brightness: 15.,
+            min_sharpness: 20.,
+            max_saturated: 0.5,
+            window: 3,
+        }
+    }
+}
+
+/// Returns the problems with an image, including whether it is corrupted.
+///
+/// Images that couldn't be measured are only a problem if they are corrupted.
+pub fn problems(image: &ImageInfo, thresholds: &Thresholds) -> Vec<&'static str> {
+    let mut problems = Vec::new();
+    if image.corrupted {
+        problems.push("truncated");
+    }
+    if let Some(quality) = image.quality {
+        problems.extend(quality.problems(thresholds));
+    }
+    problems
+}
+
+/// Returns the problems shared by a camera's most recent images, if they all have problems.
+///
+/// Night images are skipped, so a camera isn't degraded just because it's dark out. An empty
+/// vector means the camera is healthy. The images must be sorted by datetime.
+///
+/// # Examples
+///
+/// ```
+/// # use atlas::quality::{self, Thresholds};
+/// assert!(quality::degraded(&[], &Thresholds::default()).is_empty());
+/// ```
+pub fn degraded(images: &[ImageInfo], thresholds: &Thresholds) -> Vec<&'static str> {
+    if thresholds.window == 0 || images.len() < thresholds.window {
+        return Vec::new();
+    }
+    let mut all = Vec::new();
+    let mut count = 0;
+    for (i, image) in images.iter().enumerate().rev() {
+        if count == thresholds.window {
+            break;
+        }
+        if is_night(images, i, thresholds) {
+            continue;
+        }
+        count += 1;
+        let problems = problems(image, thresholds);
+        if problems.is_empty() {
+            return Vec::new();
+        }
+        for problem in problems {
+            if !all.contains(&problem) {
+                all.push(problem);
+            }
+        }
+    }
+    if count < thresholds.window {
+        return Vec::new();
+    }
+    all
+}
+
+/// Returns true if an image looks like it was taken at night.
+///
+/// The daylight hours at a polar station change all year, so we can't tell night from the
+/// datetime alone. Instead, a dark image is a night image unless the camera took a bright enough
+/// image at about the same time a day earlier.
+fn is_night(images: &[ImageInfo], i: usize, thresholds: &Thresholds) -> bool {
+    let bright = |image: &ImageInfo| {
+        image.quality.map(|q| q.brightness >= thresholds.min_brightness)
+    };
+    if bright(&images[i]) != Some(false) {
+        return false;
+    }
+    let tolerance = Duration::minutes(DAYLIGHT_TOLERANCE_MINUTES);
+    let target = images[i].datetime - Duration::days(1);
+    let earliest = target - tolerance;
+    let start = match images[..i].binary_search_by(|image| image.datetime.cmp(&earliest)) {
+        Ok(j) | Err(j) => j,
+    };
+    !images[start..i]
+        .iter()
+        .take_while(|image| image.datetime <= target + tolerance)
+        .any(|image| bright(image) == Some(true))
+}
+
+/// Iron handler that serves a camera's image quality metrics as CSV.
+///
+/// The camera name is taken from the `name` route parameter, and is case-insensitive. Images that
+/// couldn't be measured are left out.
+#[derive(Debug)]
+pub struct QualityCsvHandler {
+    catalogs: HashMap<String, Arc<RwLock<Vec<ImageInfo>>>>,
+}
+
+impl QualityCsvHandler {
+    /// Creates a new handler for catalogs keyed by camera name.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use std::collections::HashMap;
+    /// # use std::sync::{Arc, RwLock};
+    /// # use atlas::quality::QualityCsvHandler;
+    /// let mut catalogs = HashMap::new();
+    /// catalogs.insert("ATLAS_CAM".to_string(), Arc::new(RwLock::new(Vec::new())));
+    /// let handler = QualityCsvHandler::new(catalogs);
+    /// ```
+    pub fn new(catalogs: HashMap<String, Arc<RwLock<Vec<ImageInfo>>>>) -> QualityCsvHandler {
+        QualityCsvHandler {
+            catalogs: catalogs.into_iter()
+                .map(|(name, catalog)| (name.to_ascii_lowercase(), catalog))
+                .collect(),
+        }
+    }
+}
+
+impl Handler for QualityCsvHandler {
+    fn handle(&self, request: &mut Request) -> IronResult<Response> {
+        let name = iexpect!(request.extensions.get::<Router>().and_then(|p| p.find("name")))
+            .to_ascii_lowercase();
+        let catalog = iexpect!(self.catalogs.get(&name), status::NotFound);
+        let mut data = String::new();
+        writeln!(&mut data, "Datetime,Brightness,Sharpness,Saturated (%)").unwrap();
+        for image in catalog.read().unwrap().iter() {
+            if let Some(quality) = image.quality {
+                writeln!(&mut data,
+                         "{},{:.1},{:.1},{:.2}",
+                         image.datetime.to_rfc3339(),
+                         quality.brightness,
+                         quality.sharpness,
+                         quality.saturated * 100.)
+                    .unwrap();
+            }
+        }
+        let mut response = Response::with((status::Ok, data));
+        response.headers
+            .set(ContentType(Mime(TopLevel::Text, SubLevel::Ext("csv".to_string()), vec![])));
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::laplacian_variance;
+
+    use chrono::Duration;
+
+    use image::{self, DynamicImage, GrayImage, Luma};
+
+    use cam::Camera;
+    use catalog::ImageInfo;
+
+    #[test]
+    fn sharpness() {
+        assert_eq!(0., laplacian_variance(&[10; 25], 5, 5));
+        let image = image::open("data/HEL_Terminus_20160803_180000.jpg").unwrap();
+        let sharp = Quality::new(&image);
+        let blurry = Quality::new(&image.blur(8.));
+        assert!(blurry.sharpness < sharp.sharpness);
+    }
+
+    #[test]
+    fn saturated() {
+        let white = DynamicImage::ImageLuma8(GrayImage::from_pixel(8, 8, Luma { data: [255] }));
+        let quality = Quality::new(&white);
+        assert_eq!(255., quality.brightness);
+        assert_eq!(1., quality.saturated);
+        assert_eq!(vec!["blurry", "saturated"],
+                   quality.problems(&Thresholds::default()));
+    }
+
+    fn image(hours: i64, brightness: f64) -> ImageInfo {
+        let camera = Camera::new("ATLAS_CAM", "data").unwrap();
+        let mut image = ImageInfo::new(&camera, "data/ATLAS_CAM_20160725_121500.jpg").unwrap();
+        image.datetime = image.datetime + Duration::hours(hours);
+        image.quality = Some(Quality {
+            brightness: brightness,
+            sharpness: 100.,
+            saturated: 0.,
+        });
+        image
+    }
+
+    #[test]
+    fn degraded_window() {
+        let reference = image(0, 100.);
+        let dark = image(24, 1.);
+        let thresholds = Thresholds { window: 2, ..Default::default() };
+        assert!(degraded(&[reference.clone(), dark.clone()], &thresholds).is_empty());
+        assert_eq!(vec!["too dark"],
+                   degraded(&[reference.clone(), dark.clone(), dark.clone()], &thresholds));
+        let mut healthy = dark.clone();
+        healthy.quality = None;
+        assert!(degraded(&[reference.clone(), dark.clone(), healthy], &thresholds).is_empty());
+    }
+
+    #[test]
+    fn night_is_not_degraded() {
+        let thresholds = Thresholds::default();
+        let night = (0..48).map(|hours| image(hours, if hours % 24 < 12 { 1. } else { 100. }));
+        let mut images = night.collect::<Vec<_>>();
+        assert!(degraded(&images, &thresholds).is_empty());
+        images.truncate(36);
+        assert!(degraded(&images, &thresholds).is_empty());
+
+        let mut images = (0..24).map(|hours| image(hours, 100.)).collect::<Vec<_>>();
+        images.extend((24..30).map(|hours| image(hours, 1.)));
+        assert_eq!(vec!["too dark"], degraded(&images, &thresholds));
+    }
+}
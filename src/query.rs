@@ -0,0 +1,44 @@
+//! Helpers for reading query parameters from Iron requests.
+
+use std::collections::HashMap;
+
+use chrono::{self, DateTime, Duration, NaiveDate, TimeZone, UTC};
+
+use iron::Request;
+
+use url::form_urlencoded;
+
+/// Returns the query parameters of a request as a map.
+///
+/// Parameters with empty values are treated as if they weren't there.
+pub fn params(request: &Request) -> HashMap<String, String> {
+    match request.url.query {
+        Some(ref query) => {
+            form_urlencoded::parse(query.as_bytes())
+                .filter(|&(_, ref v)| !v.is_empty())
+                .map(|(k, v)| (k.into_owned(), v.into_owned()))
+                .collect()
+        }
+        None => HashMap::new(),
+    }
+}
+
+/// Parses a `%Y-%m-%d` date.
+pub fn parse_date(s: &str) -> chrono::ParseResult<NaiveDate> {
+    NaiveDate::parse_from_str(s, "%Y-%m-%d")
+}
+
+/// Parses a `%Y-%m-%d` date into the datetime at the start of that day.
+pub fn parse_day_start(s: &str) -> chrono::ParseResult<DateTime<UTC>> {
+    parse_date(s).map(|d| UTC.from_utc_date(&d).and_hms(0, 0, 0))
+}
+
+/// Parses a range bound, either a `%Y-%m-%d` date or an RFC 3339 datetime.
+///
+/// A date means the start of that day, or the end of it if `is_end` is true.
+pub fn parse_bound(s: &str, is_end: bool) -> Option<DateTime<UTC>> {
+    match parse_day_start(s) {
+        Ok(datetime) => Some(if is_end { datetime + Duration::days(1) } else { datetime }),
+        Err(_) => DateTime::parse_from_rfc3339(s).ok().map(|d| d.with_timezone(&UTC)),
+    }
+}
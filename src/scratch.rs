@@ -0,0 +1,104 @@
+//! Temporary working directories and files.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{ATOMIC_USIZE_INIT, AtomicUsize, Ordering};
+
+use chrono::{Timelike, UTC};
+
+use Result;
+
+static COUNTER: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// A uniquely-named directory in the system temporary directory, removed when dropped.
+///
+/// The name includes the process id, and the directory is only used if this call created it, so
+/// processes never share a scratch directory.
+#[derive(Debug)]
+pub struct ScratchDir {
+    path: PathBuf,
+}
+
+impl ScratchDir {
+    /// Creates a new scratch directory whose name starts with `prefix`.
+    pub fn new(prefix: &str) -> Result<ScratchDir> {
+        loop {
+            let path = env::temp_dir().join(format!("{}-{}-{}-{}",
+                                                    prefix,
+                                                    process::id(),
+                                                    UTC::now().timestamp(),
+                                                    COUNTER.fetch_add(1, Ordering::SeqCst)));
+            match fs::create_dir(&path) {
+                Ok(()) => return Ok(ScratchDir { path: path }),
+                Err(ref err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    /// Returns the path to this directory.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        if let Err(err) = fs::remove_dir_all(&self.path) {
+            warn!("Could not remove {}: {}", self.path.to_string_lossy(), err);
+        }
+    }
+}
+
+/// Returns a unique temporary path next to `path`, for writing a file before renaming it into
+/// place.
+///
+/// The name starts with a dot so cameras and caches skip it, and is unique so that concurrent
+/// writers of the same file never share a temporary file.
+pub fn temporary_path(path: &Path) -> PathBuf {
+    let now = UTC::now();
+    let file_name = path.file_name()
+        .map(|f| f.to_string_lossy().into_owned())
+        .unwrap_or_else(String::new);
+    path.with_file_name(format!(".{}.{}-{}-{}-{}.tmp",
+                                file_name,
+                                process::id(),
+                                now.timestamp(),
+                                now.nanosecond(),
+                                COUNTER.fetch_add(1, Ordering::SeqCst)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::path::Path;
+    use std::process;
+
+    #[test]
+    fn temporary_paths_are_unique() {
+        let path = Path::new("thumbnails/atlas_cam/320x240/ATLAS_CAM_20160725_121500.jpg");
+        let a = temporary_path(path);
+        let b = temporary_path(path);
+        assert!(a != b);
+        assert_eq!(path.parent(), a.parent());
+        assert!(a.file_name().unwrap().to_string_lossy().starts_with(".ATLAS_CAM_20160725"));
+    }
+
+    #[test]
+    fn scratch_dirs_are_unique() {
+        let a = ScratchDir::new("atlas-scratch").unwrap();
+        let b = ScratchDir::new("atlas-scratch").unwrap();
+        assert!(a.path() != b.path());
+        assert!(a.path().is_dir());
+        assert!(a.path()
+            .to_string_lossy()
+            .contains(&format!("atlas-scratch-{}-", process::id())));
+        let path = a.path().to_path_buf();
+        drop(a);
+        assert!(!path.exists());
+    }
+}
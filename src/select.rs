@@ -0,0 +1,331 @@
+//! Choose which camera images go into an animation.
+//!
+//! Including every image makes for poor animations: in the Greenland winter most frames are black,
+//! and a stalled camera produces runs of near-identical frames. A `Selection` filters frames by
+//! time of day, sun elevation, brightness, similarity to the previous frame, and corruption, and
+//! can cap the number of frames per day.
+
+use std::f64::consts::PI;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Datelike, NaiveDate, NaiveTime, Timelike, UTC};
+
+use image::{self, FilterType};
+
+use cam::Camera;
+use jpeg;
+
+/// The width and height of the downsampled images used for brightness and similarity.
+const SAMPLE_WIDTH: u32 = 32;
+const SAMPLE_HEIGHT: u32 = 24;
+
+/// A geographic location, in decimal degrees.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Location {
+    /// Latitude, positive north.
+    pub latitude: f64,
+    /// Longitude, positive east.
+    pub longitude: f64,
+}
+
+/// A set of frame selection policies.
+///
+/// The default selection keeps every frame.
+#[derive(Clone, Debug, Default)]
+pub struct Selection {
+    /// Only keep frames taken within this UTC time-of-day window.
+    ///
+    /// If the start is after the end, the window wraps around midnight.
+    pub window: Option<(NaiveTime, NaiveTime)>,
+    /// Only keep frames taken when the sun is at least this many degrees above the horizon at
+    /// the given location.
+    pub min_sun_elevation: Option<(f64, Location)>,
+    /// Only keep frames whose mean brightness, from 0 to 255, is at least this value.
+    pub min_brightness: Option<f64>,
+    /// Drop frames whose mean absolute difference from the previous kept frame, from 0 to 255,
+    /// is less than this value.
+    pub min_difference: Option<f64>,
+    /// Keep at most this many frames per UTC day, spread evenly through the day.
+    pub max_per_day: Option<usize>,
+    /// Drop frames that are truncated or otherwise corrupt.
+    pub skip_corrupt: bool,
+}
+
+impl Selection {
+    /// Selects frames from a camera's image paths.
+    ///
+    /// The paths should be sorted by datetime, as returned by `Camera::paths_since`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use atlas::cam::Camera;
+    /// # use atlas::select::Selection;
+    /// let camera = Camera::new("ATLAS_CAM", "data").unwrap();
+    /// let selection = Selection { max_per_day: Some(1), ..Default::default() };
+    /// let paths = selection.select(&camera, camera.paths().unwrap());
+    /// assert_eq!(1, paths.len());
+    /// ```
+    pub fn select(&self, camera: &Camera, paths: Vec<PathBuf>) -> Vec<PathBuf> {
+        let mut frames = Vec::new();
+        let mut previous: Option<Vec<u8>> = None;
+        for path in paths {
+            let datetime = match camera.datetime(&path) {
+                Ok(datetime) => datetime,
+                Err(_) => continue,
+            };
+            if !self.is_in_window(&datetime) || !self.is_sunlit(&datetime) {
+                continue;
+            }
+            if self.skip_corrupt && is_corrupt(&path) {
+                debug!("Skipping corrupt frame {}", path.to_string_lossy());
+                continue;
+            }
+            if self.min_brightness.is_some() || self.min_difference.is_some() {
+                let sample = match sample(&path) {
+                    Some(sample) => sample,
+                    None => {
+                        if self.skip_corrupt {
+                            continue;
+                        }
+                        frames.push((datetime, path));
+                        continue;
+                    }
+                };
+                if let Some(min_brightness) = self.min_brightness {
+                    if brightness(&sample) < min_brightness {
+                        continue;
+                    }
+                }
+                if let Some(min_difference) = self.min_difference {
+                    if let Some(ref previous) = previous {
+                        if difference(previous, &sample) < min_difference {
+                            continue;
+                        }
+                    }
+                }
+                previous = Some(sample);
+            }
+            frames.push((datetime, path));
+        }
+        match self.max_per_day {
+            Some(max) => thin(frames, max),
+            None => frames.into_iter().map(|(_, path)| path).collect(),
+        }
+    }
+
+    fn is_in_window(&self, datetime: &DateTime<UTC>) -> bool {
+        match self.window {
+            Some((start, end)) => {
+                let time = datetime.time();
+                if start <= end {
+                    start <= time && time < end
+                } else {
+                    start <= time || time < end
+                }
+            }
+            None => true,
+        }
+    }
+
+    fn is_sunlit(&self, datetime: &DateTime<UTC>) -> bool {
+        match self.min_sun_elevation {
+            Some((min, location)) => sun_elevation(datetime, location) >= min,
+            None => true,
+        }
+    }
+}
+
+/// Returns the approximate elevation of the sun above the horizon, in degrees.
+///
+/// Uses the NOAA general solar position equations, which are good to about a degree.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate chrono;
+/// # extern crate atlas;
+/// # use chrono::{TimeZone, UTC};
+/// # use atlas::select::{Location, sun_elevation};
+/// # fn main() {
+/// let helheim = Location { latitude: 66.35, longitude: -38.2 };
+/// let midnight = UTC.ymd(2016, 12, 21).and_hms(2, 30, 0);
+/// assert!(sun_elevation(&midnight, helheim) < 0.);
+/// # }
+/// ```
+pub fn sun_elevation(datetime: &DateTime<UTC>, location: Location) -> f64 {
+    let hour = datetime.hour() as f64 + datetime.minute() as f64 / 60. +
+               datetime.second() as f64 / 3600.;
+    let gamma = 2. * PI / 365. * (datetime.ordinal0() as f64 + (hour - 12.) / 24.);
+    let equation_of_time = 229.18 *
+                           (0.000075 + 0.001868 * gamma.cos() - 0.032077 * gamma.sin() -
+                            0.014615 * (2. * gamma).cos() -
+                            0.040849 * (2. * gamma).sin());
+    let declination = 0.006918 - 0.399912 * gamma.cos() + 0.070257 * gamma.sin() -
+                      0.006758 * (2. * gamma).cos() +
+                      0.000907 * (2. * gamma).sin() -
+                      0.002697 * (3. * gamma).cos() +
+                      0.00148 * (3. * gamma).sin();
+    let true_solar_time = hour * 60. + equation_of_time + 4. * location.longitude;
+    let hour_angle = (true_solar_time / 4. - 180.).to_radians();
+    let latitude = location.latitude.to_radians();
+    let cos_zenith = latitude.sin() * declination.sin() +
+                     latitude.cos() * declination.cos() * hour_angle.cos();
+    90. - cos_zenith.max(-1.).min(1.).acos().to_degrees()
+}
+
+fn is_corrupt(path: &Path) -> bool {
+    let mut bytes = Vec::new();
+    match File::open(path).and_then(|mut file| file.read_to_end(&mut bytes)) {
+        Ok(_) => jpeg::parse(&bytes).map(|info| info.truncated).unwrap_or(true),
+        Err(_) => true,
+    }
+}
+
+/// Returns a small grayscale version of the image, or `None` if it can't be decoded.
+fn sample(path: &Path) -> Option<Vec<u8>> {
+    image::open(path)
+        .map(|image| {
+            image.resize_exact(SAMPLE_WIDTH, SAMPLE_HEIGHT, FilterType::Triangle)
+                .to_luma()
+                .into_raw()
+        })
+        .ok()
+}
+
+fn brightness(sample: &[u8]) -> f64 {
+    if sample.is_empty() {
+        return 0.;
+    }
+    sample.iter().fold(0., |sum, &v| sum + v as f64) / sample.len() as f64
+}
+
+fn difference(a: &[u8], b: &[u8]) -> f64 {
+    if a.is_empty() || a.len() != b.len() {
+        return 255.;
+    }
+    a.iter()
+        .zip(b)
+        .fold(0., |sum, (&a, &b)| sum + (a as f64 - b as f64).abs()) / a.len() as f64
+}
+
+/// Keeps at most `max` frames per day, evenly spaced through each day's frames.
+fn thin(frames: Vec<(DateTime<UTC>, PathBuf)>, max: usize) -> Vec<PathBuf> {
+    let mut days: Vec<(NaiveDate, Vec<PathBuf>)> = Vec::new();
+    for (datetime, path) in frames {
+        let date = datetime.date().naive_utc();
+        let is_new_day = days.last().map(|&(d, _)| d != date).unwrap_or(true);
+        if is_new_day {
+            days.push((date, Vec::new()));
+        }
+        days.last_mut().unwrap().1.push(path);
+    }
+    let mut paths = Vec::new();
+    for (_, day) in days {
+        if day.len() <= max {
+            paths.extend(day);
+        } else {
+            let n = day.len();
+            paths.extend((0..max).map(|k| day[k * n / max].clone()));
+        }
+    }
+    paths
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::{brightness, difference, thin};
+
+    use std::path::PathBuf;
+
+    use chrono::{NaiveTime, TimeZone, UTC};
+
+    use cam::Camera;
+
+    const HELHEIM: Location = Location {
+        latitude: 66.35,
+        longitude: -38.2,
+    };
+
+    #[test]
+    fn sun() {
+        // Local solar noon at the summer solstice.
+        let noon = sun_elevation(&UTC.ymd(2016, 6, 21).and_hms(14, 33, 0), HELHEIM);
+        assert!((noon - 47.1).abs() < 1., "{}", noon);
+        let midnight = sun_elevation(&UTC.ymd(2016, 6, 21).and_hms(2, 33, 0), HELHEIM);
+        assert!(midnight.abs() < 1., "{}", midnight);
+        let winter = sun_elevation(&UTC.ymd(2016, 12, 21).and_hms(14, 33, 0), HELHEIM);
+        assert!(winter < 1., "{}", winter);
+    }
+
+    #[test]
+    fn window() {
+        let camera = Camera::new("ATLAS_CAM", "data").unwrap();
+        let selection = Selection {
+            window: Some((NaiveTime::from_hms(12, 0, 0), NaiveTime::from_hms(13, 0, 0))),
+            ..Default::default()
+        };
+        let paths = selection.select(&camera, camera.paths().unwrap());
+        assert_eq!(vec![PathBuf::from("data/ATLAS_CAM_20160725_121500.jpg")], paths);
+
+        let selection = Selection {
+            window: Some((NaiveTime::from_hms(14, 0, 0), NaiveTime::from_hms(13, 0, 0))),
+            ..Default::default()
+        };
+        let paths = selection.select(&camera, camera.paths().unwrap());
+        assert_eq!(vec![PathBuf::from("data/ATLAS_CAM_20160725_141500.jpg")], paths);
+    }
+
+    #[test]
+    fn sun_filter() {
+        let camera = Camera::new("ATLAS_CAM", "data").unwrap();
+        let selection = Selection {
+            min_sun_elevation: Some((90., HELHEIM)),
+            ..Default::default()
+        };
+        assert!(selection.select(&camera, camera.paths().unwrap()).is_empty());
+    }
+
+    #[test]
+    fn brightness_and_difference() {
+        let camera = Camera::new("ATLAS_CAM", "data").unwrap();
+        let selection = Selection {
+            min_brightness: Some(256.),
+            ..Default::default()
+        };
+        assert!(selection.select(&camera, camera.paths().unwrap()).is_empty());
+        let selection = Selection {
+            min_difference: Some(256.),
+            ..Default::default()
+        };
+        assert_eq!(1, selection.select(&camera, camera.paths().unwrap()).len());
+        assert_eq!(10., brightness(&[0, 20]));
+        assert_eq!(5., difference(&[0, 20], &[5, 15]));
+    }
+
+    #[test]
+    fn skip_corrupt() {
+        let camera = Camera::new("ATLAS_CAM", "data").unwrap();
+        let selection = Selection {
+            skip_corrupt: true,
+            ..Default::default()
+        };
+        assert_eq!(2, selection.select(&camera, camera.paths().unwrap()).len());
+    }
+
+    #[test]
+    fn thin_days() {
+        let frames = (0..10)
+            .map(|i| {
+                (UTC.ymd(2016, 7, 25).and_hms(i, 0, 0), PathBuf::from(format!("{}.jpg", i)))
+            })
+            .chain(Some((UTC.ymd(2016, 7, 26).and_hms(0, 0, 0), PathBuf::from("next.jpg"))))
+            .collect::<Vec<_>>();
+        let paths = thin(frames, 2);
+        assert_eq!(vec![PathBuf::from("0.jpg"), PathBuf::from("5.jpg"), PathBuf::from("next.jpg")],
+                   paths);
+    }
+}
@@ -1,19 +1,20 @@
 //! Serve data using Iron.
 
 use std::ascii::AsciiExt;
-use std::collections::BTreeMap;
-#[cfg(feature = "magick_rust")]
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fmt::Write;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::Read;
+use std::mem;
+use std::net::IpAddr;
 use std::path::{Path, PathBuf};
+use std::result;
 use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread;
+use std::time::SystemTime;
 
-#[cfg(feature = "magick_rust")]
-use chrono::Duration;
-use chrono::UTC;
+use chrono::{Duration, NaiveTime, UTC};
 
 use handlebars_iron::{DirectorySource, HandlebarsEngine, Template};
 
@@ -32,39 +33,75 @@ use router::Router;
 use rustc_serialize::json::{Json, ToJson};
 use rustc_serialize::Decodable;
 
-use sbd::storage::FilesystemStorage;
-
 use staticfile::Static;
 
 use toml;
 
 use url::Url;
 
-use {Error, Result};
-use cam::Camera;
+use {ConfigProblem, Error, Result};
+use animation::{self, AnimationEncoder, AnimationHandler, AnimationWatcher, Animator};
+use api::Api;
+use archive::{Archiver, Policy};
+use browse::Browser;
+use cam::{self, Camera, Naming};
+use catalog::{CatalogHandler, CatalogWatcher, ImageInfo};
+use change::{ChangeCsvHandler, ChangeDetector, ChangeScore, ChangeWatcher, Region};
 use heartbeat::{HeartbeatV1, expected_next_scan_time};
-use watch::{DirectoryWatcher, HeartbeatWatcher};
-#[cfg(feature = "magick_rust")]
-use magick::{self, GifHandler, GifWatcher};
+use images::ImageHandler;
+use iridium::{MessageBrowser, MessageInfo, MessageWatcher};
+use link::{LinkCsvHandler, LinkHandler, Pricing};
+use location::LocationsHandler;
+use overlay::{Overlay, Position};
+use quality::{self, QualityCsvHandler, Thresholds};
+use query;
+use select::{Location, Selection};
+use station::{HEARTBEAT_VERSIONS, Station, StationHandler, StationsHandler};
+use sutron::{self, scan_sessions};
+use thumbnail::{Size, ThumbnailCache, ThumbnailHandler, ThumbnailWatcher};
+use timelapse::{TimelapseFileHandler, TimelapseHandler, TimelapseQueue, TimelapseStatusHandler,
+                TimelapseWorker};
+use upload::UploadHandler;
+use video::{FfmpegEncoder, VideoFormat};
+use watch::{DirectoryWatcher, HeartbeatWatcher, Stoppable, SutronWatcher};
 
 /// The ATLAS status server.
 ///
 /// The server is configured with a toml file. See `data/config.toml` in this repository for an
-/// example of a config file.
+/// example of a config file, and `data/stations.toml` for a server with more than one station.
+///
+/// A running server watches its config file and reloads it when it changes. See `serve`.
 #[derive(Debug)]
 pub struct Server {
+    config_file: PathBuf,
     config: Configuration,
-    heartbeats: Arc<RwLock<Vec<HeartbeatV1>>>,
-    #[cfg(feature = "magick_rust")]
-    gifs: HashMap<String, Arc<RwLock<Vec<u8>>>>,
+    stations: Vec<Station>,
+    messages: Arc<RwLock<Vec<MessageInfo>>>,
+    catalogs: HashMap<String, Arc<RwLock<Vec<ImageInfo>>>>,
+    animations: HashMap<String, Arc<RwLock<Vec<u8>>>>,
+    changes: HashMap<String, (ChangeDetector, Arc<RwLock<Vec<ChangeScore>>>)>,
+    timelapses: Option<TimelapseQueue>,
+    timelapse_worker: Option<TimelapseWorker>,
+    watchers: Watchers,
 }
 
+/// Running watcher threads, keyed by what they watch, with the fingerprint of the configuration
+/// they were started with, the flag that stops them, and the thread itself.
+type Watchers = HashMap<String, (String, Arc<AtomicBool>, thread::JoinHandle<()>)>;
+
 #[derive(Debug, RustcDecodable)]
 struct Configuration {
     server: ServerConfig,
+    station: Option<Vec<StationConfig>>,
     camera: Vec<CameraConfig>,
-    #[cfg(feature = "magick_rust")]
+    thumbnails: Option<ThumbnailsConfig>,
     gif: GifConfig,
+    timelapse: Option<TimelapseConfig>,
+    change: Option<HashMap<String, ChangeConfig>>,
+    quality: Option<QualityConfig>,
+    upload: Option<UploadConfig>,
+    archive: Option<ArchiveConfig>,
+    link: Option<LinkConfig>,
 }
 
 #[derive(Debug, RustcDecodable)]
@@ -73,18 +110,87 @@ struct ServerConfig {
     port: u16,
     resource_dir: String,
     iridium_dir: String,
-    imeis: Vec<String>,
+    imeis: Option<Vec<String>>,
     img_url: String,
-    active_camera: String,
+    active_camera: Option<String>,
+    sutron_dir: Option<String>,
+    serve_images: Option<bool>,
+}
+
+#[derive(Debug, RustcDecodable)]
+struct StationConfig {
+    id: String,
+    name: Option<String>,
+    imeis: Vec<String>,
+    cameras: Option<Vec<String>>,
+    active_camera: Option<String>,
+    heartbeat_version: Option<u8>,
+    sutron_dir: Option<String>,
 }
 
 #[derive(Debug, RustcDecodable)]
 struct CameraConfig {
     directory: String,
     name: Option<String>,
+    pattern: Option<String>,
+    format: Option<String>,
+    extensions: Option<Vec<String>>,
+    timezone: Option<String>,
+}
+
+#[derive(Debug, RustcDecodable)]
+struct ThumbnailsConfig {
+    directory: String,
+    size: Vec<SizeConfig>,
+}
+
+#[derive(Debug, RustcDecodable)]
+struct SizeConfig {
+    name: String,
+    width: u32,
+    height: u32,
+}
+
+#[derive(Debug, RustcDecodable)]
+struct QualityConfig {
+    min_brightness: Option<f64>,
+    min_sharpness: Option<f64>,
+    max_saturated: Option<f64>,
+    window: Option<usize>,
+}
+
+#[derive(Debug, RustcDecodable)]
+struct ChangeConfig {
+    threshold: f64,
+    region: Option<Vec<f64>>,
+}
+
+#[derive(Debug, RustcDecodable)]
+struct LinkConfig {
+    monthly_fee: Option<f64>,
+    included_bytes: Option<usize>,
+    price_per_kilobyte: Option<f64>,
+    minimum_message_bytes: Option<usize>,
+}
+
+#[derive(Debug, RustcDecodable)]
+struct ArchiveConfig {
+    after_days: Option<i64>,
+    quality: Option<u8>,
+    thin_after_days: Option<i64>,
+    delete_after_days: Option<i64>,
+}
+
+#[derive(Debug, RustcDecodable)]
+struct UploadConfig {
+    tokens: Vec<String>,
+}
+
+#[derive(Debug, RustcDecodable)]
+struct TimelapseConfig {
+    directory: String,
 }
 
-#[cfg(feature = "magick_rust")]
 #[derive(Debug, RustcDecodable)]
 struct GifConfig {
     days: i64,
@@ -92,6 +198,569 @@ struct GifConfig {
     width: u64,
     height: u64,
     names: Vec<String>,
+    encoder: Option<String>,
+    videos: Option<Vec<String>>,
+    ffmpeg: Option<String>,
+    selection: Option<HashMap<String, SelectionConfig>>,
+    overlay: Option<OverlayConfig>,
+}
+
+#[derive(Debug, RustcDecodable)]
+struct OverlayConfig {
+    font: String,
+    size: Option<f32>,
+    position: Option<String>,
+    text: Option<String>,
+    datetime_format: Option<String>,
+}
+
+#[derive(Debug, RustcDecodable)]
+struct SelectionConfig {
+    start: Option<String>,
+    end: Option<String>,
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+    min_sun_elevation: Option<f64>,
+    min_brightness: Option<f64>,
+    min_difference: Option<f64>,
+    max_per_day: Option<usize>,
+    skip_corrupt: Option<bool>,
+}
+
+impl CameraConfig {
+    fn to_camera(&self) -> Result<Camera> {
+        let directory = Path::new(&self.directory);
+        let name = match self.name {
+            Some(ref name) => name.to_string(),
+            None => {
+                try!(directory.file_name()
+                        .ok_or(Error::InvalidCameraPath("no file name".to_string(),
+                                                        directory.to_path_buf())))
+                    .to_string_lossy()
+                    .into_owned()
+            }
+        };
+        let mut naming = Naming::default();
+        if let Some(ref pattern) = self.pattern {
+            naming.pattern = pattern.to_string();
+        }
+        if let Some(ref format) = self.format {
+            naming.format = format.to_string();
+        }
+        if let Some(ref extensions) = self.extensions {
+            naming.extensions = extensions.clone();
+        }
+        if let Some(ref timezone) = self.timezone {
+            naming.timezone = try!(cam::parse_timezone(timezone));
+        }
+        Camera::with_naming(&name, directory, naming)
+    }
+}
+
+impl ArchiveConfig {
+    fn to_policy(&self) -> Result<Policy> {
+        let mut policy = Policy::default();
+        if let Some(after_days) = self.after_days {
+            policy.archive_after = Duration::days(after_days);
+        }
+        policy.quality = self.quality;
+        if let Some(thin_after_days) = self.thin_after_days {
+            policy.thin_after = if thin_after_days > 0 {
+                Some(Duration::days(thin_after_days))
+            } else {
+                None
+            };
+        }
+        policy.delete_after = self.delete_after_days.map(Duration::days);
+        try!(policy.validate());
+        Ok(policy)
+    }
+}
+
+impl SelectionConfig {
+    fn to_selection(&self) -> Result<Selection> {
+        let window = match (self.start.as_ref(), self.end.as_ref()) {
+            (Some(start), Some(end)) => {
+                Some((try!(NaiveTime::parse_from_str(start, "%H:%M")),
+                      try!(NaiveTime::parse_from_str(end, "%H:%M"))))
+            }
+            (None, None) => None,
+            _ => {
+                return Err(Error::ServerConfigError("gif selection needs both a start and an end"
+                    .to_string()))
+            }
+        };
+        let min_sun_elevation = match (self.min_sun_elevation, self.latitude, self.longitude) {
+            (Some(elevation), Some(latitude), Some(longitude)) => {
+                Some((elevation,
+                      Location {
+                          latitude: latitude,
+                          longitude: longitude,
+                      }))
+            }
+            (Some(_), _, _) => {
+                return Err(Error::ServerConfigError("gif selection needs a latitude and \
+                                                     longitude to filter on sun elevation"
+                    .to_string()))
+            }
+            (None, _, _) => None,
+        };
+        Ok(Selection {
+            window: window,
+            min_sun_elevation: min_sun_elevation,
+            min_brightness: self.min_brightness,
+            min_difference: self.min_difference,
+            max_per_day: self.max_per_day,
+            skip_corrupt: self.skip_corrupt.unwrap_or(false),
+        })
+    }
+}
+
+/// The templates that the server renders.
+const TEMPLATES: &'static [&'static str] = &["camera", "index", "link", "messages", "station",
+                                               "stations", "status", "sutron"];
+
+/// The lines of the tables and keys in a toml source, used to point problems at their lines.
+///
+/// The decoded configuration doesn't remember where anything came from, so we scan the source
+/// for table headers and keys ourselves.
+#[derive(Debug)]
+struct Lines {
+    entries: Vec<LineEntry>,
+}
+
+#[derive(Debug)]
+struct LineEntry {
+    table: String,
+    index: usize,
+    key: Option<String>,
+    line: usize,
+}
+
+impl Lines {
+    fn new(source: &str) -> Lines {
+        let mut entries = Vec::new();
+        let mut counts = HashMap::new();
+        let mut table = String::new();
+        let mut index = 0;
+        for (i, line) in source.lines().enumerate() {
+            let line = line.trim();
+            let key = if line.starts_with('[') {
+                let is_array = line.starts_with("[[");
+                table = line.trim_matches(|c: char| c == '[' || c == ']').trim().to_string();
+                index = if is_array {
+                    let count = counts.entry(table.clone()).or_insert(0);
+                    *count += 1;
+                    *count - 1
+                } else {
+                    0
+                };
+                None
+            } else if !line.starts_with('#') && line.contains('=') {
+                Some(line[..line.find('=').unwrap()].trim().trim_matches('"').to_string())
+            } else {
+                continue;
+            };
+            entries.push(LineEntry {
+                table: table.clone(),
+                index: index,
+                key: key,
+                line: i + 1,
+            });
+        }
+        Lines { entries: entries }
+    }
+
+    /// Returns the line of a key in a table, or of the table itself if the key isn't there.
+    ///
+    /// `index` counts tables in an array of tables, e.g. the second `[[camera]]` is at index one.
+    fn find(&self, table: &str, index: usize, key: Option<&str>) -> Option<usize> {
+        let matches = |entry: &&LineEntry| entry.table == table && entry.index == index;
+        key.and_then(|key| {
+                self.entries
+                    .iter()
+                    .filter(&matches)
+                    .find(|e| e.key.as_ref().map_or(false, |k| k == key))
+            })
+            .or_else(|| self.entries.iter().filter(&matches).find(|e| e.key.is_none()))
+            .map(|e| e.line)
+    }
+
+    /// Returns the line of a field in a toml decoding error, e.g. `camera[1].directory`.
+    fn field(&self, field: &str) -> Option<usize> {
+        let mut names = Vec::new();
+        let mut index = 0;
+        for segment in field.split('.') {
+            let name = match segment.find('[') {
+                Some(n) => {
+                    index = segment[n + 1..].trim_right_matches(']').parse().unwrap_or(0);
+                    &segment[..n]
+                }
+                None => segment,
+            };
+            match name.parse::<usize>() {
+                Ok(n) => index = n,
+                Err(_) => names.push(name),
+            }
+        }
+        let key = match names.pop() {
+            Some(key) => key,
+            None => return None,
+        };
+        self.find(&names.join("."), index, Some(key)).or_else(|| {
+            names.push(key);
+            self.find(&names.join("."), index, None)
+        })
+    }
+}
+
+/// Collects configuration problems.
+///
+/// Unless `environment` is set, files and directories aren't checked, only the configuration
+/// itself.
+#[derive(Debug)]
+struct Checker {
+    lines: Lines,
+    problems: Vec<ConfigProblem>,
+    environment: bool,
+}
+
+impl Checker {
+    fn add<S: Into<String>>(&mut self, table: &str, index: usize, key: Option<&str>, message: S) {
+        let line = self.lines.find(table, index, key);
+        self.problems.push(ConfigProblem {
+            line: line,
+            message: message.into(),
+        });
+    }
+
+    fn directory(&mut self, table: &str, index: usize, key: &str, path: &str) {
+        if self.environment && !Path::new(path).is_dir() {
+            self.add(table,
+                     index,
+                     Some(key),
+                     format!("{} is not a directory: {}", key, path));
+        }
+    }
+
+    /// Checks a directory that the server creates if it's missing.
+    fn creatable_directory(&mut self, table: &str, index: usize, key: &str, path: &str) {
+        let path = Path::new(path);
+        if self.environment && path.exists() && !path.is_dir() {
+            self.add(table,
+                     index,
+                     Some(key),
+                     format!("{} is not a directory: {}", key, path.display()));
+        }
+    }
+
+    fn file(&mut self, table: &str, index: usize, key: &str, path: &str) {
+        if self.environment && !Path::new(path).is_file() {
+            self.add(table, index, Some(key), format!("{} is not a file: {}", key, path));
+        }
+    }
+
+    fn imei(&mut self, table: &str, index: usize, imei: &str) {
+        if imei.len() != 15 || !imei.chars().all(|c| c.is_digit(10)) {
+            self.add(table,
+                     index,
+                     Some("imeis"),
+                     format!("Invalid IMEI, expected fifteen digits: {}", imei));
+        }
+    }
+
+    fn camera(&mut self,
+              table: &str,
+              index: usize,
+              key: &str,
+              cameras: &HashMap<String, Camera>,
+              name: &str) {
+        if !cameras.contains_key(name) {
+            self.add(table, index, Some(key), format!("Unknown camera name: {}", name));
+        }
+    }
+}
+
+impl Configuration {
+    /// Parses and decodes a configuration, pointing any problems at their lines.
+    fn from_source(source: &str) -> result::Result<Configuration, Vec<ConfigProblem>> {
+        let mut parser = toml::Parser::new(source);
+        let toml = match parser.parse() {
+            Some(toml) => toml,
+            None => {
+                return Err(parser.errors
+                    .iter()
+                    .map(|e| {
+                        ConfigProblem {
+                            line: Some(parser.to_linecol(e.lo).0 + 1),
+                            message: e.desc.clone(),
+                        }
+                    })
+                    .collect())
+            }
+        };
+        let mut decoder = toml::Decoder::new(toml::Value::Table(toml));
+        Configuration::decode(&mut decoder).map_err(|e| {
+            vec![ConfigProblem {
+                     line: e.field.as_ref().and_then(|f| Lines::new(source).field(f)),
+                     message: e.to_string(),
+                 }]
+        })
+    }
+
+    /// Checks everything that the server would otherwise only find out about once it's running,
+    /// and returns every problem found.
+    ///
+    /// The source is the toml that this configuration was decoded from.
+    fn validate(&self, source: &str) -> Vec<ConfigProblem> {
+        self.check(source, true)
+    }
+
+    /// Checks the rules that every configuration must follow, whatever is on disk.
+    ///
+    /// These are the same rules that `validate` checks, so a server can't be created from a
+    /// configuration that `check_config` would reject for anything other than files and
+    /// directories.
+    fn rules(&self, source: &str) -> Vec<ConfigProblem> {
+        self.check(source, false)
+    }
+
+    fn check(&self, source: &str, environment: bool) -> Vec<ConfigProblem> {
+        let mut check = Checker {
+            lines: Lines::new(source),
+            problems: Vec::new(),
+            environment: environment,
+        };
+
+        let server = &self.server;
+        if server.ip.parse::<IpAddr>().is_err() {
+            check.add("server",
+                      0,
+                      Some("ip"),
+                      format!("Invalid ip address: {}", server.ip));
+        }
+        check.directory("server", 0, "resource_dir", &server.resource_dir);
+        check.directory("server", 0, "iridium_dir", &server.iridium_dir);
+        if let Err(err) = Url::parse(&server.img_url) {
+            check.add("server",
+                      0,
+                      Some("img_url"),
+                      format!("Invalid image url {}: {}", server.img_url, err));
+        }
+        for imei in server.imeis.iter().flat_map(|imeis| imeis.iter()) {
+            check.imei("server", 0, imei);
+        }
+        if let Some(ref sutron_dir) = server.sutron_dir {
+            check.directory("server", 0, "sutron_dir", sutron_dir);
+        }
+
+        let mut cameras = HashMap::new();
+        for (i, config) in self.camera.iter().enumerate() {
+            check.directory("camera", i, "directory", &config.directory);
+            let camera = match config.to_camera() {
+                Ok(camera) => camera,
+                Err(err) => {
+                    check.add("camera", i, None, err.to_string());
+                    continue;
+                }
+            };
+            if cameras.contains_key(camera.name()) {
+                check.add("camera",
+                          i,
+                          Some("name"),
+                          format!("Duplicate camera name: {}", camera.name()));
+                continue;
+            }
+            if check.environment && Path::new(&config.directory).is_dir() {
+                match camera.latest_file_name() {
+                    Ok(Some(_)) => {}
+                    Ok(None) => {
+                        check.add("camera",
+                                  i,
+                                  Some("directory"),
+                                  format!("Camera {} has no images", camera.name()))
+                    }
+                    Err(err) => {
+                        check.add("camera",
+                                  i,
+                                  Some("directory"),
+                                  format!("Could not read the images of camera {}: {}",
+                                          camera.name(),
+                                          err))
+                    }
+                }
+            }
+            cameras.insert(camera.name().to_string(), camera);
+        }
+
+        if let Some(ref active_camera) = server.active_camera {
+            check.camera("server", 0, "active_camera", &cameras, active_camera);
+        }
+        match self.station {
+            None => {
+                if server.imeis.is_none() {
+                    check.add("server", 0, None, "No imeis and no stations configured");
+                }
+                if server.active_camera.is_none() {
+                    check.add("server", 0, None, "No active_camera configured");
+                }
+            }
+            Some(ref stations) => {
+                let has_camera = |s: &StationConfig| {
+                    s.cameras.as_ref().map_or(false, |c| !c.is_empty())
+                };
+                if !stations.first().map_or(false, has_camera) {
+                    check.add("station", 0, None, "The first station needs a camera");
+                }
+                let mut ids = HashSet::new();
+                let mut imeis = HashSet::new();
+                let mut station_cameras = HashMap::new();
+                for (i, station) in stations.iter().enumerate() {
+                    if let Err(err) = Station::new(&station.id, &station.id, Vec::new()) {
+                        check.add("station", i, Some("id"), err.to_string());
+                    }
+                    if !ids.insert(&station.id) {
+                        check.add("station",
+                                  i,
+                                  Some("id"),
+                                  format!("Duplicate station id: {}", station.id));
+                    }
+                    for imei in &station.imeis {
+                        check.imei("station", i, imei);
+                        if !imeis.insert(imei) {
+                            check.add("station",
+                                      i,
+                                      Some("imeis"),
+                                      format!("IMEI {} is in more than one station", imei));
+                        }
+                    }
+                    let names = station.cameras.as_ref().map(|v| v.as_slice()).unwrap_or(&[]);
+                    for name in names {
+                        check.camera("station", i, "cameras", &cameras, name);
+                        if *station_cameras.entry(name).or_insert(i) != i {
+                            check.add("station",
+                                      i,
+                                      Some("cameras"),
+                                      format!("Camera {} is in more than one station", name));
+                        }
+                    }
+                    if let Some(ref active_camera) = station.active_camera {
+                        if !names.contains(active_camera) {
+                            check.add("station",
+                                      i,
+                                      Some("active_camera"),
+                                      format!("Active camera {} is not one of the station's \
+                                               cameras",
+                                              active_camera));
+                        }
+                    }
+                    if let Some(version) = station.heartbeat_version {
+                        if !HEARTBEAT_VERSIONS.contains(&version) {
+                            check.add("station",
+                                      i,
+                                      Some("heartbeat_version"),
+                                      format!("Unsupported heartbeat version: {}", version));
+                        }
+                    }
+                    if let Some(ref sutron_dir) = station.sutron_dir {
+                        check.directory("station", i, "sutron_dir", sutron_dir);
+                    }
+                }
+            }
+        }
+
+        if let Some(ref thumbnails) = self.thumbnails {
+            check.creatable_directory("thumbnails", 0, "directory", &thumbnails.directory);
+            for (i, size) in thumbnails.size.iter().enumerate() {
+                if size.width == 0 || size.height == 0 {
+                    check.add("thumbnails.size",
+                              i,
+                              Some("width"),
+                              format!("Thumbnail size {} is empty", size.name));
+                }
+            }
+        }
+
+        let gif = &self.gif;
+        if let Some(ref encoder) = gif.encoder {
+            if let Err(err) = animation::encoder(encoder) {
+                check.add("gif", 0, Some("encoder"), err.to_string());
+            }
+        }
+        for video in gif.videos.iter().flat_map(|videos| videos.iter()) {
+            if let Err(err) = video.parse::<VideoFormat>() {
+                check.add("gif", 0, Some("videos"), err.to_string());
+            }
+        }
+        for name in &gif.names {
+            check.camera("gif", 0, "names", &cameras, name);
+        }
+        for (name, selection) in gif.selection.iter().flat_map(|s| s.iter()) {
+            let table = format!("gif.selection.{}", name);
+            check.camera(&table, 0, "", &cameras, name);
+            if let Err(err) = selection.to_selection() {
+                check.add(&table, 0, None, err.to_string());
+            }
+        }
+        if let Some(ref overlay) = gif.overlay {
+            check.file("gif.overlay", 0, "font", &overlay.font);
+            if let Some(ref position) = overlay.position {
+                if let Err(err) = position.parse::<Position>() {
+                    check.add("gif.overlay", 0, Some("position"), err.to_string());
+                }
+            }
+        }
+
+        if let Some(ref timelapse) = self.timelapse {
+            check.creatable_directory("timelapse", 0, "directory", &timelapse.directory);
+        }
+        for (name, change) in self.change.iter().flat_map(|c| c.iter()) {
+            let table = format!("change.{}", name);
+            check.camera(&table, 0, "", &cameras, name);
+            if let Some(ref region) = change.region {
+                if let Err(err) = Region::from_slice(region) {
+                    check.add(&table, 0, Some("region"), err.to_string());
+                }
+            }
+        }
+        if let Some(ref archive) = self.archive {
+            if let Err(err) = archive.to_policy() {
+                check.add("archive", 0, None, err.to_string());
+            }
+        }
+        if let Some(ref upload) = self.upload {
+            if upload.tokens.iter().any(|t| t.is_empty()) {
+                check.add("upload", 0, Some("tokens"), "Empty upload token");
+            }
+        }
+
+        let templates = Path::new(&server.resource_dir).join("templates");
+        if check.environment && templates.is_dir() {
+            for name in TEMPLATES {
+                if !templates.join(format!("{}.hbs", name)).is_file() {
+                    check.add("server",
+                              0,
+                              Some("resource_dir"),
+                              format!("Missing template: {}.hbs", name));
+                }
+            }
+            let mut hbse = HandlebarsEngine::new();
+            hbse.add(Box::new(DirectorySource::new(&templates.to_string_lossy(), ".hbs")));
+            if let Err(err) = hbse.reload() {
+                check.add("server",
+                          0,
+                          Some("resource_dir"),
+                          format!("Template error: {}", err));
+            }
+        } else if check.environment && Path::new(&server.resource_dir).is_dir() {
+            check.add("server",
+                      0,
+                      Some("resource_dir"),
+                      format!("No templates directory: {}", templates.display()));
+        }
+
+        check.problems
+    }
 }
 
 impl Server {
@@ -103,74 +772,283 @@ impl Server {
     /// # use atlas::server::Server;
     /// let server = Server::new("data/config.toml").unwrap();
     /// ```
-    #[cfg(feature = "magick_rust")]
     pub fn new<P: AsRef<Path>>(config_file: P) -> Result<Server> {
-        let config = try!(Server::config_from_file(config_file));
-        Ok(Server {
-            gifs: config.gif
-                .names
-                .iter()
-                .map(|n| (n.to_string(), Arc::new(RwLock::new(Vec::new()))))
-                .collect(),
+        let config = try!(Server::config_from_file(&config_file));
+        let mut server = Server {
+            config_file: config_file.as_ref().to_path_buf(),
             config: config,
-            heartbeats: Arc::new(RwLock::new(Vec::new())),
+            stations: Vec::new(),
+            messages: Arc::new(RwLock::new(Vec::new())),
+            catalogs: HashMap::new(),
+            animations: HashMap::new(),
+            changes: HashMap::new(),
+            timelapses: None,
+            timelapse_worker: None,
+            watchers: HashMap::new(),
+        };
+        server.stations = try!(server.new_stations());
+        server.catalogs = try!(server.new_catalogs());
+        server.animations = try!(server.new_animations());
+        server.changes = try!(server.new_changes());
+        if let Some((queue, worker)) = try!(server.new_timelapses()) {
+            server.timelapses = Some(queue);
+            server.timelapse_worker = Some(worker);
+        }
+        Ok(server)
+    }
+
+    /// Creates the configured stations.
+    ///
+    /// Without any `[[station]]` sections, the `[server]` section describes a single station,
+    /// ATLAS, with every camera. The configuration rules have already been checked, so e.g.
+    /// every station's cameras exist.
+    fn new_stations(&self) -> Result<Vec<Station>> {
+        let configs = match self.config.station {
+            Some(ref configs) => configs,
+            None => {
+                let imeis = self.config.server.imeis.clone().unwrap_or_else(Vec::new);
+                let mut station = try!(Station::new("atlas", "ATLAS", imeis));
+                station.cameras = try!(self.cameras());
+                station.active_camera = self.config.server.active_camera.clone();
+                station.sutron_dir = self.config.server.sutron_dir.as_ref().map(PathBuf::from);
+                return Ok(vec![station]);
+            }
+        };
+        let cameras = try!(self.camera_map());
+        let mut stations = Vec::new();
+        for config in configs {
+            let name = config.name.as_ref().unwrap_or(&config.id);
+            let mut station = try!(Station::new(&config.id, name, config.imeis.clone()));
+            for name in config.cameras.as_ref().map(|v| v.as_slice()).unwrap_or(&[]) {
+                station.cameras.push(cameras[name].clone());
+            }
+            station.active_camera = config.active_camera
+                .clone()
+                .or_else(|| station.cameras.first().map(|c| c.name().to_string()));
+            if let Some(version) = config.heartbeat_version {
+                station.heartbeat_version = version;
+            }
+            station.sutron_dir = config.sutron_dir.as_ref().map(PathBuf::from);
+            stations.push(station);
+        }
+        Ok(stations)
+    }
+
+    /// Creates a change detector and empty change scores for each configured camera, keyed by
+    /// camera name.
+    fn new_changes(&self)
+                   -> Result<HashMap<String, (ChangeDetector, Arc<RwLock<Vec<ChangeScore>>>)>> {
+        let mut changes = HashMap::new();
+        if let Some(ref configs) = self.config.change {
+            for (name, config) in configs {
+                let mut detector = ChangeDetector::new(config.threshold);
+                if let Some(ref region) = config.region {
+                    detector = detector.region(try!(Region::from_slice(region)));
+                }
+                changes.insert(name.to_string(),
+                               (detector, Arc::new(RwLock::new(Vec::new()))));
+            }
+        }
+        Ok(changes)
+    }
+
+    /// Creates the on-demand timelapse queue and its worker, if timelapses are configured.
+    fn new_timelapses(&self) -> Result<Option<(TimelapseQueue, TimelapseWorker)>> {
+        let config = match self.config.timelapse {
+            Some(ref config) => config,
+            None => return Ok(None),
+        };
+        let mut cameras = Vec::new();
+        for camera in try!(self.cameras()) {
+            let selection = try!(self.gif_selection(camera.name()));
+            cameras.push((camera, selection));
+        }
+        let (queue, mut worker) = TimelapseQueue::new(&config.directory, cameras);
+        if let Some(ref ffmpeg) = self.config.gif.ffmpeg {
+            worker = worker.ffmpeg(ffmpeg);
+        }
+        Ok(Some((queue, worker)))
+    }
+
+    /// Creates an empty animation for each gif camera and animation encoder, keyed by the
+    /// animation's file name, e.g. `atlas_cam.mp4`.
+    fn new_animations(&self) -> Result<HashMap<String, Arc<RwLock<Vec<u8>>>>> {
+        let mut animations = HashMap::new();
+        for encoder in try!(self.animation_encoders()) {
+            for name in self.config.gif.names.iter() {
+                animations.insert(animation_file_name(name, encoder.as_ref()),
+                                  Arc::new(RwLock::new(Vec::new())));
+            }
+        }
+        Ok(animations)
+    }
+
+    fn new_catalogs(&self) -> Result<HashMap<String, Arc<RwLock<Vec<ImageInfo>>>>> {
+        self.cameras().map(|v| {
+            v.into_iter()
+                .map(|c| (c.name().to_string(), Arc::new(RwLock::new(Vec::new()))))
+                .collect()
         })
     }
 
-    /// Creates a new server from the provided toml configuration.
+    /// Reads a configuration file, and checks it against the configuration rules.
+    fn config_from_file<P: AsRef<Path>>(config_file: P) -> Result<Configuration> {
+        let source = try!(read_config(config_file));
+        let config = try!(Configuration::from_source(&source).map_err(Error::InvalidConfig));
+        let problems = config.rules(&source);
+        if problems.is_empty() {
+            Ok(config)
+        } else {
+            Err(Error::InvalidConfig(problems))
+        }
+    }
+
+    /// Checks a configuration file and returns every problem with it.
+    ///
+    /// This catches what would otherwise only fail once the server is running: missing
+    /// directories, unknown camera names, cameras without images, malformed IMEIs and urls, and
+    /// broken templates. Problems point at their line in the file where possible. An empty
+    /// vector means that the configuration is good.
     ///
     /// # Examples
     ///
     /// ```
     /// # use atlas::server::Server;
-    /// let server = Server::new("data/config.toml").unwrap();
+    /// let problems = Server::check_config("data/config.toml").unwrap();
+    /// for problem in problems {
+    ///     println!("{}", problem);
+    /// }
     /// ```
-    #[cfg(not(feature = "magick_rust"))]
-    pub fn new<P: AsRef<Path>>(config_file: P) -> Result<Server> {
-        let config = try!(Server::config_from_file(config_file));
-        Ok(Server {
-            config: config,
-            heartbeats: Arc::new(RwLock::new(Vec::new())),
+    pub fn check_config<P: AsRef<Path>>(config_file: P) -> Result<Vec<ConfigProblem>> {
+        let source = try!(read_config(config_file));
+        Ok(match Configuration::from_source(&source) {
+            Ok(config) => config.validate(&source),
+            Err(problems) => problems,
         })
     }
 
-    fn config_from_file<P: AsRef<Path>>(config_file: P) -> Result<Configuration> {
-        let mut config = String::new();
-        {
-            let mut file = try!(File::open(config_file));
-            try!(file.read_to_string(&mut config));
-        }
-        let mut parser = toml::Parser::new(&config);
-        let toml = match parser.parse() {
-            Some(t) => t,
-            None => return Err(Error::TomlParse(parser.errors.clone())),
-        };
-        let mut decoder = toml::Decoder::new(toml::Value::Table(toml));
-        Configuration::decode(&mut decoder).map_err(|e| Error::from(e))
-    }
-
     /// Starts the atlas server.
     ///
     /// This method should run forever.
     ///
+    /// The config file is watched while the server runs. When it changes, it's checked just like
+    /// `check_config` does, and if it's good the pages, cameras, stations, and watchers are
+    /// rebuilt in place. Watchers whose configuration didn't change keep running, along with
+    /// everything they've read so far. If the new configuration has problems, they're logged and
+    /// the server keeps running with the old one. The address can't change without a restart.
+    ///
     /// # Examples
     ///
     /// ```no_run
     /// # use atlas::server::Server;
-    /// let mut server = Server::new("data/config.toml").unwrap();
+    /// let server = Server::new("data/config.toml").unwrap();
     /// server.serve().unwrap().unwrap();
     /// ```
-    pub fn serve(&mut self) -> Result<HttpResult<Listening>> {
+    pub fn serve(mut self) -> Result<HttpResult<Listening>> {
+        let handler = Arc::new(RwLock::new(try!(self.handler())));
+        let mut chain = Chain::new(ReloadableHandler { handler: handler.clone() });
+        chain.link(self.logger());
+
+        try!(self.start_watchers());
+        let (ip, port) = (self.config.server.ip.clone(), self.config.server.port);
+        let mut watcher = try!(ConfigWatcher::new(self, handler));
+        thread::spawn(move || watcher.watch().unwrap());
+        Ok(Iron::new(chain).http((ip.as_str(), port)))
+    }
+
+    /// Returns the handler for every page, static file, and image.
+    fn handler(&self) -> Result<Arc<Handler>> {
         let mut mount = Mount::new();
         mount.mount("/static/", self.staticfiles());
+        if self.serve_images() {
+            mount.mount("/images/", ImageHandler::new(try!(self.cameras())));
+        }
         mount.mount("/", try!(self.router()));
         let mut chain = Chain::new(mount);
         chain.link_after(try!(self.handlebars_engine()));
-        chain.link(self.logger());
+        Ok(Arc::new(chain))
+    }
+
+    /// Re-reads the config file, and returns the handler for the reloaded server.
+    ///
+    /// If the new configuration has problems, the server is left as it was.
+    fn reload(&mut self) -> Result<Arc<Handler>> {
+        let problems = try!(Server::check_config(&self.config_file));
+        if !problems.is_empty() {
+            return Err(Error::InvalidConfig(problems));
+        }
+        let mut server = try!(Server::new(&self.config_file));
+        try!(server.adopt(self));
+        let handler = try!(server.handler());
+        if server.addr() != self.addr() {
+            warn!("Still listening on {}:{}, a new address needs a restart",
+                  self.config.server.ip,
+                  self.config.server.port);
+        }
+        server.watchers = mem::replace(&mut self.watchers, HashMap::new());
+        *self = server;
+        if let Err(err) = self.start_watchers() {
+            error!("Error while starting watchers after reload: {}", err);
+        }
+        Ok(handler)
+    }
 
-        self.start_heartbeat_watcher();
-        try!(self.start_gif_watcher());
-        Ok(Iron::new(chain).http(self.addr()))
+    /// Takes over the data of the old server's watchers that keep running after a reload.
+    fn adopt(&mut self, old: &Server) -> Result<()> {
+        let running = |key: &str, fingerprint: &str| {
+            old.watchers.get(key).map_or(false, |&(ref f, _, _)| f == fingerprint)
+        };
+        if running("messages", &self.messages_fingerprint()) {
+            self.messages = old.messages.clone();
+        }
+        let heartbeats = running("heartbeats", &self.heartbeats_fingerprint());
+        let mut stations = self.stations.clone();
+        for station in &mut stations {
+            if let Some(previous) = old.stations.iter().find(|s| s.id == station.id) {
+                if heartbeats {
+                    station.heartbeats = previous.heartbeats.clone();
+                }
+                if running(&format!("sutron:{}", station.id), &sutron_fingerprint(station)) {
+                    station.sutron_records = previous.sutron_records.clone();
+                }
+            }
+        }
+        self.stations = stations;
+        for camera in try!(self.cameras()) {
+            let name = camera.name();
+            if running(&format!("catalog:{}", name), &format!("{:?}", camera)) {
+                if let Some(catalog) = old.catalogs.get(name) {
+                    self.catalogs.insert(name.to_string(), catalog.clone());
+                }
+            }
+            let detector = self.changes.get(name).map(|&(detector, _)| detector);
+            if let Some(detector) = detector {
+                if running(&format!("change:{}", name),
+                           &format!("{:?} {:?}", camera, detector)) {
+                    if let Some(&(_, ref scores)) = old.changes.get(name) {
+                        self.changes.insert(name.to_string(), (detector, scores.clone()));
+                    }
+                }
+            }
+        }
+        let cameras = try!(self.camera_map());
+        let mut animations = Vec::new();
+        for encoder in try!(self.animation_encoders()) {
+            for name in self.config.gif.names.iter() {
+                let file_name = animation_file_name(name, encoder.as_ref());
+                let fingerprint = match cameras.get(name) {
+                    Some(camera) => self.gif_fingerprint(camera),
+                    None => continue,
+                };
+                if running(&format!("gif:{}", file_name), &fingerprint) {
+                    if let Some(animation) = old.animations.get(&file_name) {
+                        animations.push((file_name, animation.clone()));
+                    }
+                }
+            }
+        }
+        self.animations.extend(animations);
+        Ok(())
     }
 
     /// Returns this server's address as an (ip, port) pair.
@@ -214,7 +1092,7 @@ impl Server {
         Path::new(&self.config.server.iridium_dir)
     }
 
-    /// Returns the IMEI number.
+    /// Returns the IMEI numbers of every station's modems.
     ///
     /// # Examples
     ///
@@ -223,8 +1101,158 @@ impl Server {
     /// let server = Server::new("data/config.toml").unwrap();
     /// let imeis = server.imeis();
     /// ```
-    pub fn imeis(&self) -> &Vec<String> {
-        &self.config.server.imeis
+    pub fn imeis(&self) -> Vec<String> {
+        self.stations.iter().flat_map(|s| s.imeis.iter().cloned()).collect()
+    }
+
+    /// Returns this server's stations.
+    ///
+    /// The first station is the default, and is the one shown on the top-level pages.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use atlas::server::Server;
+    /// let server = Server::new("data/config.toml").unwrap();
+    /// assert_eq!("atlas", server.stations()[0].id);
+    /// ```
+    pub fn stations(&self) -> &[Station] {
+        &self.stations
+    }
+
+    /// Returns the directory that holds the default station's Sutron log files, if there is one.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use atlas::server::Server;
+    /// let server = Server::new("data/config.toml").unwrap();
+    /// let dir = server.sutron_dir();
+    /// ```
+    pub fn sutron_dir(&self) -> Option<&Path> {
+        self.default_station().sutron_dir.as_ref().map(|p| p.as_path())
+    }
+
+    fn default_station(&self) -> &Station {
+        &self.stations[0]
+    }
+
+    /// Returns the station that a camera belongs to, or the default station.
+    fn camera_station(&self, name: &str) -> &Station {
+        self.stations.iter().find(|s| s.has_camera(name)).unwrap_or(self.default_station())
+    }
+
+    /// Returns true if this server should serve camera images itself.
+    ///
+    /// Images are served under `/images/<camera name>/`, so `img_url` should point at
+    /// `/images`, e.g. `http://atlas.lidar.io/images`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use atlas::server::Server;
+    /// let server = Server::new("data/config.toml").unwrap();
+    /// assert!(!server.serve_images());
+    /// ```
+    pub fn serve_images(&self) -> bool {
+        self.config.server.serve_images.unwrap_or(false)
+    }
+
+    /// Returns this server's thumbnail cache, if thumbnails are configured.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use atlas::server::Server;
+    /// let server = Server::new("data/config.toml").unwrap();
+    /// let cache = server.thumbnail_cache();
+    /// ```
+    pub fn thumbnail_cache(&self) -> Option<ThumbnailCache> {
+        self.config.thumbnails.as_ref().map(|t| {
+            ThumbnailCache::new(&t.directory,
+                                t.size
+                                    .iter()
+                                    .map(|s| {
+                                        Size {
+                                            name: s.name.to_string(),
+                                            width: s.width,
+                                            height: s.height,
+                                        }
+                                    })
+                                    .collect())
+        })
+    }
+
+    /// Returns the policy for archiving old camera images, if archiving is configured.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use atlas::server::Server;
+    /// let server = Server::new("data/config.toml").unwrap();
+    /// let policy = server.archive_policy().unwrap().unwrap();
+    /// ```
+    pub fn archive_policy(&self) -> Result<Option<Policy>> {
+        match self.config.archive {
+            Some(ref config) => config.to_policy().map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the airtime pricing used to estimate the cost of the Iridium link.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use atlas::server::Server;
+    /// let server = Server::new("data/config.toml").unwrap();
+    /// let pricing = server.link_pricing();
+    /// ```
+    pub fn link_pricing(&self) -> Pricing {
+        let mut pricing = Pricing::default();
+        if let Some(ref config) = self.config.link {
+            if let Some(monthly_fee) = config.monthly_fee {
+                pricing.monthly_fee = monthly_fee;
+            }
+            if let Some(included_bytes) = config.included_bytes {
+                pricing.included_bytes = included_bytes;
+            }
+            if let Some(price_per_kilobyte) = config.price_per_kilobyte {
+                pricing.price_per_kilobyte = price_per_kilobyte;
+            }
+            if let Some(minimum_message_bytes) = config.minimum_message_bytes {
+                pricing.minimum_message_bytes = minimum_message_bytes;
+            }
+        }
+        pricing
+    }
+
+    /// Returns the image quality thresholds used to decide whether a camera is degraded.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use atlas::server::Server;
+    /// let server = Server::new("data/config.toml").unwrap();
+    /// let thresholds = server.quality_thresholds();
+    /// ```
+    pub fn quality_thresholds(&self) -> Thresholds {
+        let mut thresholds = Thresholds::default();
+        if let Some(ref config) = self.config.quality {
+            if let Some(min_brightness) = config.min_brightness {
+                thresholds.min_brightness = min_brightness;
+            }
+            if let Some(min_sharpness) = config.min_sharpness {
+                thresholds.min_sharpness = min_sharpness;
+            }
+            if let Some(max_saturated) = config.max_saturated {
+                thresholds.max_saturated = max_saturated;
+            }
+            if let Some(window) = config.window {
+                thresholds.window = window;
+            }
+        }
+        thresholds
     }
 
     /// Returns a `PathBuf` to a resource directory.
@@ -244,6 +1272,9 @@ impl Server {
 
     /// Returns a vector of this server's cameras.
     ///
+    /// If the server serves images itself, the cameras build their image urls with their names,
+    /// to match the image handler.
+    ///
     /// # Examples
     ///
     /// ```
@@ -252,27 +1283,17 @@ impl Server {
     /// let cameras = server.cameras();
     /// ```
     pub fn cameras(&self) -> Result<Vec<Camera>> {
+        let serve_images = self.serve_images();
         self.config
             .camera
             .iter()
             .map(|c| {
-                let directory = Path::new(&c.directory);
-                let name = match c.name {
-                    Some(ref name) => name.to_string(),
-                    None => {
-                        try!(directory.file_name()
-                                .ok_or(Error::InvalidCameraPath("no file name".to_string(),
-                                                                directory.to_path_buf())))
-                            .to_string_lossy()
-                            .into_owned()
-                    }
-                };
-                Camera::new(&name, directory)
+                c.to_camera()
+                    .map(|camera| if serve_images { camera.urls_by_name() } else { camera })
             })
             .collect()
     }
 
-    #[cfg(feature = "magick_rust")]
     fn camera_map(&self) -> Result<HashMap<String, Camera>> {
         self.cameras().map(|v| {
             v.into_iter()
@@ -283,24 +1304,125 @@ impl Server {
 
     fn router(&self) -> Result<Router> {
         let mut router = Router::new();
+        let station = self.default_station();
         router.get("/",
-                   try!(IndexHandler::new(self.heartbeats.clone(),
-                                          try!(self.cameras()),
-                                          &self.config.server.active_camera,
-                                          try!(self.img_url()))));
+                   try!(IndexHandler::new(station.heartbeats.clone(),
+                                          station.cameras.clone(),
+                                          station.active_camera
+                                              .as_ref()
+                                              .map(|s| s.as_str())
+                                              .unwrap_or(""),
+                                          try!(self.img_url())))
+                       .health(self.catalogs.clone(), self.quality_thresholds()));
         router.get("/status",
-                   try!(StatusHandler::new(self.heartbeats.clone(),
-                                           self.iridium_dir(),
-                                           self.imeis().clone())));
+                   StatusHandler::new(station.heartbeats.clone(),
+                                      self.messages.clone(),
+                                      self.imeis()));
+        let mut messages = MessageBrowser::new(self.messages.clone(), station.heartbeats.clone());
+        for other in &self.stations[1..] {
+            messages = messages.heartbeats(other.heartbeats.clone());
+        }
+        router.get("/messages", messages.page_handler());
+        router.get("/messages/:id/raw", messages.raw_handler());
+        router.get("/link",
+                   LinkHandler::new(self.messages.clone(), self.link_pricing()));
+        router.get("/link.csv",
+                   LinkCsvHandler::new(self.messages.clone(), self.link_pricing()));
+        router.get("/locations", LocationsHandler::new(self.messages.clone()));
         router.get("/soc.csv",
-                   CsvHandler::new(self.heartbeats.clone(), SocCsvProvider));
+                   CsvHandler::new(station.heartbeats.clone(), SocCsvProvider));
         router.get("/temperature.csv",
-                   CsvHandler::new(self.heartbeats.clone(), TemperatureCsvProvider));
+                   CsvHandler::new(station.heartbeats.clone(), TemperatureCsvProvider));
+        let api = Api::new(station.heartbeats.clone(), self.messages.clone());
+        router.get("/api/v1/heartbeats", api.heartbeats_handler());
+        router.get("/api/v1/heartbeats/latest", api.latest_handler());
+        router.get("/api/v1/heartbeats/:datetime", api.heartbeat_handler());
+        router.get("/cameras/:name/index.json",
+                   CatalogHandler::new(self.catalogs.clone()));
+        router.get("/cameras/:name/quality.csv",
+                   QualityCsvHandler::new(self.catalogs.clone()));
+        let browser = Browser::new(try!(self.cameras())
+                                       .into_iter()
+                                       .map(|c| {
+                                           let images = self.catalogs[c.name()].clone();
+                                           (c, images)
+                                       })
+                                       .collect(),
+                                   try!(self.img_url()));
+        router.get("/cameras/:name", browser.page_handler());
+        router.get("/cameras/:name/images.json", browser.images_handler());
+        router.get("/cameras/:name/calendar.json", browser.calendar_handler());
+        if let Some(cache) = self.thumbnail_cache() {
+            router.get("/cameras/:name/:file",
+                       ThumbnailHandler::new(try!(self.cameras()), cache, try!(self.img_url())));
+        }
+        if !self.changes.is_empty() {
+            router.get("/cameras/:name/change.csv",
+                       ChangeCsvHandler::new(self.changes.clone()));
+        }
+        if let Some(ref queue) = self.timelapses {
+            router.get("/cameras/:name/timelapse",
+                       TimelapseHandler::new(queue.clone()));
+            router.get("/timelapses/:id", TimelapseFileHandler::new(queue.clone()));
+            router.get("/timelapses/:id/status",
+                       TimelapseStatusHandler::new(queue.clone()));
+        }
+        let tokens = self.upload_tokens();
+        if !tokens.is_empty() {
+            router.put("/cameras/:name/upload",
+                       UploadHandler::new(try!(self.cameras()), tokens.clone()));
+            router.post("/cameras/:name/upload",
+                        UploadHandler::new(try!(self.cameras()), tokens));
+        }
+        router.get("/sutron", SutronHandler::new(station.sutron_records.clone()));
+        router.get("/sutron/scans.csv",
+                   ScanCsvHandler::new(station.sutron_records.clone()));
 
+        try!(self.add_station_handlers(&mut router));
         try!(self.add_gif_handler(&mut router));
         Ok(router)
     }
 
+    /// Adds the stations overview page and each station's pages under `/stations/<id>`.
+    fn add_station_handlers(&self, router: &mut Router) -> Result<()> {
+        router.get("/stations",
+                   StationsHandler::new(self.stations.clone(), self.messages.clone()));
+        for station in &self.stations {
+            let prefix = format!("/stations/{}", station.id);
+            router.get(prefix.clone(),
+                       StationHandler::new(station.clone(),
+                                           self.messages.clone(),
+                                           try!(self.img_url())));
+            router.get(format!("{}/soc.csv", prefix),
+                       CsvHandler::new(station.heartbeats.clone(), SocCsvProvider));
+            router.get(format!("{}/temperature.csv", prefix),
+                       CsvHandler::new(station.heartbeats.clone(), TemperatureCsvProvider));
+            if station.sutron_dir.is_some() {
+                router.get(format!("{}/sutron", prefix),
+                           SutronHandler::new(station.sutron_records.clone()));
+                router.get(format!("{}/sutron/scans.csv", prefix),
+                           ScanCsvHandler::new(station.sutron_records.clone()));
+            }
+            let api = Api::new(station.heartbeats.clone(), self.messages.clone());
+            router.get(format!("{}/api/v1/heartbeats", prefix),
+                       api.heartbeats_handler());
+            router.get(format!("{}/api/v1/heartbeats/latest", prefix),
+                       api.latest_handler());
+            router.get(format!("{}/api/v1/heartbeats/:datetime", prefix),
+                       api.heartbeat_handler());
+        }
+        Ok(())
+    }
+
+    /// Returns the tokens that may upload images. Uploads are disabled if there are none.
+    fn upload_tokens(&self) -> Vec<String> {
+        self.config
+            .upload
+            .as_ref()
+            .map(|u| u.tokens.iter().filter(|t| !t.is_empty()).cloned().collect())
+            .unwrap_or_else(Vec::new)
+    }
+
     fn staticfiles(&self) -> Static {
         Static::new(self.resource_path("static"))
     }
@@ -309,8 +1431,8 @@ impl Server {
         let mut hbse = HandlebarsEngine::new();
         let template_path = self.resource_path("templates");
         hbse.add(Box::new(DirectorySource::new(&template_path.to_string_lossy(), ".hbs")));
-        // FIXME
-        hbse.reload().unwrap();
+        try!(hbse.reload()
+            .map_err(|e| Error::ServerConfigError(format!("Template error: {}", e))));
         Ok(hbse)
     }
 
@@ -321,74 +1443,375 @@ impl Server {
         logger::Logger::new(format)
     }
 
-    fn start_heartbeat_watcher(&self) {
-        let heartbeats = self.heartbeats.clone();
-        let mut watcher =
-            HeartbeatWatcher::new(self.iridium_dir(), self.imeis().clone(), heartbeats);
-        thread::spawn(move || {
-            watcher.refresh().unwrap();
-            watcher.watch().unwrap();
-        });
+    /// Starts every watcher thread and the timelapse worker.
+    ///
+    /// Watcher threads that are already running with the same configuration are left alone, and
+    /// ones that are no longer needed are stopped.
+    fn start_watchers(&mut self) -> Result<()> {
+        let mut spawner = Spawner::new(mem::replace(&mut self.watchers, HashMap::new()));
+        let result = self.spawn_watchers(&mut spawner);
+        self.watchers = spawner.finish();
+        self.start_timelapse_worker();
+        result
+    }
+
+    fn spawn_watchers(&self, spawner: &mut Spawner) -> Result<()> {
+        self.start_heartbeat_watcher(spawner);
+        self.start_message_watcher(spawner);
+        self.start_sutron_watchers(spawner);
+        try!(self.start_catalog_watchers(spawner));
+        try!(self.start_thumbnail_watchers(spawner));
+        try!(self.start_change_watchers(spawner));
+        try!(self.start_gif_watcher(spawner));
+        self.start_archivers(spawner)
+    }
+
+    fn messages_fingerprint(&self) -> String {
+        format!("{:?}", self.iridium_dir())
+    }
+
+    fn heartbeats_fingerprint(&self) -> String {
+        let stations = self.stations.iter().map(|s| (&s.id, &s.imeis)).collect::<Vec<_>>();
+        format!("{:?} {:?}", self.iridium_dir(), stations)
+    }
+
+    /// The gif watchers depend on the gif config, and on the heartbeats used for the overlay.
+    fn gif_fingerprint(&self, camera: &Camera) -> String {
+        format!("{:?} {:?} {}",
+                camera,
+                self.config.gif,
+                self.heartbeats_fingerprint())
+    }
+
+    fn start_message_watcher(&self, spawner: &mut Spawner) {
+        let watcher = MessageWatcher::new(self.iridium_dir(), self.messages.clone());
+        spawner.watch("messages".to_string(), self.messages_fingerprint(), watcher);
+    }
+
+    /// Starts one watcher for every station's heartbeats, so the Iridium directory is only read
+    /// once per refresh.
+    fn start_heartbeat_watcher(&self, spawner: &mut Spawner) {
+        let station = self.default_station();
+        let mut watcher = HeartbeatWatcher::new(self.iridium_dir(),
+                                                station.imeis.clone(),
+                                                station.heartbeats.clone());
+        for station in &self.stations[1..] {
+            watcher = watcher.station(station.imeis.clone(), station.heartbeats.clone());
+        }
+        spawner.watch("heartbeats".to_string(), self.heartbeats_fingerprint(), watcher);
+    }
+
+    fn start_sutron_watchers(&self, spawner: &mut Spawner) {
+        for station in &self.stations {
+            if let Some(ref sutron_dir) = station.sutron_dir {
+                let watcher = SutronWatcher::new(sutron_dir, station.sutron_records.clone());
+                spawner.watch(format!("sutron:{}", station.id),
+                              sutron_fingerprint(station),
+                              watcher);
+            }
+        }
+    }
+
+    fn start_catalog_watchers(&self, spawner: &mut Spawner) -> Result<()> {
+        for camera in try!(self.cameras()) {
+            let catalog = self.catalogs[camera.name()].clone();
+            let key = format!("catalog:{}", camera.name());
+            let fingerprint = format!("{:?}", camera);
+            spawner.watch(key, fingerprint, CatalogWatcher::new(camera, catalog));
+        }
+        Ok(())
+    }
+
+    fn start_thumbnail_watchers(&self, spawner: &mut Spawner) -> Result<()> {
+        if let Some(cache) = self.thumbnail_cache() {
+            for camera in try!(self.cameras()) {
+                let key = format!("thumbnails:{}", camera.name());
+                let fingerprint = format!("{:?} {:?}", camera, cache);
+                spawner.watch(key, fingerprint, ThumbnailWatcher::new(camera, cache.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    fn start_change_watchers(&self, spawner: &mut Spawner) -> Result<()> {
+        for camera in try!(self.cameras()) {
+            if let Some(&(detector, ref scores)) = self.changes.get(camera.name()) {
+                let key = format!("change:{}", camera.name());
+                let fingerprint = format!("{:?} {:?}", camera, detector);
+                spawner.watch(key,
+                              fingerprint,
+                              ChangeWatcher::new(camera, detector, scores.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    fn start_archivers(&self, spawner: &mut Spawner) -> Result<()> {
+        if let Some(policy) = try!(self.archive_policy()) {
+            for camera in try!(self.cameras()) {
+                let key = format!("archive:{}", camera.name());
+                let fingerprint = format!("{:?} {:?}", camera, policy);
+                spawner.spawn(key,
+                              fingerprint,
+                              move |stop| Archiver::new(camera, policy).stop(stop).run());
+            }
+        }
+        Ok(())
+    }
+
+    fn start_timelapse_worker(&mut self) {
+        if let Some(worker) = self.timelapse_worker.take() {
+            thread::spawn(move || worker.run());
+        }
     }
 
-    #[cfg(feature = "magick_rust")]
     fn add_gif_handler(&self, router: &mut Router) -> Result<()> {
         let mut cameras = try!(self.camera_map());
         for name in self.config.gif.names.iter() {
-            match cameras.remove(name) {
-                Some(camera) => {
-                    router.get(format!("/{}.gif", camera.name().to_ascii_lowercase()),
-                               GifHandler::new(self.gifs[camera.name()].clone()));
-                }
-                None => {
-                    return Err(Error::ServerConfigError(format!("Invalid camera name in gif \
-                                                                 config: {}",
-                                                                name)))
+            if let Some(camera) = cameras.remove(name) {
+                for encoder in try!(self.animation_encoders()) {
+                    let file_name = animation_file_name(camera.name(), encoder.as_ref());
+                    router.get(format!("/{}", file_name),
+                               AnimationHandler::new(self.animations[&file_name].clone(),
+                                                     encoder.content_type()));
                 }
             }
         }
         Ok(())
     }
 
-    #[cfg(not(feature = "magick_rust"))]
-    fn add_gif_handler(&self, _: &mut Router) -> Result<()> {
-        Ok(())
+    /// Returns the gif encoder followed by an encoder for each configured video format.
+    fn animation_encoders(&self) -> Result<Vec<Box<AnimationEncoder>>> {
+        let mut encoders = vec![match self.config.gif.encoder {
+                                    Some(ref name) => try!(animation::encoder(name)),
+                                    None => animation::default_encoder(),
+                                }];
+        if let Some(ref videos) = self.config.gif.videos {
+            for video in videos {
+                let mut encoder = FfmpegEncoder::new(try!(video.parse::<VideoFormat>()));
+                if let Some(ref ffmpeg) = self.config.gif.ffmpeg {
+                    encoder = encoder.program(ffmpeg);
+                }
+                encoders.push(Box::new(encoder));
+            }
+        }
+        Ok(encoders)
+    }
+
+    fn gif_selection(&self, name: &str) -> Result<Selection> {
+        match self.config.gif.selection.as_ref().and_then(|s| s.get(name)) {
+            Some(config) => config.to_selection(),
+            None => Ok(Default::default()),
+        }
     }
 
-    #[cfg(feature = "magick_rust")]
-    fn start_gif_watcher(&self) -> Result<()> {
+    fn gif_overlay(&self, name: &str) -> Result<Option<Overlay>> {
+        let config = match self.config.gif.overlay {
+            Some(ref config) => config,
+            None => return Ok(None),
+        };
+        let mut overlay = try!(Overlay::from_path(&config.font))
+            .heartbeats(self.camera_station(name).heartbeats.clone());
+        if let Some(size) = config.size {
+            overlay = overlay.size(size);
+        }
+        if let Some(ref position) = config.position {
+            overlay = overlay.position(try!(position.parse::<Position>()));
+        }
+        if let Some(ref text) = config.text {
+            overlay = overlay.text(text.as_str());
+        }
+        if let Some(ref datetime_format) = config.datetime_format {
+            overlay = overlay.datetime_format(datetime_format.as_str());
+        }
+        Ok(Some(overlay))
+    }
+
+    fn start_gif_watcher(&self, spawner: &mut Spawner) -> Result<()> {
         let mut cameras = try!(self.camera_map());
-        let gif_config = magick::GifConfig {
+        let gif_config = animation::GifConfig {
             width: self.config.gif.width,
             height: self.config.gif.height,
             delay: Duration::milliseconds(self.config.gif.delay),
         };
         for name in self.config.gif.names.iter() {
-            match cameras.remove(name) {
-                Some(camera) => {
-                    let mut watcher = GifWatcher::new(camera,
-                                                      Duration::days(self.config.gif.days),
-                                                      gif_config,
-                                                      self.gifs[name].clone());
-                    thread::spawn(move || {
-                        watcher.refresh().unwrap();
-                        watcher.watch().unwrap();
-                    });
-                }
-                None => {
-                    return Err(Error::ServerConfigError(format!("Could not start gif watcher \
-                                                                 for camera: {}",
-                                                                name)))
+            if let Some(camera) = cameras.remove(name) {
+                for encoder in try!(self.animation_encoders()) {
+                    let file_name = animation_file_name(name, encoder.as_ref());
+                    let animation = self.animations[&file_name].clone();
+                    let mut animator = Animator::new(camera.clone(), gif_config, encoder)
+                        .selection(try!(self.gif_selection(name)));
+                    if let Some(overlay) = try!(self.gif_overlay(name)) {
+                        animator = animator.overlay(overlay);
+                    }
+                    let watcher = AnimationWatcher::new(animator,
+                                                        Duration::days(self.config.gif.days),
+                                                        animation);
+                    spawner.watch(format!("gif:{}", file_name),
+                                  self.gif_fingerprint(&camera),
+                                  watcher);
                 }
             }
         }
         Ok(())
     }
+}
 
-    #[cfg(not(feature = "magick_rust"))]
-    fn start_gif_watcher(&self) -> Result<()> {
-        Ok(())
+fn sutron_fingerprint(station: &Station) -> String {
+    format!("{:?}", station.sutron_dir)
+}
+
+/// Starts watcher threads, reusing the threads that are already running with the same
+/// configuration.
+struct Spawner {
+    previous: Watchers,
+    running: Watchers,
+}
+
+impl Spawner {
+    fn new(previous: Watchers) -> Spawner {
+        Spawner {
+            previous: previous,
+            running: HashMap::new(),
+        }
+    }
+
+    /// Runs `f` in a new thread, unless there's already one running for the same key and
+    /// fingerprint.
+    ///
+    /// `f` is given the flag that is set when the thread should stop. If a thread with another
+    /// fingerprint is running for the same key, it's stopped, and `f` only starts once that
+    /// thread has exited, so the two never work on the same files at once.
+    fn spawn<F>(&mut self, key: String, fingerprint: String, f: F)
+        where F: FnOnce(Arc<AtomicBool>) + Send + 'static
+    {
+        let mut replaced = None;
+        if let Some((previous, stop, thread)) = self.previous.remove(&key) {
+            if previous == fingerprint {
+                self.running.insert(key, (fingerprint, stop, thread));
+                return;
+            }
+            info!("Restarting {}", key);
+            stop.store(true, Ordering::SeqCst);
+            replaced = Some(thread);
+        }
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let thread = thread::spawn(move || {
+            if let Some(thread) = replaced {
+                let _ = thread.join();
+            }
+            f(thread_stop)
+        });
+        self.running.insert(key, (fingerprint, stop, thread));
+    }
+
+    /// Refreshes and then watches with `watcher` in a new thread, like `spawn`.
+    ///
+    /// A failed first refresh is logged, and the watcher keeps watching so the next change in
+    /// the directory can succeed, just like a failed refresh while watching.
+    fn watch<W>(&mut self, key: String, fingerprint: String, watcher: W)
+        where W: DirectoryWatcher + Send + 'static
+    {
+        self.spawn(key, fingerprint, move |stop| {
+            let mut watcher = Stoppable::new(watcher, stop);
+            if let Err(err) = watcher.refresh() {
+                error!("Error while refreshing in {}: {}",
+                       watcher.directory().to_string_lossy(),
+                       err);
+            }
+            watcher.watch().unwrap();
+        });
+    }
+
+    /// Stops the previous threads that weren't reused, and returns the running threads.
+    fn finish(self) -> Watchers {
+        for (key, (_, stop, _)) in self.previous {
+            info!("Stopping {}", key);
+            stop.store(true, Ordering::SeqCst);
+        }
+        self.running
+    }
+}
+
+/// Serves requests with a handler that can be swapped out while the server runs.
+///
+/// The lock is only held long enough to clone the current handler, so a slow request never holds
+/// up a reload.
+struct ReloadableHandler {
+    handler: Arc<RwLock<Arc<Handler>>>,
+}
+
+impl Handler for ReloadableHandler {
+    fn handle(&self, request: &mut Request) -> IronResult<Response> {
+        let handler = self.handler.read().unwrap().clone();
+        handler.handle(request)
+    }
+}
+
+/// Watches the config file, and reloads the server when it changes.
+struct ConfigWatcher {
+    directory: PathBuf,
+    modified: Option<SystemTime>,
+    server: Server,
+    handler: Arc<RwLock<Arc<Handler>>>,
+}
+
+impl ConfigWatcher {
+    fn new(server: Server, handler: Arc<RwLock<Arc<Handler>>>) -> Result<ConfigWatcher> {
+        let modified = try!(fs::metadata(&server.config_file)).modified().ok();
+        let directory = match server.config_file.parent() {
+            Some(parent) if parent != Path::new("") => parent.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        Ok(ConfigWatcher {
+            directory: directory,
+            modified: modified,
+            server: server,
+            handler: handler,
+        })
+    }
+}
+
+impl DirectoryWatcher for ConfigWatcher {
+    fn directory(&self) -> &Path {
+        &self.directory
     }
+
+    fn refresh(&mut self) -> Result<()> {
+        let modified = try!(fs::metadata(&self.server.config_file)).modified().ok();
+        if modified == self.modified {
+            return Ok(());
+        }
+        self.modified = modified;
+        let config_file = self.server.config_file.to_string_lossy().into_owned();
+        match self.server.reload() {
+            Ok(handler) => {
+                *self.handler.write().unwrap() = handler;
+                info!("Reloaded {}", config_file);
+                Ok(())
+            }
+            Err(Error::InvalidConfig(problems)) => {
+                error!("Keeping the current configuration, {} has problems:", config_file);
+                for problem in problems {
+                    error!("{}: {}", config_file, problem);
+                }
+                Ok(())
+            }
+            Err(err) => Err(err),
+        }
+    }
+}
+
+fn read_config<P: AsRef<Path>>(config_file: P) -> Result<String> {
+    let mut source = String::new();
+    let mut file = try!(File::open(config_file));
+    try!(file.read_to_string(&mut source));
+    Ok(source)
+}
+
+fn animation_file_name(camera_name: &str, encoder: &AnimationEncoder) -> String {
+    format!("{}.{}", camera_name.to_ascii_lowercase(), encoder.extension())
 }
 
 /// The main page for the atlas status site, http://atlas.lidar.io.
@@ -398,6 +1821,8 @@ pub struct IndexHandler {
     cameras: Vec<Camera>,
     active_camera: String,
     url: Url,
+    catalogs: HashMap<String, Arc<RwLock<Vec<ImageInfo>>>>,
+    thresholds: Thresholds,
 }
 
 impl IndexHandler {
@@ -447,8 +1872,41 @@ impl IndexHandler {
             cameras: cameras,
             active_camera: active_camera.to_string(),
             url: img_url,
+            catalogs: HashMap::new(),
+            thresholds: Thresholds::default(),
         })
     }
+
+    /// Uses camera catalogs, keyed by camera name, to flag degraded cameras on the index page.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate url;
+    /// # extern crate atlas;
+    /// # use std::collections::HashMap;
+    /// # use std::sync::{Arc, RwLock};
+    /// # use atlas::server::IndexHandler;
+    /// use atlas::cam::Camera;
+    /// # fn main() {
+    /// let heartbeats = Arc::new(RwLock::new(Vec::new()));
+    /// let url = url::Url::parse("http://iridiumcam.lidar.io").unwrap();
+    /// let cameras = vec![Camera::new("ATLAS_CAM", "data").unwrap()];
+    /// let mut catalogs = HashMap::new();
+    /// catalogs.insert("ATLAS_CAM".to_string(), Arc::new(RwLock::new(Vec::new())));
+    /// let handler = IndexHandler::new(heartbeats, cameras, "ATLAS_CAM", url)
+    ///     .unwrap()
+    ///     .health(catalogs, Default::default());
+    /// # }
+    /// ```
+    pub fn health(mut self,
+                  catalogs: HashMap<String, Arc<RwLock<Vec<ImageInfo>>>>,
+                  thresholds: Thresholds)
+                  -> IndexHandler {
+        self.catalogs = catalogs;
+        self.thresholds = thresholds;
+        self
+    }
 }
 
 impl Handler for IndexHandler {
@@ -487,9 +1945,20 @@ impl Handler for IndexHandler {
                                        format!("latest_image_{}", c.name().to_ascii_lowercase())
                                            .to_json());
                             map.insert("name".to_string(), c.name().to_json());
+                            map.insert("browse".to_string(),
+                                       format!("cameras/{}", c.name().to_ascii_lowercase())
+                                           .to_json());
                             if c.name() == self.active_camera {
                                 map.insert("active".to_string(), "active".to_json());
                             }
+                            if let Some(catalog) = self.catalogs.get(c.name()) {
+                                let problems = quality::degraded(&catalog.read().unwrap(),
+                                                                 &self.thresholds);
+                                if !problems.is_empty() {
+                                    map.insert("degraded".to_string(),
+                                               problems.join(", ").to_json());
+                                }
+                            }
                             Some(map)
                         })
                     })
@@ -513,7 +1982,7 @@ impl Handler for IndexHandler {
 #[derive(Debug)]
 pub struct StatusHandler {
     heartbeats: Arc<RwLock<Vec<HeartbeatV1>>>,
-    storage: FilesystemStorage,
+    messages: Arc<RwLock<Vec<MessageInfo>>>,
     imeis: Vec<String>,
 }
 
@@ -526,19 +1995,20 @@ impl StatusHandler {
     /// # use std::sync::{Arc, RwLock};
     /// # use atlas::server::StatusHandler;
     /// let heartbeats = Arc::new(RwLock::new(Vec::new()));
+    /// let messages = Arc::new(RwLock::new(Vec::new()));
     /// let handler = StatusHandler::new(heartbeats,
-    ///                                  "/var/iridium",
+    ///                                  messages,
     ///                                  vec!["300234063909200".to_string()]);
     /// ```
-    pub fn new<P: AsRef<Path>>(heartbeats: Arc<RwLock<Vec<HeartbeatV1>>>,
-                               iridium_dir: P,
-                               imeis: Vec<String>)
-                               -> Result<StatusHandler> {
-        Ok(StatusHandler {
+    pub fn new(heartbeats: Arc<RwLock<Vec<HeartbeatV1>>>,
+               messages: Arc<RwLock<Vec<MessageInfo>>>,
+               imeis: Vec<String>)
+               -> StatusHandler {
+        StatusHandler {
             heartbeats: heartbeats,
-            storage: try!(FilesystemStorage::open(iridium_dir)),
+            messages: messages,
             imeis: imeis,
-        })
+        }
     }
 }
 
@@ -548,18 +2018,9 @@ impl Handler for StatusHandler {
         let mut response = Response::new();
 
         let mut modems = Vec::new();
+        let all_messages = self.messages.read().unwrap();
         for (i, imei) in self.imeis.iter().enumerate() {
-            let mut messages = self.storage
-                .iter()
-                .filter_map(|r| {
-                    r.ok().and_then(|m| if m.imei() == imei {
-                        Some(m)
-                    } else {
-                        None
-                    })
-                })
-                .collect::<Vec<_>>();
-            messages.sort();
+            let messages = all_messages.iter().filter(|m| &m.imei == imei).collect::<Vec<_>>();
             let mut modem = BTreeMap::<String, Json>::new();
             modem.insert("imei".to_string(), imei.to_json());
             modem.insert("id".to_string(), format!("imei_{}", imei).to_json());
@@ -571,9 +2032,9 @@ impl Handler for StatusHandler {
             for message in messages.into_iter().rev().take(100) {
                 let mut data = BTreeMap::new();
                 data.insert("datetime".to_string(),
-                            message.time_of_session().to_string().to_json());
+                            message.time_of_session.to_string().to_json());
                 data.insert("payload".to_string(),
-                            itry!(message.payload_str()).to_json());
+                            String::from_utf8_lossy(&message.payload).into_owned().to_json());
                 message_json.push(data);
             }
             modem.insert("messages".to_string(), message_json.to_json());
@@ -674,9 +2135,236 @@ impl CsvProvider for TemperatureCsvProvider {
     }
 }
 
+/// The maximum number of records shown on the Sutron page.
+const MAX_SUTRON_RECORDS: usize = 1000;
+
+/// An Iron handler that displays records from the Sutron logs.
+///
+/// The records can be filtered by record type and date using the `type`, `start`, and `end`
+/// query parameters. Dates are formatted `%Y-%m-%d`, and both `start` and `end` are inclusive.
+#[derive(Debug)]
+pub struct SutronHandler {
+    records: Arc<RwLock<Vec<sutron::Record>>>,
+}
+
+impl SutronHandler {
+    /// Creates a new Sutron handler.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use std::sync::{Arc, RwLock};
+    /// # use atlas::server::SutronHandler;
+    /// let records = Arc::new(RwLock::new(Vec::new()));
+    /// let handler = SutronHandler::new(records);
+    /// ```
+    pub fn new(records: Arc<RwLock<Vec<sutron::Record>>>) -> SutronHandler {
+        SutronHandler { records: records }
+    }
+}
+
+impl Handler for SutronHandler {
+    fn handle(&self, request: &mut Request) -> IronResult<Response> {
+        let params = query::params(request);
+        let record_type = params.get("type");
+        let start = match params.get("start") {
+            Some(s) => Some(itry!(query::parse_day_start(s), status::BadRequest)),
+            None => None,
+        };
+        let end = match params.get("end") {
+            Some(s) => {
+                Some(itry!(query::parse_day_start(s), status::BadRequest) + Duration::days(1))
+            }
+            None => None,
+        };
+
+        let records = self.records.read().unwrap();
+        let types = records.iter().map(|r| r.record_type()).collect::<BTreeSet<_>>();
+        let records = records.iter()
+            .filter(|r| {
+                record_type.map_or(true, |t| r.record_type() == t) &&
+                start.map_or(true, |s| r.datetime >= s) && end.map_or(true, |e| r.datetime < e)
+            })
+            .collect::<Vec<_>>();
+
+        let mut data = BTreeMap::<String, Json>::new();
+        data.insert("types".to_string(),
+                    types.into_iter()
+                        .map(|t| {
+                            let mut map = BTreeMap::<String, Json>::new();
+                            map.insert("name".to_string(), t.to_json());
+                            if record_type.map_or(false, |r| r == t) {
+                                map.insert("selected".to_string(), "selected".to_json());
+                            }
+                            map
+                        })
+                        .collect::<Vec<_>>()
+                        .to_json());
+        data.insert("start".to_string(), params.get("start").cloned().to_json());
+        data.insert("end".to_string(), params.get("end").cloned().to_json());
+        data.insert("nrecords".to_string(), records.len().to_json());
+        data.insert("records".to_string(),
+                    records.iter()
+                        .rev()
+                        .take(MAX_SUTRON_RECORDS)
+                        .map(|r| {
+                            let mut map = BTreeMap::<String, Json>::new();
+                            map.insert("datetime".to_string(), r.datetime.to_string().to_json());
+                            map.insert("type".to_string(), r.record_type().to_json());
+                            map.insert("data".to_string(), r.data.to_json());
+                            map
+                        })
+                        .collect::<Vec<_>>()
+                        .to_json());
+
+        let mut response = Response::new();
+        response.set_mut(Template::new("sutron", data)).set_mut(status::Ok);
+        Ok(response)
+    }
+}
+
+/// An Iron handler that returns the Sutron scan sessions as CSV.
+#[derive(Debug)]
+pub struct ScanCsvHandler {
+    records: Arc<RwLock<Vec<sutron::Record>>>,
+}
+
+impl ScanCsvHandler {
+    /// Creates a new scan session CSV handler.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use std::sync::{Arc, RwLock};
+    /// # use atlas::server::ScanCsvHandler;
+    /// let records = Arc::new(RwLock::new(Vec::new()));
+    /// let handler = ScanCsvHandler::new(records);
+    /// ```
+    pub fn new(records: Arc<RwLock<Vec<sutron::Record>>>) -> ScanCsvHandler {
+        ScanCsvHandler { records: records }
+    }
+}
+
+impl Handler for ScanCsvHandler {
+    fn handle(&self, _: &mut Request) -> IronResult<Response> {
+        let mut response = Response::new();
+        response.status = Some(status::Ok);
+        response.headers
+            .set(ContentType(Mime(TopLevel::Text, SubLevel::Ext("csv".to_string()), vec![])));
+        let mut data = String::new();
+
+        writeln!(&mut data, "Scan on,Scan start,Scan end,Outcome,Reason").unwrap();
+        for session in scan_sessions(&self.records.read().unwrap()) {
+            let reason = match session.outcome {
+                sutron::ScanOutcome::Skipped(ref reason) => reason.as_str(),
+                _ => "",
+            };
+            writeln!(&mut data,
+                     "{},{},{},{},\"{}\"",
+                     session.on,
+                     session.start.map(|d| d.to_string()).unwrap_or(String::new()),
+                     session.end.map(|d| d.to_string()).unwrap_or(String::new()),
+                     session.outcome,
+                     reason.replace('"', "\"\""))
+                .unwrap();
+        }
+        response.body = Some(Box::new(data));
+        Ok(response)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use super::{Configuration, ReloadableHandler, Spawner};
+    use {Error, Result};
+
+    use std::collections::HashMap;
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::{Read, Write};
+    use std::net::{SocketAddr, TcpStream};
+    use std::path::{Path, PathBuf};
+    use std::sync::{Arc, RwLock};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::mpsc::channel;
+    use std::thread;
+    use std::time::Duration as StdDuration;
+
+    use chrono::{Duration, NaiveTime};
+
+    use iron::Iron;
+
+    use iridium::MessageInfo;
+    use thumbnail::Size;
+    use watch::DirectoryWatcher;
+
+    /// A watcher whose refreshes always fail.
+    struct FailingWatcher(PathBuf);
+
+    impl DirectoryWatcher for FailingWatcher {
+        fn directory(&self) -> &Path {
+            &self.0
+        }
+
+        fn refresh(&mut self) -> Result<()> {
+            Err(Error::ServerConfigError("always fails".to_string()))
+        }
+    }
+
+    fn write_config(name: &str, source: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("atlas-config-{}.toml", name));
+        File::create(&path).unwrap().write_all(source.as_bytes()).unwrap();
+        path
+    }
+
+    /// Copies the ATLAS_CAM images into a new directory, renamed for the named camera.
+    fn camera_directory(test: &str, camera: &str) -> PathBuf {
+        let directory = env::temp_dir().join(format!("atlas-{}", test)).join(camera);
+        fs::create_dir_all(&directory).unwrap();
+        for suffix in &["20160725_121500.jpg", "20160725_141500.jpg"] {
+            fs::copy(format!("data/ATLAS_CAM_{}", suffix),
+                     directory.join(format!("{}_{}", camera, suffix)))
+                .unwrap();
+        }
+        directory
+    }
+
+    /// A config that passes `check_config`, with the given cameras and their directories.
+    fn reload_config(cameras: &[(&str, &Path)]) -> String {
+        let mut source = format!("[server]
+ip = \"127.0.0.1\"
+port = 3000
+resource_dir = \".\"
+iridium_dir = \"data\"
+imeis = [\"300234063909200\"]
+img_url = \"http://iridiumcam.lidar.io\"
+active_camera = \"{}\"
+
+[gif]
+days = 7
+delay = 500
+height = 512
+width = 384
+names = []
+",
+                                 cameras[0].0);
+        for &(name, directory) in cameras {
+            source.push_str(&format!("\n[[camera]]\nname = \"{}\"\ndirectory = \"{}\"\n",
+                                     name,
+                                     directory.to_string_lossy()));
+        }
+        source
+    }
+
+    /// Returns the status code of the response to a GET request.
+    fn get(addr: SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "GET {} HTTP/1.0\r\nHost: localhost\r\n\r\n", path).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response.split_whitespace().nth(1).unwrap_or("").to_string()
+    }
 
     #[test]
     fn addr() {
@@ -706,6 +2394,96 @@ mod tests {
                    server.img_url().unwrap().as_str());
     }
 
+    #[test]
+    fn sutron_dir() {
+        let server = Server::new("data/config.toml").unwrap();
+        assert_eq!("/Users/gadomski/atlas/sutron",
+                   server.sutron_dir().unwrap().to_string_lossy());
+    }
+
+    #[test]
+    fn thumbnail_cache() {
+        let server = Server::new("data/config.toml").unwrap();
+        let cache = server.thumbnail_cache().unwrap();
+        assert_eq!(&Size {
+                       name: "small".to_string(),
+                       width: 256,
+                       height: 192,
+                   },
+                   cache.size("small").unwrap());
+    }
+
+    #[test]
+    fn animations() {
+        let server = Server::new("data/config.toml").unwrap();
+        assert_eq!(6, server.animations.len());
+        assert!(server.animations.contains_key("atlas_cam.gif"));
+        assert!(server.animations.contains_key("hel_terminus.mp4"));
+        assert!(server.animations.contains_key("hel_terminus.webm"));
+    }
+
+    #[test]
+    fn quality_thresholds() {
+        let server = Server::new("data/config.toml").unwrap();
+        let thresholds = server.quality_thresholds();
+        assert_eq!(10., thresholds.min_brightness);
+        assert_eq!(20., thresholds.min_sharpness);
+        assert_eq!(6, thresholds.window);
+    }
+
+    #[test]
+    fn changes() {
+        let server = Server::new("data/config.toml").unwrap();
+        assert_eq!(1, server.changes.len());
+        assert_eq!(15., server.changes["HEL_Terminus"].0.threshold());
+    }
+
+    #[test]
+    fn link_pricing() {
+        let server = Server::new("data/config.toml").unwrap();
+        let pricing = server.link_pricing();
+        assert_eq!(20., pricing.monthly_fee);
+        assert_eq!(12000, pricing.included_bytes);
+    }
+
+    #[test]
+    fn archive_policy() {
+        let server = Server::new("data/config.toml").unwrap();
+        let policy = server.archive_policy().unwrap().unwrap();
+        assert_eq!(Duration::days(60), policy.archive_after);
+        assert_eq!(Some(85), policy.quality);
+        assert_eq!(Some(Duration::days(365)), policy.thin_after);
+        assert_eq!(None, policy.delete_after);
+    }
+
+    #[test]
+    fn upload_tokens() {
+        let server = Server::new("data/config.toml").unwrap();
+        assert!(server.upload_tokens().is_empty());
+    }
+
+    #[test]
+    fn timelapses() {
+        let server = Server::new("data/config.toml").unwrap();
+        let queue = server.timelapses.unwrap();
+        assert!(queue.camera("hel_terminus").is_some());
+        assert_eq!(Path::new("/Users/gadomski/atlas/timelapses/foo.gif"),
+                   queue.path("foo.gif"));
+        assert!(server.timelapse_worker.is_some());
+    }
+
+    #[test]
+    fn gif_selection() {
+        let server = Server::new("data/config.toml").unwrap();
+        let selection = server.gif_selection("ATLAS_CAM").unwrap();
+        assert_eq!(Some((NaiveTime::from_hms(10, 0, 0), NaiveTime::from_hms(22, 0, 0))),
+                   selection.window);
+        assert_eq!(Some(12), selection.max_per_day);
+        assert!(selection.skip_corrupt);
+        let selection = server.gif_selection("HEL_Terminus").unwrap();
+        assert!(selection.window.is_none());
+    }
+
     #[test]
     fn resource_path() {
         let server = Server::new("data/config.toml").unwrap();
@@ -728,4 +2506,281 @@ mod tests {
         assert_eq!("/Users/gadomski/iridiumcam/ATLAS_CAM",
                    camera.path().to_string_lossy());
     }
+
+    #[test]
+    fn stations() {
+        let server = Server::new("data/config.toml").unwrap();
+        let stations = server.stations();
+        assert_eq!(1, stations.len());
+        assert_eq!("atlas", stations[0].id);
+        assert_eq!(3, stations[0].cameras.len());
+        assert_eq!(Some("ATLAS_CAM".to_string()), stations[0].active_camera);
+
+        let server = Server::new("data/stations.toml").unwrap();
+        let stations = server.stations();
+        assert_eq!(2, stations.len());
+        assert_eq!("ATLAS-2", stations[1].name);
+        assert_eq!(vec!["300234064737720".to_string()], stations[1].imeis);
+        assert_eq!(Some("ATLAS2_CAM".to_string()), stations[1].active_camera);
+        assert_eq!(None, stations[1].sutron_dir);
+        assert_eq!(3, server.imeis().len());
+        assert_eq!("/Users/gadomski/atlas/sutron",
+                   server.sutron_dir().unwrap().to_string_lossy());
+        assert_eq!("atlas-2", server.camera_station("ATLAS2_CAM").id);
+    }
+
+    /// Returns the problems that keep a server from being created from the config source.
+    fn new_problems(name: &str, source: &str) -> Vec<String> {
+        match Server::new(write_config(name, source)) {
+            Err(Error::InvalidConfig(problems)) => {
+                problems.into_iter().map(|p| p.message).collect()
+            }
+            Err(err) => panic!("Unexpected error: {}", err),
+            Ok(_) => Vec::new(),
+        }
+    }
+
+    #[test]
+    fn first_station_needs_a_camera() {
+        let mut source = String::new();
+        File::open("data/stations.toml").unwrap().read_to_string(&mut source).unwrap();
+        let source = source.replace("cameras = [\"ATLAS_CAM\", \"HEL_Terminus\"]\n", "");
+        assert_eq!(vec!["The first station needs a camera".to_string()],
+                   new_problems("no-camera", &source));
+    }
+
+    #[test]
+    fn camera_in_one_station() {
+        let mut source = String::new();
+        File::open("data/stations.toml").unwrap().read_to_string(&mut source).unwrap();
+        assert!(new_problems("one-station", &source).is_empty());
+        let source = source.replace("cameras = [\"ATLAS2_CAM\"]\n",
+                                    "cameras = [\"ATLAS2_CAM\", \"ATLAS_CAM\"]\n");
+        assert_eq!(vec!["Camera ATLAS_CAM is in more than one station".to_string()],
+                   new_problems("two-stations", &source));
+    }
+
+    #[test]
+    fn check_config() {
+        let source = r#"[server]
+ip = "0.0.0.0"
+port = 3000
+resource_dir = "."
+iridium_dir = "/nonexistent/atlas-iridium"
+imeis = ["300234063909200"]
+img_url = "http://iridiumcam.lidar.io"
+active_camera = "ATLAS_CAM"
+
+[gif]
+days = 7
+delay = 500
+height = 512
+width = 384
+names = []
+
+[[camera]]
+directory = "data"
+name = "ATLAS_CAM"
+"#;
+        let config = Configuration::from_source(source).unwrap();
+        let problems = config.validate(source);
+        assert_eq!(1, problems.len());
+        assert_eq!(Some(5), problems[0].line);
+        assert!(problems[0].message.contains("iridium_dir"));
+    }
+
+    #[test]
+    fn config_problems() {
+        let source = r#"[server]
+ip = "0.0.0.0"
+port = 3000
+resource_dir = "."
+iridium_dir = "data"
+imeis = ["300234063909200", "3002340"]
+img_url = "not a url"
+active_camera = "ATLAS_CAM"
+
+[gif]
+days = 7
+delay = 500
+height = 512
+width = 384
+names = ["ATLAS_CAM", "Nope"]
+
+[[camera]]
+directory = "data"
+name = "ATLAS_CAM"
+
+[[camera]]
+directory = "does/not/exist"
+name = "Missing"
+"#;
+        let config = Configuration::from_source(source).unwrap();
+        let problems = config.validate(source);
+        assert_eq!(vec![Some(7), Some(6), Some(22), Some(15)],
+                   problems.iter().map(|p| p.line).collect::<Vec<_>>());
+        assert!(problems[1].message.contains("3002340"));
+    }
+
+    #[test]
+    fn new_checks_the_config_rules() {
+        let mut source = String::new();
+        File::open("data/config.toml").unwrap().read_to_string(&mut source).unwrap();
+        let path = write_config("rules", &source.replace("ip = \"0.0.0.0\"", "ip = \"nope\""));
+        match Server::new(&path) {
+            Err(Error::InvalidConfig(problems)) => {
+                assert_eq!(vec![Some(2)], problems.iter().map(|p| p.line).collect::<Vec<_>>());
+            }
+            Err(err) => panic!("Unexpected error: {}", err),
+            Ok(_) => panic!("An invalid ip address was accepted"),
+        }
+    }
+
+    #[test]
+    fn config_syntax_errors() {
+        let problems = Configuration::from_source("[server]\nip = \"0.0.0.0\"\nport = \n")
+            .err()
+            .unwrap();
+        assert_eq!(Some(3), problems[0].line);
+        let problems = Configuration::from_source("[server]\nip = \"0.0.0.0\"\nport = \"3000\"\n")
+            .err()
+            .unwrap();
+        assert_eq!(Some(3), problems[0].line);
+    }
+
+    #[test]
+    fn camera_naming() {
+        let server = Server::new("data/config.toml").unwrap();
+        let cameras = server.cameras().unwrap();
+        let camera = &cameras[2];
+        assert_eq!("{name}-{datetime}Z", camera.naming().pattern);
+        assert_eq!(vec!["jpg".to_string(), "jpeg".to_string()],
+                   camera.naming().extensions);
+        assert!(camera.datetime("Hel_Melange-2017-05-01T12-00-00Z.JPG").is_ok());
+    }
+
+    #[test]
+    fn adopt() {
+        let mut old = Server::new("data/config.toml").unwrap();
+        let fingerprint = old.messages_fingerprint();
+        old.watchers.insert("messages".to_string(),
+                            (fingerprint, Arc::new(AtomicBool::new(false)), thread::spawn(|| {})));
+        old.messages.write().unwrap().push(MessageInfo::read("data/160714_000252.sbd").unwrap());
+
+        let mut server = Server::new("data/config.toml").unwrap();
+        server.adopt(&old).unwrap();
+        assert_eq!(1, server.messages.read().unwrap().len());
+
+        old.watchers.insert("messages".to_string(),
+                            ("other".to_string(),
+                             Arc::new(AtomicBool::new(false)),
+                             thread::spawn(|| {})));
+        let mut server = Server::new("data/config.toml").unwrap();
+        server.adopt(&old).unwrap();
+        assert!(server.messages.read().unwrap().is_empty());
+    }
+
+    #[test]
+    fn spawner() {
+        let unchanged = Arc::new(AtomicBool::new(false));
+        let changed = Arc::new(AtomicBool::new(false));
+        let removed = Arc::new(AtomicBool::new(false));
+        let mut previous = HashMap::new();
+        previous.insert("unchanged".to_string(),
+                        ("1".to_string(), unchanged.clone(), thread::spawn(|| {})));
+        previous.insert("changed".to_string(),
+                        ("1".to_string(), changed.clone(), thread::spawn(|| {})));
+        previous.insert("removed".to_string(),
+                        ("1".to_string(), removed.clone(), thread::spawn(|| {})));
+
+        let mut spawner = Spawner::new(previous);
+        spawner.spawn("unchanged".to_string(), "1".to_string(), |_| {});
+        spawner.spawn("changed".to_string(), "2".to_string(), |_| {});
+        spawner.spawn("added".to_string(), "1".to_string(), |_| {});
+        let running = spawner.finish();
+
+        assert!(!unchanged.load(Ordering::SeqCst));
+        assert!(changed.load(Ordering::SeqCst));
+        assert!(removed.load(Ordering::SeqCst));
+        assert_eq!(3, running.len());
+        assert_eq!("2", running["changed"].0);
+        assert!(!running["changed"].1.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn spawner_keeps_watching_after_a_failed_refresh() {
+        let mut spawner = Spawner::new(HashMap::new());
+        spawner.watch("failing".to_string(),
+                      "1".to_string(),
+                      FailingWatcher(env::temp_dir()));
+        let mut running = spawner.finish();
+        thread::sleep(StdDuration::from_millis(100));
+        let (_, stop, thread) = running.remove("failing").unwrap();
+        stop.store(true, Ordering::SeqCst);
+        assert!(thread.join().is_ok());
+    }
+
+    #[test]
+    fn spawner_waits_for_the_replaced_thread() {
+        let stop = Arc::new(AtomicBool::new(false));
+        let exited = Arc::new(AtomicBool::new(false));
+        let (thread_stop, thread_exited) = (stop.clone(), exited.clone());
+        let thread = thread::spawn(move || {
+            while !thread_stop.load(Ordering::SeqCst) {
+                thread::sleep(StdDuration::from_millis(10));
+            }
+            thread::sleep(StdDuration::from_millis(100));
+            thread_exited.store(true, Ordering::SeqCst);
+        });
+        let mut previous = HashMap::new();
+        previous.insert("archive:ATLAS_CAM".to_string(), ("1".to_string(), stop, thread));
+
+        let mut spawner = Spawner::new(previous);
+        let (tx, rx) = channel();
+        spawner.spawn("archive:ATLAS_CAM".to_string(),
+                      "2".to_string(),
+                      move |_| tx.send(exited.load(Ordering::SeqCst)).unwrap());
+        spawner.finish();
+        assert!(rx.recv_timeout(StdDuration::from_secs(10)).unwrap());
+    }
+
+    #[test]
+    fn reload_bad_config() {
+        let directory = camera_directory("reload-bad", "ATLAS_CAM");
+        let path = write_config("reload-bad",
+                                &reload_config(&[("ATLAS_CAM", directory.as_path())]));
+        let mut server = Server::new(&path).unwrap();
+
+        write_config("reload-bad", "[server]\nip = \"0.0.0.0\"\nport = \n");
+        match server.reload() {
+            Err(Error::InvalidConfig(_)) => {}
+            Err(err) => panic!("Unexpected error: {}", err),
+            Ok(_) => panic!("A bad config was reloaded"),
+        }
+        assert_eq!(3000, server.addr().1);
+        assert_eq!(vec!["ATLAS_CAM"],
+                   server.cameras().unwrap().iter().map(|c| c.name()).collect::<Vec<_>>());
+        assert_eq!(1, server.stations().len());
+        assert_eq!("atlas", server.stations()[0].id);
+    }
+
+    #[test]
+    fn reload_new_camera() {
+        let first = camera_directory("reload-new", "ATLAS_CAM");
+        let second = camera_directory("reload-new", "second");
+        let path = write_config("reload-new", &reload_config(&[("ATLAS_CAM", first.as_path())]));
+        let mut server = Server::new(&path).unwrap();
+        let handler = Arc::new(RwLock::new(server.handler().unwrap()));
+        let mut listening = Iron::new(ReloadableHandler { handler: handler.clone() })
+            .http("127.0.0.1:0")
+            .unwrap();
+        assert_eq!("404", get(listening.socket, "/cameras/second/index.json"));
+
+        write_config("reload-new",
+                     &reload_config(&[("ATLAS_CAM", first.as_path()),
+                                      ("second", second.as_path())]));
+        *handler.write().unwrap() = server.reload().unwrap();
+        assert_eq!("200", get(listening.socket, "/cameras/second/index.json"));
+        listening.close().unwrap();
+    }
 }
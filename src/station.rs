@@ -0,0 +1,296 @@
+//! Stations, each a group of Iridium modems, cameras, and Sutron logs.
+//!
+//! ATLAS started out as the only station, so the top-level pages all describe it. One server can
+//! watch over more than one station, though, and each station gets its own page under
+//! `/stations/<id>` with its own heartbeats, CSV files, Sutron logs, and API. The stations page
+//! gives an overview of all of them.
+
+use std::ascii::AsciiExt;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+use chrono::UTC;
+
+use handlebars_iron::Template;
+
+use iron::{Handler, status};
+use iron::prelude::*;
+
+use rustc_serialize::json::{Json, ToJson};
+
+use url::Url;
+
+use {Error, Result};
+use cam::Camera;
+use heartbeat::HeartbeatV1;
+use iridium::MessageInfo;
+use sutron;
+
+/// The heartbeat format versions that we can decode.
+pub const HEARTBEAT_VERSIONS: &'static [u8] = &[1];
+
+/// A station and the data it has sent us.
+#[derive(Clone, Debug)]
+pub struct Station {
+    /// The station's id, used in its urls.
+    pub id: String,
+    /// The station's display name.
+    pub name: String,
+    /// The IMEIs of the station's Iridium modems.
+    pub imeis: Vec<String>,
+    /// The station's cameras.
+    pub cameras: Vec<Camera>,
+    /// The name of the camera to show first, if any.
+    pub active_camera: Option<String>,
+    /// The version of the station's heartbeat format.
+    pub heartbeat_version: u8,
+    /// The directory that holds the station's Sutron log files, if there is one.
+    pub sutron_dir: Option<PathBuf>,
+    /// The station's heartbeats, sorted by datetime.
+    pub heartbeats: Arc<RwLock<Vec<HeartbeatV1>>>,
+    /// The records from the station's Sutron logs.
+    pub sutron_records: Arc<RwLock<Vec<sutron::Record>>>,
+}
+
+impl Station {
+    /// Creates a new station with no cameras and no Sutron logs.
+    ///
+    /// Ids must be lowercase ASCII letters, digits, dashes, or underscores.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use atlas::station::Station;
+    /// let station = Station::new("atlas", "ATLAS", vec!["300234063909200".to_string()]).unwrap();
+    /// assert!(Station::new("Not an id", "ATLAS", Vec::new()).is_err());
+    /// ```
+    pub fn new(id: &str, name: &str, imeis: Vec<String>) -> Result<Station> {
+        let valid = |c: char| {
+            c.is_ascii() && (c.is_lowercase() || c.is_digit(10) || c == '-' || c == '_')
+        };
+        if id.is_empty() || !id.chars().all(valid) {
+            return Err(Error::ServerConfigError(format!("Invalid station id: {}", id)));
+        }
+        Ok(Station {
+            id: id.to_string(),
+            name: name.to_string(),
+            imeis: imeis,
+            cameras: Vec::new(),
+            active_camera: None,
+            heartbeat_version: 1,
+            sutron_dir: None,
+            heartbeats: Arc::new(RwLock::new(Vec::new())),
+            sutron_records: Arc::new(RwLock::new(Vec::new())),
+        })
+    }
+
+    /// Returns true if the named camera belongs to this station.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use atlas::cam::Camera;
+    /// # use atlas::station::Station;
+    /// let mut station = Station::new("atlas", "ATLAS", Vec::new()).unwrap();
+    /// station.cameras.push(Camera::new("ATLAS_CAM", "data").unwrap());
+    /// assert!(station.has_camera("ATLAS_CAM"));
+    /// assert!(!station.has_camera("HEL_Terminus"));
+    /// ```
+    pub fn has_camera(&self, name: &str) -> bool {
+        self.cameras.iter().any(|c| c.name() == name)
+    }
+
+    fn summary_json(&self, messages: &[MessageInfo]) -> BTreeMap<String, Json> {
+        let mut map = BTreeMap::new();
+        map.insert("id".to_string(), self.id.to_json());
+        map.insert("name".to_string(), self.name.to_json());
+        map.insert("heartbeat_version".to_string(), self.heartbeat_version.to_json());
+        let modems = self.imeis
+            .iter()
+            .map(|imei| {
+                let mut modem = BTreeMap::new();
+                let mut nmessages = 0;
+                let mut last_message = None;
+                for message in messages.iter().filter(|m| &m.imei == imei) {
+                    nmessages += 1;
+                    last_message = Some(message.time_of_session);
+                }
+                modem.insert("imei".to_string(), imei.to_json());
+                modem.insert("nmessages".to_string(), nmessages.to_json());
+                modem.insert("last_message".to_string(),
+                             last_message.map(|d| d.to_string()).to_json());
+                modem
+            })
+            .collect::<Vec<_>>();
+        map.insert("modems".to_string(), modems.to_json());
+        map.insert("ncameras".to_string(), self.cameras.len().to_json());
+        if let Some(heartbeat) = self.heartbeats.read().unwrap().last() {
+            map.insert("last_heartbeat".to_string(), heartbeat.datetime().to_string().to_json());
+        }
+        map
+    }
+}
+
+/// Serves the overview page of all stations.
+#[derive(Debug)]
+pub struct StationsHandler {
+    stations: Vec<Station>,
+    messages: Arc<RwLock<Vec<MessageInfo>>>,
+}
+
+impl StationsHandler {
+    /// Creates a new handler for the given stations and message index.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use std::sync::{Arc, RwLock};
+    /// # use atlas::station::{Station, StationsHandler};
+    /// let stations = vec![Station::new("atlas", "ATLAS", Vec::new()).unwrap()];
+    /// let handler = StationsHandler::new(stations, Arc::new(RwLock::new(Vec::new())));
+    /// ```
+    pub fn new(stations: Vec<Station>,
+               messages: Arc<RwLock<Vec<MessageInfo>>>)
+               -> StationsHandler {
+        StationsHandler {
+            stations: stations,
+            messages: messages,
+        }
+    }
+}
+
+impl Handler for StationsHandler {
+    fn handle(&self, _: &mut Request) -> IronResult<Response> {
+        let messages = self.messages.read().unwrap();
+        let stations = self.stations
+            .iter()
+            .map(|s| s.summary_json(&messages))
+            .collect::<Vec<_>>();
+        let mut data = BTreeMap::<String, Json>::new();
+        data.insert("stations".to_string(), stations.to_json());
+        data.insert("now".to_string(),
+                    format!("{}", UTC::now().format("%Y-%m-%d %H:%M:%S UTC")).to_json());
+        let mut response = Response::new();
+        response.set_mut(Template::new("stations", data)).set_mut(status::Ok);
+        Ok(response)
+    }
+}
+
+/// Serves the page of a single station.
+#[derive(Debug)]
+pub struct StationHandler {
+    station: Station,
+    messages: Arc<RwLock<Vec<MessageInfo>>>,
+    url: Url,
+}
+
+impl StationHandler {
+    /// Creates a new handler for a station.
+    ///
+    /// The image url is used to link to the latest image from each of the station's cameras.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate url;
+    /// # extern crate atlas;
+    /// # use std::sync::{Arc, RwLock};
+    /// # use atlas::station::{Station, StationHandler};
+    /// # fn main() {
+    /// let station = Station::new("atlas", "ATLAS", Vec::new()).unwrap();
+    /// let url = url::Url::parse("http://iridiumcam.lidar.io").unwrap();
+    /// let handler = StationHandler::new(station, Arc::new(RwLock::new(Vec::new())), url);
+    /// # }
+    /// ```
+    pub fn new(station: Station,
+               messages: Arc<RwLock<Vec<MessageInfo>>>,
+               img_url: Url)
+               -> StationHandler {
+        StationHandler {
+            station: station,
+            messages: messages,
+            url: img_url,
+        }
+    }
+}
+
+impl Handler for StationHandler {
+    fn handle(&self, _: &mut Request) -> IronResult<Response> {
+        let mut data = self.station.summary_json(&self.messages.read().unwrap());
+        if let Some(heartbeat) = self.station.heartbeats.read().unwrap().last() {
+            data.insert("last_scan_start".to_string(),
+                        heartbeat.scan_start_datetime.to_string().to_json());
+            data.insert("temperature_external".to_string(),
+                        format!("{}", heartbeat.temperature_external).to_json());
+            data.insert("temperature_mount".to_string(),
+                        format!("{}", heartbeat.temperature_mount).to_json());
+            data.insert("pressure".to_string(),
+                        format!("{}", heartbeat.pressure).to_json());
+            data.insert("humidity".to_string(),
+                        format!("{}", heartbeat.humidity).to_json());
+            data.insert("soc1".to_string(), format!("{}", heartbeat.soc1).to_json());
+            data.insert("soc2".to_string(), format!("{}", heartbeat.soc2).to_json());
+        }
+        let cameras = self.station
+            .cameras
+            .iter()
+            .map(|c| {
+                let mut map = BTreeMap::new();
+                map.insert("name".to_string(), c.name().to_json());
+                map.insert("browse".to_string(),
+                           format!("../cameras/{}", c.name().to_ascii_lowercase()).to_json());
+                if let Some(file_name) = c.latest_file_name().ok().and_then(|o| o) {
+                    if let Some(url) = c.url(&self.url, &file_name) {
+                        map.insert("url".to_string(), url.as_str().to_json());
+                    }
+                    if let Ok(datetime) = c.datetime(&file_name) {
+                        map.insert("datetime".to_string(), datetime.to_string().to_json());
+                    }
+                }
+                if self.station.active_camera.as_ref().map_or(false, |a| a == c.name()) {
+                    map.insert("active".to_string(), "active".to_json());
+                }
+                map
+            })
+            .collect::<Vec<_>>();
+        data.insert("cameras".to_string(), cameras.to_json());
+        if self.station.sutron_dir.is_some() {
+            data.insert("sutron".to_string(), true.to_json());
+        }
+        let mut response = Response::new();
+        response.set_mut(Template::new("station", data)).set_mut(status::Ok);
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use iridium::MessageInfo;
+
+    #[test]
+    fn ids() {
+        assert!(Station::new("atlas", "ATLAS", Vec::new()).is_ok());
+        assert!(Station::new("atlas-2_b", "ATLAS 2", Vec::new()).is_ok());
+        assert!(Station::new("", "ATLAS", Vec::new()).is_err());
+        assert!(Station::new("ATLAS", "ATLAS", Vec::new()).is_err());
+        assert!(Station::new("atlas/2", "ATLAS", Vec::new()).is_err());
+    }
+
+    #[test]
+    fn summary() {
+        let station = Station::new("atlas", "ATLAS", vec!["300234063909200".to_string()])
+            .unwrap();
+        let messages = vec![MessageInfo::read("data/160714_000240.sbd").unwrap(),
+                            MessageInfo::read("data/160714_000252.sbd").unwrap()];
+        let summary = station.summary_json(&messages);
+        let modems = summary["modems"].as_array().unwrap();
+        assert_eq!(1, modems.len());
+        assert_eq!(2, modems[0]["nmessages"].as_u64().unwrap());
+        assert_eq!("2016-07-14 00:02:52 UTC",
+                   modems[0]["last_message"].as_string().unwrap());
+        assert!(!summary.contains_key("last_heartbeat"));
+    }
+}
@@ -2,17 +2,23 @@
 //!
 //! These log files are not transmitted back via satellite, but are retrieved when we take trips to
 //! the site.
+//!
+//! Log files can cover a year or more of records, and can be corrupted after power loss, so the
+//! `Reader` streams records one line at a time. `Log` is a convenience wrapper that reads
+//! everything into memory.
 
 use std::error;
 use std::fmt;
 use std::fs::File;
-use std::io::{self, BufRead, BufReader};
+use std::io::{self, BufRead, BufReader, Lines};
 use std::path::Path;
 use std::result;
 use std::str::FromStr;
 
 use chrono::{self, DateTime, TimeZone, UTC};
 
+const HEADER: &'static str = "Station Name";
+
 /// Custom result type for Sutron errors.
 pub type Result<T> = result::Result<T, Error>;
 
@@ -25,8 +31,12 @@ pub enum Error {
     ChronoParse(chrono::ParseError),
     /// Wrapper around `std::io::Error`.
     Io(io::Error),
+    /// An error on a specific (one-based) line of a log file.
+    Line(usize, Box<Error>),
     /// The sutron log is too short.
     LogTooShort,
+    /// Two logs from different stations can't be merged.
+    MismatchedStationNames(String, String),
     /// A record is too short.
     RecordTooShort(usize),
     /// A record is missing the first comma.
@@ -39,7 +49,9 @@ impl error::Error for Error {
             Error::BadLogHeader(_) => "bad log header",
             Error::ChronoParse(ref err) => err.description(),
             Error::Io(ref err) => err.description(),
+            Error::Line(_, ref err) => err.description(),
             Error::LogTooShort => "log is too short",
+            Error::MismatchedStationNames(_, _) => "mismatched station names",
             Error::RecordTooShort(_) => "record is too short",
             Error::RecordMissingComma(_) => "record is missing the first comma",
         }
@@ -52,7 +64,11 @@ impl fmt::Display for Error {
             Error::BadLogHeader(ref s) => write!(f, "bad log header: {}", s),
             Error::ChronoParse(ref err) => write!(f, "chrono error: {}", err),
             Error::Io(ref err) => write!(f, "io error: {}", err),
+            Error::Line(n, ref err) => write!(f, "line {}: {}", n, err),
             Error::LogTooShort => write!(f, "log is too short"),
+            Error::MismatchedStationNames(ref a, ref b) => {
+                write!(f, "mismatched station names: {}, {}", a, b)
+            }
             Error::RecordTooShort(n) => write!(f, "record is too short: {}", n),
             Error::RecordMissingComma(ref s) => write!(f, "record is missing a comma: {}", s),
         }
@@ -74,13 +90,33 @@ impl From<chrono::ParseError> for Error {
 /// A Sutron log file.
 ///
 /// By default these files have the name `ssp.txt`, but other names can be used.
-#[derive(Debug)]
+///
+/// A log's `Display` implementation writes it in the same format that the data logger uses, so
+/// logs can be read, modified, and written back out.
+#[derive(Clone, Debug, PartialEq)]
 pub struct Log {
     station_name: String,
     records: Vec<Record>,
 }
 
 impl Log {
+    /// Creates a new log from a station name and some records.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use atlas::sutron::Log;
+    /// let record = "06/11/2015,11:59:13,scan_on".parse().unwrap();
+    /// let log = Log::new("HEL_ATLAS", vec![record]);
+    /// assert_eq!("Station Name\nHEL_ATLAS\n06/11/2015,11:59:13,scan_on\n", log.to_string());
+    /// ```
+    pub fn new<S: Into<String>>(station_name: S, records: Vec<Record>) -> Log {
+        Log {
+            station_name: station_name.into(),
+            records: records,
+        }
+    }
+
     /// Reads a log file from a path.
     ///
     /// # Examples
@@ -90,24 +126,38 @@ impl Log {
     /// let log = Log::from_path("data/ssp.txt").unwrap();
     /// ```
     pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Log> {
-        let mut lines = BufReader::new(try!(File::open(path))).lines();
-        if let Some(r) = lines.next() {
-            let s = try!(r);
-            if s != "Station Name" {
-                return Err(Error::BadLogHeader(s));
+        Log::from_reader(BufReader::new(try!(File::open(path))))
+    }
+
+    /// Reads a log from anything that implements `BufRead`.
+    ///
+    /// The log is read strictly, so any bad line will fail the entire log. If the log contains
+    /// more than one header, the first station name is used. Use a `Reader` directly if you need
+    /// more control.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use atlas::sutron::Log;
+    /// let data = "Station Name\nHEL_ATLAS\n06/11/2015,11:59:13,scan_on\n";
+    /// let log = Log::from_reader(data.as_bytes()).unwrap();
+    /// assert_eq!(1, log.records().len());
+    /// ```
+    pub fn from_reader<R: BufRead>(read: R) -> Result<Log> {
+        let mut reader = Reader::new(read);
+        let mut station_name = None;
+        let mut records = Vec::new();
+        for entry in &mut reader {
+            let entry = try!(entry);
+            if station_name.is_none() {
+                station_name = entry.station_name;
             }
-        } else {
-            return Err(Error::LogTooShort);
+            records.push(entry.record);
         }
-        let station_name = if let Some(r) = lines.next() {
-            try!(r)
-        } else {
-            return Err(Error::LogTooShort);
+        let station_name = match station_name.or_else(|| reader.station_name().map(String::from)) {
+            Some(station_name) => station_name,
+            None => return Err(Error::LogTooShort),
         };
-        let mut records = Vec::new();
-        for line in lines {
-            records.push(try!(try!(line).parse()));
-        }
         Ok(Log {
             station_name: station_name,
             records: records,
@@ -139,12 +189,250 @@ impl Log {
     pub fn records(&self) -> &Vec<Record> {
         &self.records
     }
+
+    /// Merges another log into this one.
+    ///
+    /// Logs retrieved on different site visits usually overlap, so after merging the records are
+    /// sorted and any duplicates are removed. Logs from different stations can't be merged.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use atlas::sutron::Log;
+    /// let mut log = Log::from_path("data/ssp.txt").unwrap();
+    /// let other = Log::from_path("data/ssp.txt").unwrap();
+    /// log.merge(other).unwrap();
+    /// assert_eq!(49, log.records().len());
+    /// ```
+    pub fn merge(&mut self, other: Log) -> Result<()> {
+        if self.station_name != other.station_name {
+            return Err(Error::MismatchedStationNames(self.station_name.clone(),
+                                                     other.station_name));
+        }
+        self.records.extend(other.records);
+        sort_records(&mut self.records);
+        Ok(())
+    }
 }
 
-/// A Sutron log record.
+/// Sorts records by datetime and removes duplicate records.
 ///
-/// We keep this simple as possible, with a datetime and some text data.
+/// The sort is stable, so records with the same datetime stay in the order they were read. A
+/// record is only removed if it's identical to an earlier record with the same datetime.
+///
+/// # Examples
+///
+/// ```
+/// # use atlas::sutron::{self, Log};
+/// let log = Log::from_path("data/ssp.txt").unwrap();
+/// let mut records = log.records().to_vec();
+/// records.extend(log.records().iter().cloned());
+/// sutron::sort_records(&mut records);
+/// assert_eq!(log.records(), records.as_slice());
+/// ```
+pub fn sort_records(records: &mut Vec<Record>) {
+    records.sort_by_key(|r| r.datetime);
+    let mut sorted: Vec<Record> = Vec::with_capacity(records.len());
+    for record in records.drain(..) {
+        let duplicate = sorted.iter()
+            .rev()
+            .take_while(|r| r.datetime == record.datetime)
+            .any(|r| *r == record);
+        if !duplicate {
+            sorted.push(record);
+        }
+    }
+    *records = sorted;
+}
+
+impl fmt::Display for Log {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(writeln!(f, "{}", HEADER));
+        try!(writeln!(f, "{}", self.station_name));
+        for record in &self.records {
+            try!(writeln!(f, "{}", record));
+        }
+        Ok(())
+    }
+}
+
+/// A streaming reader for Sutron log files.
+///
+/// The reader yields one `Entry` per record line, so a log file never needs to be held in memory.
+/// Multiple logs concatenated into one file (i.e. with more than one `Station Name` header) are
+/// handled transparently, and blank lines are skipped.
+///
+/// In strict mode (the default) a bad line is yielded as an `Error::Line`, which includes the
+/// line number. In lenient mode bad lines are logged and skipped.
+#[derive(Debug)]
+pub struct Reader<R> {
+    lines: Lines<R>,
+    line_number: usize,
+    station_name: Option<String>,
+    lenient: bool,
+}
+
+/// A record read from a log file, along with where it came from.
 #[derive(Debug)]
+pub struct Entry {
+    /// The one-based line number of the record.
+    pub line: usize,
+    /// The station name from the most recent header, if there was one.
+    pub station_name: Option<String>,
+    /// The record itself.
+    pub record: Record,
+}
+
+impl Reader<BufReader<File>> {
+    /// Opens a reader for a log file.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use atlas::sutron::Reader;
+    /// let reader = Reader::from_path("data/ssp.txt").unwrap();
+    /// assert_eq!(49, reader.count());
+    /// ```
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Reader<BufReader<File>>> {
+        Ok(Reader::new(BufReader::new(try!(File::open(path)))))
+    }
+}
+
+impl<R: BufRead> Reader<R> {
+    /// Creates a new, strict reader.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use atlas::sutron::Reader;
+    /// let reader = Reader::new("Station Name\nHEL_ATLAS\n".as_bytes());
+    /// ```
+    pub fn new(read: R) -> Reader<R> {
+        Reader {
+            lines: read.lines(),
+            line_number: 0,
+            station_name: None,
+            lenient: false,
+        }
+    }
+
+    /// Sets whether this reader skips bad lines instead of returning errors.
+    ///
+    /// I/O errors other than invalid UTF-8 are always returned.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use atlas::sutron::Reader;
+    /// let data = "Station Name\nHEL_ATLAS\ngarbage\n06/11/2015,11:59:13,scan_on\n";
+    /// let reader = Reader::new(data.as_bytes()).lenient(true);
+    /// assert_eq!(1, reader.filter_map(|r| r.ok()).count());
+    /// ```
+    pub fn lenient(mut self, lenient: bool) -> Reader<R> {
+        self.lenient = lenient;
+        self
+    }
+
+    /// Returns the station name from the most recently read header.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use atlas::sutron::Reader;
+    /// let mut reader = Reader::from_path("data/ssp.txt").unwrap();
+    /// assert_eq!(None, reader.station_name());
+    /// reader.next();
+    /// assert_eq!(Some("HEL_ATLAS"), reader.station_name());
+    /// ```
+    pub fn station_name(&self) -> Option<&str> {
+        self.station_name.as_ref().map(|s| s.as_str())
+    }
+
+    /// Returns the number of lines read so far.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use atlas::sutron::Reader;
+    /// let mut reader = Reader::from_path("data/ssp.txt").unwrap();
+    /// reader.next();
+    /// assert_eq!(3, reader.line_number());
+    /// ```
+    pub fn line_number(&self) -> usize {
+        self.line_number
+    }
+
+    fn next_line(&mut self) -> Option<io::Result<String>> {
+        let line = self.lines.next();
+        if line.is_some() {
+            self.line_number += 1;
+        }
+        line.map(|r| r.map(|s| s.trim_right_matches('\r').to_string()))
+    }
+
+    fn line_error<E: Into<Error>>(&self, err: E) -> Error {
+        Error::Line(self.line_number, Box::new(err.into()))
+    }
+}
+
+impl<R: BufRead> Iterator for Reader<R> {
+    type Item = Result<Entry>;
+
+    fn next(&mut self) -> Option<Result<Entry>> {
+        loop {
+            let line = match self.next_line() {
+                Some(Ok(line)) => line,
+                Some(Err(err)) => {
+                    if self.lenient && err.kind() == io::ErrorKind::InvalidData {
+                        warn!("Skipping line {} of sutron log: {}", self.line_number, err);
+                        continue;
+                    }
+                    return Some(Err(self.line_error(err)));
+                }
+                None => return None,
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            if line == HEADER {
+                match self.next_line() {
+                    Some(Ok(station_name)) => {
+                        self.station_name = Some(station_name);
+                        continue;
+                    }
+                    Some(Err(err)) => return Some(Err(self.line_error(err))),
+                    None => return Some(Err(self.line_error(Error::LogTooShort))),
+                }
+            }
+            if self.station_name.is_none() && !self.lenient {
+                return Some(Err(self.line_error(Error::BadLogHeader(line))));
+            }
+            match line.parse::<Record>() {
+                Ok(record) => {
+                    return Some(Ok(Entry {
+                        line: self.line_number,
+                        station_name: self.station_name.clone(),
+                        record: record,
+                    }))
+                }
+                Err(err) => {
+                    if self.lenient {
+                        warn!("Skipping line {} of sutron log: {}", self.line_number, err);
+                    } else {
+                        return Some(Err(self.line_error(err)));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A Sutron log record.
+///
+/// We keep this simple as possible, with a datetime and some text data. Records are ordered by
+/// datetime, then by data, but logs are sorted with `sort_records`, which keeps records with the
+/// same datetime in the order they were read.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub struct Record {
     /// The date and time that the record was laid down.
     pub datetime: DateTime<UTC>,
@@ -154,6 +442,29 @@ pub struct Record {
     pub data: String,
 }
 
+impl Record {
+    /// Returns the type of this record, e.g. `scan_on` or `scan_skip`.
+    ///
+    /// The type is the first comma-delimited field of the record's data.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use atlas::sutron::Record;
+    /// let record: Record = "06/11/2015,11:59:21,scan_start,05/08/15 05:07:40".parse().unwrap();
+    /// assert_eq!("scan_start", record.record_type());
+    /// ```
+    pub fn record_type(&self) -> &str {
+        self.data.split(',').next().unwrap_or("")
+    }
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{},{}", self.datetime.format("%m/%d/%Y,%H:%M:%S"), self.data)
+    }
+}
+
 impl FromStr for Record {
     type Err = Error;
 
@@ -161,12 +472,14 @@ impl FromStr for Record {
         if s.chars().count() < 20 {
             return Err(Error::RecordTooShort(s.len()));
         }
-        let comma = s.chars().skip(19).next().unwrap();
+        // Corrupted lines can contain multi-byte characters, so we can't slice by byte index
+        // until we know where the character boundaries are.
+        let (index, comma) = s.char_indices().nth(19).unwrap();
         if comma != ',' {
             return Err(Error::RecordMissingComma(comma.to_string()));
         }
-        let datetime = try!(UTC.datetime_from_str(&s[0..19], "%m/%d/%Y,%H:%M:%S"));
-        let data = s[20..].to_string();
+        let datetime = try!(UTC.datetime_from_str(&s[0..index], "%m/%d/%Y,%H:%M:%S"));
+        let data = s[index + 1..].to_string();
         Ok(Record {
             datetime: datetime,
             data: data,
@@ -174,10 +487,112 @@ impl FromStr for Record {
     }
 }
 
+/// A scan session, as recorded in a Sutron log.
+///
+/// Each scan session begins with a `scan_on` record and ends with either a `scan_stop` or a
+/// `scan_skip` record.
+#[derive(Clone, Debug)]
+pub struct ScanSession {
+    /// The time the scanner was turned on.
+    pub on: DateTime<UTC>,
+    /// The time the scan was started, if it was.
+    pub start: Option<DateTime<UTC>>,
+    /// The time the scan was stopped or skipped, if it was.
+    pub end: Option<DateTime<UTC>>,
+    /// How the session ended.
+    pub outcome: ScanOutcome,
+}
+
+/// How a scan session ended.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ScanOutcome {
+    /// The scan ran and was stopped.
+    Completed,
+    /// The scan was skipped, with the reason reported by the scanner.
+    Skipped(String),
+    /// We didn't see the end of the scan session.
+    Incomplete,
+}
+
+impl fmt::Display for ScanOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ScanOutcome::Completed => write!(f, "completed"),
+            ScanOutcome::Skipped(_) => write!(f, "skipped"),
+            ScanOutcome::Incomplete => write!(f, "incomplete"),
+        }
+    }
+}
+
+/// Groups records into scan sessions.
+///
+/// The records should be sorted by datetime. Records that aren't part of a scan session are
+/// ignored.
+///
+/// # Examples
+///
+/// ```
+/// # use atlas::sutron::{Log, scan_sessions};
+/// let log = Log::from_path("data/ssp.txt").unwrap();
+/// let sessions = scan_sessions(log.records());
+/// assert_eq!(14, sessions.len());
+/// ```
+pub fn scan_sessions(records: &[Record]) -> Vec<ScanSession> {
+    let mut sessions = Vec::new();
+    let mut session: Option<ScanSession> = None;
+    for record in records {
+        match record.record_type() {
+            "scan_on" => {
+                if let Some(session) = session.take() {
+                    sessions.push(session);
+                }
+                session = Some(ScanSession::new(record.datetime));
+            }
+            "scan_start" => {
+                if session.is_none() {
+                    session = Some(ScanSession::new(record.datetime));
+                }
+                if let Some(ref mut session) = session {
+                    session.start = Some(record.datetime);
+                }
+            }
+            "scan_stop" | "scan_skip" => {
+                let mut s = session.take().unwrap_or_else(|| ScanSession::new(record.datetime));
+                s.end = Some(record.datetime);
+                s.outcome = if record.record_type() == "scan_stop" {
+                    ScanOutcome::Completed
+                } else {
+                    let reason = record.data.splitn(4, ',').nth(3).unwrap_or("");
+                    ScanOutcome::Skipped(reason.to_string())
+                };
+                sessions.push(s);
+            }
+            _ => {}
+        }
+    }
+    if let Some(session) = session {
+        sessions.push(session);
+    }
+    sessions
+}
+
+impl ScanSession {
+    fn new(on: DateTime<UTC>) -> ScanSession {
+        ScanSession {
+            on: on,
+            start: None,
+            end: None,
+            outcome: ScanOutcome::Incomplete,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    use std::fs::File;
+    use std::io::Read;
     use std::str::FromStr;
 
     use chrono::{TimeZone, UTC};
@@ -215,6 +630,208 @@ mod tests {
         assert!(r.is_err());
     }
 
+    #[test]
+    fn not_ascii() {
+        let r = Record::from_str("06/11/2015,11:5\u{fffd}:13,the data");
+        assert!(r.is_err());
+    }
+
+    #[test]
+    fn reader_line_numbers() {
+        let data = "Station Name\nHEL_ATLAS\n06/11/2015,11:59:13,a\n\n06/11/2015,11:59:17,b\n";
+        let entries = Reader::new(data.as_bytes()).map(|r| r.unwrap()).collect::<Vec<_>>();
+        assert_eq!(2, entries.len());
+        assert_eq!(3, entries[0].line);
+        assert_eq!(5, entries[1].line);
+        assert_eq!("b", entries[1].record.data);
+    }
+
+    #[test]
+    fn reader_concatenated() {
+        let data = "Station Name\nHEL_ATLAS\n06/11/2015,11:59:13,a\nStation \
+                    Name\nHEL_OTHER\n06/11/2015,11:59:17,b\n";
+        let entries = Reader::new(data.as_bytes()).map(|r| r.unwrap()).collect::<Vec<_>>();
+        assert_eq!(2, entries.len());
+        assert_eq!(Some("HEL_ATLAS".to_string()), entries[0].station_name);
+        assert_eq!(Some("HEL_OTHER".to_string()), entries[1].station_name);
+    }
+
+    #[test]
+    fn reader_strict() {
+        let data = "Station Name\nHEL_ATLAS\n06/11/2015,11:59:13,a\ngarbage\n";
+        let mut reader = Reader::new(data.as_bytes());
+        assert!(reader.next().unwrap().is_ok());
+        match reader.next().unwrap() {
+            Err(Error::Line(4, _)) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn reader_lenient() {
+        let data = "garbage\nStation Name\nHEL_ATLAS\n06/11/2015,11:59:13,a\ngarbage\n\
+                    06/11/2015,11:59:17,b\n";
+        let reader = Reader::new(data.as_bytes()).lenient(true);
+        assert_eq!(2, reader.map(|r| r.unwrap()).count());
+    }
+
+    #[test]
+    fn reader_missing_header() {
+        let mut reader = Reader::new("06/11/2015,11:59:13,a\n".as_bytes());
+        assert!(reader.next().unwrap().is_err());
+    }
+
+    #[test]
+    fn log_too_short() {
+        assert!(Log::from_reader("".as_bytes()).is_err());
+        assert!(Log::from_reader("Station Name\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn record_type() {
+        let r = Record::from_str("06/11/2015,11:59:13,scan_stop,0,0").unwrap();
+        assert_eq!("scan_stop", r.record_type());
+        let r = Record::from_str("06/11/2015,11:59:13,").unwrap();
+        assert_eq!("", r.record_type());
+    }
+
+    #[test]
+    fn scan_sessions_from_log() {
+        let log = Log::from_path("data/ssp.txt").unwrap();
+        let sessions = scan_sessions(log.records());
+        assert_eq!(14, sessions.len());
+        let reason = "MEAS_START():1006:COMMAND_NOT_ALLOWED_WHILE_LASER_LOCK_IS_ACTIVE";
+        assert_eq!(ScanOutcome::Skipped(reason.to_string()), sessions[0].outcome);
+        assert_eq!(Some(UTC.ymd(2015, 6, 11).and_hms(11, 59, 21)),
+                   sessions[0].start);
+        assert_eq!(ScanOutcome::Completed, sessions[3].outcome);
+    }
+
+    #[test]
+    fn record_round_trip() {
+        let mut file = String::new();
+        File::open("data/ssp.txt").unwrap().read_to_string(&mut file).unwrap();
+        for line in file.lines().skip(2) {
+            let record = Record::from_str(line).unwrap();
+            assert_eq!(line, record.to_string());
+            assert_eq!(record, Record::from_str(&record.to_string()).unwrap());
+        }
+    }
+
+    #[test]
+    fn log_round_trip() {
+        let mut file = String::new();
+        File::open("data/ssp.txt").unwrap().read_to_string(&mut file).unwrap();
+        let log = Log::from_path("data/ssp.txt").unwrap();
+        assert_eq!(file, log.to_string());
+        assert_eq!(log, Log::from_reader(log.to_string().as_bytes()).unwrap());
+    }
+
+    #[test]
+    fn merge_overlapping() {
+        let log = Log::from_path("data/ssp.txt").unwrap();
+        let mut first = Log::new("HEL_ATLAS", log.records()[..30].to_vec());
+        let second = Log::new("HEL_ATLAS", log.records()[20..].to_vec());
+        first.merge(second).unwrap();
+        assert_eq!(log, first);
+    }
+
+    #[test]
+    fn merge_unsorted() {
+        let log = Log::from_path("data/ssp.txt").unwrap();
+        let mut first = Log::new("HEL_ATLAS", log.records()[40..].to_vec());
+        let second = Log::new("HEL_ATLAS", log.records()[..45].to_vec());
+        first.merge(second).unwrap();
+        assert_eq!(log, first);
+    }
+
+    /// A small xorshift generator, so the merge properties can be checked on many logs without
+    /// pulling in a property testing crate.
+    struct Rng(u32);
+
+    impl Rng {
+        fn next(&mut self, n: u32) -> u32 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 17;
+            self.0 ^= self.0 << 5;
+            self.0 % n
+        }
+
+        /// Returns records with few distinct datetimes and data, so collisions are common.
+        fn records(&mut self) -> Vec<Record> {
+            let n = self.next(20);
+            (0..n)
+                .map(|_| {
+                    Record {
+                        datetime: UTC.ymd(2016, 7, 14).and_hms(0, 0, self.next(5)),
+                        data: format!("scan_on,{}", self.next(3)),
+                    }
+                })
+                .collect()
+        }
+    }
+
+    fn merged(first: &[Record], second: &[Record]) -> Log {
+        let mut log = Log::new("HEL_ATLAS", first.to_vec());
+        log.merge(Log::new("HEL_ATLAS", second.to_vec())).unwrap();
+        log
+    }
+
+    #[test]
+    fn merge_order_independent() {
+        let mut rng = Rng(0x2545f491);
+        for _ in 0..200 {
+            let (a, b) = (rng.records(), rng.records());
+            let ab = merged(&a, &b);
+            let ba = merged(&b, &a);
+            let mut ab_records = ab.records().to_vec();
+            let mut ba_records = ba.records().to_vec();
+            assert!(ab_records.windows(2).all(|w| w[0].datetime <= w[1].datetime));
+            assert!(ba_records.windows(2).all(|w| w[0].datetime <= w[1].datetime));
+            ab_records.sort();
+            ba_records.sort();
+            assert_eq!(ab_records, ba_records);
+            for record in a.iter().chain(b.iter()) {
+                assert_eq!(1, ab.records().iter().filter(|r| *r == record).count());
+            }
+        }
+    }
+
+    #[test]
+    fn merge_idempotent() {
+        let mut rng = Rng(0x9e3779b9);
+        for _ in 0..200 {
+            let (a, b) = (rng.records(), rng.records());
+            let once = merged(&a, &b);
+            let mut twice = once.clone();
+            twice.merge(Log::new("HEL_ATLAS", b.clone())).unwrap();
+            assert_eq!(once, twice);
+            let mut itself = once.clone();
+            itself.merge(once.clone()).unwrap();
+            assert_eq!(once, itself);
+        }
+    }
+
+    #[test]
+    fn merge_keeps_order_within_a_second() {
+        let datetime = UTC.ymd(2016, 7, 14).and_hms(0, 0, 0);
+        let record = |data: &str| {
+            Record {
+                datetime: datetime,
+                data: data.to_string(),
+            }
+        };
+        let log = merged(&[record("scan_on"), record("scan_complete")],
+                         &[record("scan_on"), record("scan_complete")]);
+        assert_eq!(vec![record("scan_on"), record("scan_complete")], log.records());
+    }
+
+    #[test]
+    fn merge_different_stations() {
+        let mut log = Log::new("HEL_ATLAS", Vec::new());
+        assert!(log.merge(Log::new("HEL_OTHER", Vec::new())).is_err());
+    }
+
     #[test]
     fn empty_record() {
         let r = Record::from_str("06/11/2015,11:59:13,");
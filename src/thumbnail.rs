@@ -0,0 +1,358 @@
+//! Resized copies of camera images.
+//!
+//! Full-size camera images are heavy for anyone on a slow link. The thumbnail cache keeps resized
+//! copies of every image in a set of configured sizes, generated when new images show up in a
+//! camera directory.
+
+use std::ascii::AsciiExt;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use image::{self, FilterType, ImageFormat};
+
+use iron::{Handler, status};
+use iron::headers::{CacheControl, CacheDirective, ContentType, Location};
+use iron::prelude::*;
+use iron::mime::{Mime, SubLevel, TopLevel};
+
+use router::Router;
+
+use url::Url;
+
+use {Error, Result};
+use cam::Camera;
+use query;
+use scratch;
+use watch::DirectoryWatcher;
+
+/// A named thumbnail size.
+///
+/// Images are scaled to fit inside `width` x `height`, preserving their aspect ratio.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Size {
+    /// The name of this size, used in urls and cache paths.
+    pub name: String,
+    /// The maximum width of the thumbnail.
+    pub width: u32,
+    /// The maximum height of the thumbnail.
+    pub height: u32,
+}
+
+/// A directory of resized camera images.
+///
+/// Thumbnails are stored at `<directory>/<camera name>/<size name>/<file name>`.
+#[derive(Clone, Debug)]
+pub struct ThumbnailCache {
+    directory: PathBuf,
+    sizes: Vec<Size>,
+}
+
+impl ThumbnailCache {
+    /// Creates a new thumbnail cache in the given directory.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use atlas::thumbnail::{Size, ThumbnailCache};
+    /// let small = Size { name: "small".to_string(), width: 256, height: 192 };
+    /// let cache = ThumbnailCache::new("/tmp/thumbnails", vec![small]);
+    /// ```
+    pub fn new<P: AsRef<Path>>(directory: P, sizes: Vec<Size>) -> ThumbnailCache {
+        ThumbnailCache {
+            directory: directory.as_ref().to_path_buf(),
+            sizes: sizes,
+        }
+    }
+
+    /// Returns the size with the given name, if there is one.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use atlas::thumbnail::{Size, ThumbnailCache};
+    /// let small = Size { name: "small".to_string(), width: 256, height: 192 };
+    /// let cache = ThumbnailCache::new("/tmp/thumbnails", vec![small]);
+    /// assert!(cache.size("small").is_some());
+    /// assert!(cache.size("huge").is_none());
+    /// ```
+    pub fn size(&self, name: &str) -> Option<&Size> {
+        self.sizes.iter().find(|s| s.name == name)
+    }
+
+    /// Returns the path where a thumbnail of the given image would be stored.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use std::path::Path;
+    /// # use atlas::cam::Camera;
+    /// # use atlas::thumbnail::{Size, ThumbnailCache};
+    /// let small = Size { name: "small".to_string(), width: 256, height: 192 };
+    /// let cache = ThumbnailCache::new("/tmp/thumbnails", vec![small.clone()]);
+    /// let camera = Camera::new("ATLAS_CAM", "data").unwrap();
+    /// assert_eq!(Path::new("/tmp/thumbnails/ATLAS_CAM/small/foo.jpg"),
+    ///            cache.path(&camera, "foo.jpg", &small));
+    /// ```
+    pub fn path(&self, camera: &Camera, file_name: &str, size: &Size) -> PathBuf {
+        self.directory.join(camera.name()).join(&size.name).join(file_name)
+    }
+
+    /// Creates every missing or out-of-date thumbnail of an image.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use atlas::cam::Camera;
+    /// # use atlas::thumbnail::{Size, ThumbnailCache};
+    /// let small = Size { name: "small".to_string(), width: 256, height: 192 };
+    /// let cache = ThumbnailCache::new("/tmp/thumbnails", vec![small]);
+    /// let camera = Camera::new("ATLAS_CAM", "data").unwrap();
+    /// cache.generate(&camera, "data/ATLAS_CAM_20160725_121500.jpg").unwrap();
+    /// ```
+    pub fn generate<P: AsRef<Path>>(&self, camera: &Camera, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let file_name = try!(file_name(path));
+        let stale = self.sizes
+            .iter()
+            .filter(|size| !is_fresh(&self.path(camera, &file_name, size), path))
+            .collect::<Vec<_>>();
+        if stale.is_empty() {
+            return Ok(());
+        }
+        let mut bytes = Vec::new();
+        try!(try!(File::open(path)).read_to_end(&mut bytes));
+        let original = try!(image::load_from_memory(&bytes));
+        for size in stale {
+            let thumbnail = original.resize(size.width, size.height, FilterType::Triangle);
+            let destination = self.path(camera, &file_name, size);
+            if let Some(parent) = destination.parent() {
+                try!(fs::create_dir_all(parent));
+            }
+            // Write to a temporary file first so readers never see a partial thumbnail.
+            let temporary = scratch::temporary_path(&destination);
+            {
+                let mut file = try!(File::create(&temporary));
+                try!(thumbnail.save(&mut file, ImageFormat::JPEG));
+                try!(file.flush());
+            }
+            try!(fs::rename(&temporary, &destination));
+        }
+        Ok(())
+    }
+
+    /// Returns the bytes of a thumbnail, generating it first if needed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use atlas::cam::Camera;
+    /// # use atlas::thumbnail::{Size, ThumbnailCache};
+    /// let small = Size { name: "small".to_string(), width: 256, height: 192 };
+    /// let cache = ThumbnailCache::new("/tmp/thumbnails", vec![small.clone()]);
+    /// let camera = Camera::new("ATLAS_CAM", "data").unwrap();
+    /// let bytes = cache.get(&camera, "data/ATLAS_CAM_20160725_121500.jpg", &small).unwrap();
+    /// ```
+    pub fn get<P: AsRef<Path>>(&self, camera: &Camera, path: P, size: &Size) -> Result<Vec<u8>> {
+        let path = path.as_ref();
+        try!(self.generate(camera, path));
+        let mut bytes = Vec::new();
+        try!(try!(File::open(self.path(camera, &try!(file_name(path)), size)))
+            .read_to_end(&mut bytes));
+        Ok(bytes)
+    }
+}
+
+fn file_name(path: &Path) -> Result<String> {
+    path.file_name()
+        .map(|f| f.to_string_lossy().into_owned())
+        .ok_or(Error::InvalidCameraPath("no file name".to_string(), path.to_path_buf()))
+}
+
+/// Is the thumbnail at least as new as its source image?
+fn is_fresh(thumbnail: &Path, source: &Path) -> bool {
+    match (fs::metadata(thumbnail).and_then(|m| m.modified()),
+           fs::metadata(source).and_then(|m| m.modified())) {
+        (Ok(thumbnail), Ok(source)) => thumbnail >= source,
+        _ => false,
+    }
+}
+
+/// Watches a camera's directory and creates thumbnails for new images.
+#[derive(Debug)]
+pub struct ThumbnailWatcher {
+    camera: Camera,
+    directory: PathBuf,
+    cache: ThumbnailCache,
+}
+
+impl ThumbnailWatcher {
+    /// Creates a new thumbnail watcher for a camera.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use atlas::cam::Camera;
+    /// # use atlas::thumbnail::{ThumbnailCache, ThumbnailWatcher};
+    /// let cache = ThumbnailCache::new("/tmp/thumbnails", Vec::new());
+    /// let watcher = ThumbnailWatcher::new(Camera::new("ATLAS_CAM", "data").unwrap(), cache);
+    /// ```
+    pub fn new(camera: Camera, cache: ThumbnailCache) -> ThumbnailWatcher {
+        ThumbnailWatcher {
+            directory: camera.path().to_path_buf(),
+            camera: camera,
+            cache: cache,
+        }
+    }
+}
+
+impl DirectoryWatcher for ThumbnailWatcher {
+    fn directory(&self) -> &Path {
+        self.directory.as_path()
+    }
+
+    fn refresh(&mut self) -> Result<()> {
+        for path in try!(self.camera.paths()) {
+            if let Err(err) = self.cache.generate(&self.camera, &path) {
+                warn!("Could not create thumbnails for {}: {}",
+                      path.to_string_lossy(),
+                      err);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Iron handler that serves camera images by name, optionally resized.
+///
+/// The camera and file name are taken from the `name` and `file` route parameters. With a `size`
+/// query parameter, e.g. `?size=small`, the thumbnail is served. Without one, the client is
+/// redirected to the full-size image at `Camera::url`.
+#[derive(Debug)]
+pub struct ThumbnailHandler {
+    cameras: HashMap<String, Camera>,
+    cache: ThumbnailCache,
+    url: Url,
+}
+
+impl ThumbnailHandler {
+    /// Creates a new thumbnail handler.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate url;
+    /// # extern crate atlas;
+    /// # use atlas::cam::Camera;
+    /// # use atlas::thumbnail::{ThumbnailCache, ThumbnailHandler};
+    /// # fn main() {
+    /// let cache = ThumbnailCache::new("/tmp/thumbnails", Vec::new());
+    /// let url = url::Url::parse("http://iridiumcam.lidar.io").unwrap();
+    /// let handler = ThumbnailHandler::new(vec![Camera::new("ATLAS_CAM", "data").unwrap()],
+    ///                                     cache,
+    ///                                     url);
+    /// # }
+    /// ```
+    pub fn new(cameras: Vec<Camera>, cache: ThumbnailCache, url: Url) -> ThumbnailHandler {
+        ThumbnailHandler {
+            cameras: cameras.into_iter()
+                .map(|c| (c.name().to_ascii_lowercase(), c))
+                .collect(),
+            cache: cache,
+            url: url,
+        }
+    }
+}
+
+impl Handler for ThumbnailHandler {
+    fn handle(&self, request: &mut Request) -> IronResult<Response> {
+        let (name, file) = {
+            let params = iexpect!(request.extensions.get::<Router>());
+            (iexpect!(params.find("name")).to_ascii_lowercase(),
+             iexpect!(params.find("file")).to_string())
+        };
+        let camera = iexpect!(self.cameras.get(&name), status::NotFound);
+        if file.contains('/') || file.contains('\\') || file.starts_with('.') {
+            return Ok(Response::with(status::NotFound));
+        }
+        if camera.datetime(&file).is_err() {
+            return Ok(Response::with(status::NotFound));
+        }
+        let path = iexpect!(camera.locate(&file), status::NotFound);
+
+        let size = match query::params(request).get("size") {
+            Some(size) => iexpect!(self.cache.size(size), (status::BadRequest, "Unknown size.")),
+            None => {
+                let url = iexpect!(camera.url(&self.url, &file), status::NotFound);
+                let mut response = Response::with(status::Found);
+                response.headers.set(Location(url.as_str().to_string()));
+                return Ok(response);
+            }
+        };
+        let bytes = itry!(self.cache.get(camera, &path, size));
+        let mut response = Response::with((status::Ok, bytes));
+        response.headers.set(ContentType(Mime(TopLevel::Image, SubLevel::Jpeg, vec![])));
+        response.headers.set(CacheControl(vec![CacheDirective::Public,
+                                               CacheDirective::MaxAge(24 * 60 * 60)]));
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::fs;
+
+    use image::{self, GenericImage};
+
+    use cam::Camera;
+    use watch::DirectoryWatcher;
+
+    fn cache(name: &str) -> ThumbnailCache {
+        let directory = env::temp_dir().join(format!("atlas-thumbnails-{}", name));
+        let _ = fs::remove_dir_all(&directory);
+        ThumbnailCache::new(directory,
+                            vec![Size {
+                                     name: "small".to_string(),
+                                     width: 256,
+                                     height: 256,
+                                 },
+                                 Size {
+                                     name: "medium".to_string(),
+                                     width: 512,
+                                     height: 512,
+                                 }])
+    }
+
+    #[test]
+    fn generate() {
+        let cache = cache("generate");
+        let camera = Camera::new("ATLAS_CAM", "data").unwrap();
+        cache.generate(&camera, "data/ATLAS_CAM_20160725_121500.jpg").unwrap();
+        let small = image::open(cache.path(&camera,
+                                           "ATLAS_CAM_20160725_121500.jpg",
+                                           cache.size("small").unwrap()))
+            .unwrap();
+        assert_eq!(256, small.width());
+        assert_eq!(192, small.height());
+        let medium = image::open(cache.path(&camera,
+                                            "ATLAS_CAM_20160725_121500.jpg",
+                                            cache.size("medium").unwrap()))
+            .unwrap();
+        assert_eq!(512, medium.width());
+    }
+
+    #[test]
+    fn refresh() {
+        let cache = cache("refresh");
+        let camera = Camera::new("ATLAS_CAM", "data").unwrap();
+        let mut watcher = ThumbnailWatcher::new(camera.clone(), cache.clone());
+        watcher.refresh().unwrap();
+        let size = cache.size("small").unwrap();
+        assert!(cache.path(&camera, "ATLAS_CAM_20160725_121500.jpg", size).exists());
+        assert!(cache.path(&camera, "ATLAS_CAM_20160725_141500.jpg", size).exists());
+    }
+}
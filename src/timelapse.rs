@@ -0,0 +1,717 @@
+//! On-demand timelapses of arbitrary time ranges.
+//!
+//! The gif watchers keep one animation per camera for a fixed window, but researchers often want
+//! a specific range, e.g. the week of a calving event. Rendering a long range can take minutes, so
+//! timelapse requests are put on a queue and rendered one at a time by a background worker.
+//! Finished timelapses are cached on disk, keyed by their parameters, so each one is only rendered
+//! once. A range that reaches past a camera's newest image is cut off just after that image, so a
+//! cached timelapse never misses frames that arrive later.
+
+use std::ascii::AsciiExt;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::mpsc::{self, Receiver, Sender};
+
+use chrono::{DateTime, Duration, UTC};
+
+use iron::{Handler, status};
+use iron::headers::{CacheControl, CacheDirective, Location};
+use iron::prelude::*;
+use iron::mime::Mime;
+
+use router::Router;
+
+use rustc_serialize::json::{Json, ToJson};
+
+use Result;
+use animation::{self, AnimationEncoder, Animator, GifConfig};
+use cam::Camera;
+use query;
+use scratch;
+use select::Selection;
+use video::{FfmpegEncoder, VideoFormat};
+
+const DEFAULT_WIDTH: u64 = 512;
+const MAX_WIDTH: u64 = 1920;
+const DEFAULT_FPS: u64 = 2;
+const MAX_FPS: u64 = 30;
+const MAX_DAYS: i64 = 366;
+
+/// The most timelapses that can be queued, rendering, or failed at once.
+const MAX_JOBS: usize = 1000;
+
+/// The parameters of a timelapse.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TimelapseRequest {
+    /// The camera name, in lowercase.
+    pub camera: String,
+    /// The start of the range, inclusive.
+    pub start: DateTime<UTC>,
+    /// The end of the range, exclusive.
+    pub end: DateTime<UTC>,
+    /// The width of the timelapse, in pixels.
+    ///
+    /// The height is three quarters of the width, which matches the cameras' aspect ratio.
+    pub width: u64,
+    /// The number of frames per second.
+    pub fps: u64,
+    /// The format of the timelapse: `"gif"`, `"mp4"`, or `"webm"`.
+    pub format: String,
+}
+
+impl TimelapseRequest {
+    /// Returns the identifier of this timelapse, which is also its file name in the cache.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate chrono;
+    /// # extern crate atlas;
+    /// # use chrono::{TimeZone, UTC};
+    /// # use atlas::timelapse::TimelapseRequest;
+    /// # fn main() {
+    /// let request = TimelapseRequest {
+    ///     camera: "atlas_cam".to_string(),
+    ///     start: UTC.ymd(2016, 7, 25).and_hms(0, 0, 0),
+    ///     end: UTC.ymd(2016, 8, 1).and_hms(0, 0, 0),
+    ///     width: 512,
+    ///     fps: 10,
+    ///     format: "mp4".to_string(),
+    /// };
+    /// assert_eq!("atlas_cam_20160725T000000_20160801T000000_512w_10fps.mp4", request.id());
+    /// # }
+    /// ```
+    pub fn id(&self) -> String {
+        format!("{}_{}_{}_{}w_{}fps.{}",
+                self.camera
+                    .chars()
+                    .map(|c| if c.is_ascii() && c.is_alphanumeric() { c } else { '_' })
+                    .collect::<String>(),
+                self.start.format("%Y%m%dT%H%M%S"),
+                self.end.format("%Y%m%dT%H%M%S"),
+                self.width,
+                self.fps,
+                self.format)
+    }
+
+    fn config(&self) -> GifConfig {
+        GifConfig {
+            width: self.width,
+            height: self.width * 3 / 4,
+            delay: Duration::milliseconds(1000 / self.fps as i64),
+        }
+    }
+}
+
+/// The state of a timelapse.
+#[derive(Clone, Debug, PartialEq)]
+pub enum JobStatus {
+    /// Waiting for the worker.
+    Queued,
+    /// Being rendered by the worker.
+    Rendering,
+    /// Rendered and in the cache.
+    Done,
+    /// Rendering failed, with the reason.
+    Failed(String),
+}
+
+impl JobStatus {
+    /// Returns the name of this status, as used in the status JSON.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use atlas::timelapse::JobStatus;
+    /// assert_eq!("rendering", JobStatus::Rendering.name());
+    /// ```
+    pub fn name(&self) -> &'static str {
+        match *self {
+            JobStatus::Queued => "queued",
+            JobStatus::Rendering => "rendering",
+            JobStatus::Done => "done",
+            JobStatus::Failed(_) => "failed",
+        }
+    }
+}
+
+/// A queue of timelapses to be rendered, backed by an on-disk cache.
+#[derive(Clone, Debug)]
+pub struct TimelapseQueue {
+    directory: PathBuf,
+    cameras: Arc<HashMap<String, Camera>>,
+    jobs: Arc<RwLock<HashMap<String, JobStatus>>>,
+    sender: Arc<Mutex<Sender<TimelapseRequest>>>,
+}
+
+/// Renders the timelapses put on a `TimelapseQueue`.
+#[derive(Debug)]
+pub struct TimelapseWorker {
+    directory: PathBuf,
+    cameras: HashMap<String, (Camera, Selection)>,
+    jobs: Arc<RwLock<HashMap<String, JobStatus>>>,
+    receiver: Receiver<TimelapseRequest>,
+    ffmpeg: Option<PathBuf>,
+}
+
+impl TimelapseQueue {
+    /// Creates a new queue that caches timelapses in `directory`, and the worker that renders
+    /// them.
+    ///
+    /// Each camera's frames are chosen with its selection. Nothing is rendered until the worker
+    /// is run, usually in its own thread.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use atlas::cam::Camera;
+    /// # use atlas::timelapse::TimelapseQueue;
+    /// let camera = Camera::new("ATLAS_CAM", "data").unwrap();
+    /// let (queue, worker) = TimelapseQueue::new("/tmp/timelapses",
+    ///                                           vec![(camera, Default::default())]);
+    /// ```
+    pub fn new<P: AsRef<Path>>(directory: P,
+                               cameras: Vec<(Camera, Selection)>)
+                               -> (TimelapseQueue, TimelapseWorker) {
+        let (sender, receiver) = mpsc::channel();
+        let jobs = Arc::new(RwLock::new(HashMap::new()));
+        let cameras = cameras.into_iter()
+            .map(|(camera, selection)| (camera.name().to_ascii_lowercase(), (camera, selection)))
+            .collect::<HashMap<_, _>>();
+        let queue = TimelapseQueue {
+            directory: directory.as_ref().to_path_buf(),
+            cameras: Arc::new(cameras.iter()
+                .map(|(name, &(ref camera, _))| (name.clone(), camera.clone()))
+                .collect()),
+            jobs: jobs.clone(),
+            sender: Arc::new(Mutex::new(sender)),
+        };
+        let worker = TimelapseWorker {
+            directory: directory.as_ref().to_path_buf(),
+            cameras: cameras,
+            jobs: jobs,
+            receiver: receiver,
+            ffmpeg: None,
+        };
+        (queue, worker)
+    }
+
+    /// Returns the camera with the given name, ignoring case.
+    pub fn camera(&self, name: &str) -> Option<&Camera> {
+        self.cameras.get(&name.to_ascii_lowercase())
+    }
+
+    /// Returns the path where the timelapse with the given id is cached.
+    pub fn path(&self, id: &str) -> PathBuf {
+        self.directory.join(id)
+    }
+
+    /// Queues a timelapse, unless it is already cached, queued, or rendering.
+    ///
+    /// A timelapse that failed is queued again. Returns the timelapse's status, which is failed if
+    /// the worker isn't running, or if `MAX_JOBS` timelapses are already queued or rendering.
+    /// Failed timelapses are forgotten to make room for new ones.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate chrono;
+    /// # extern crate atlas;
+    /// # use chrono::{TimeZone, UTC};
+    /// # use atlas::cam::Camera;
+    /// # use atlas::timelapse::{JobStatus, TimelapseQueue, TimelapseRequest};
+    /// # fn main() {
+    /// let camera = Camera::new("ATLAS_CAM", "data").unwrap();
+    /// let (queue, worker) = TimelapseQueue::new("/tmp/atlas-timelapse-doc",
+    ///                                           vec![(camera, Default::default())]);
+    /// let request = TimelapseRequest {
+    ///     camera: "atlas_cam".to_string(),
+    ///     start: UTC.ymd(2016, 7, 25).and_hms(0, 0, 0),
+    ///     end: UTC.ymd(2016, 7, 25).and_hms(1, 0, 0),
+    ///     width: 512,
+    ///     fps: 10,
+    ///     format: "gif".to_string(),
+    /// };
+    /// assert_eq!(JobStatus::Queued, queue.submit(request));
+    /// # }
+    /// ```
+    pub fn submit(&self, request: TimelapseRequest) -> JobStatus {
+        let id = request.id();
+        if self.path(&id).is_file() {
+            return JobStatus::Done;
+        }
+        let mut jobs = self.jobs.write().unwrap();
+        match jobs.get(&id) {
+            Some(&JobStatus::Queued) => return JobStatus::Queued,
+            Some(&JobStatus::Rendering) => return JobStatus::Rendering,
+            _ => {}
+        }
+        if jobs.len() >= MAX_JOBS {
+            let failed = jobs.iter()
+                .filter(|&(_, status)| match *status {
+                    JobStatus::Failed(_) => true,
+                    _ => false,
+                })
+                .map(|(id, _)| id.clone())
+                .collect::<Vec<_>>();
+            for failed_id in failed {
+                jobs.remove(&failed_id);
+            }
+            if jobs.len() >= MAX_JOBS {
+                return JobStatus::Failed("too many timelapses are waiting to be rendered, try \
+                                          again later"
+                    .to_string());
+            }
+        }
+        let status = match self.sender.lock().unwrap().send(request) {
+            Ok(()) => JobStatus::Queued,
+            Err(_) => JobStatus::Failed("the timelapse worker is not running".to_string()),
+        };
+        jobs.insert(id, status.clone());
+        status
+    }
+
+    /// Returns the status of the timelapse with the given id, if it is known.
+    pub fn status(&self, id: &str) -> Option<JobStatus> {
+        if is_valid_id(id) && self.path(id).is_file() {
+            return Some(JobStatus::Done);
+        }
+        match self.jobs.read().unwrap().get(id) {
+            Some(&JobStatus::Done) | None => None,
+            Some(status) => Some(status.clone()),
+        }
+    }
+}
+
+impl TimelapseWorker {
+    /// Sets the path to the `ffmpeg` binary used for video timelapses.
+    pub fn ffmpeg<P: AsRef<Path>>(mut self, ffmpeg: P) -> TimelapseWorker {
+        self.ffmpeg = Some(ffmpeg.as_ref().to_path_buf());
+        self
+    }
+
+    /// Renders queued timelapses, one at a time, until the queue is dropped.
+    ///
+    /// Rendered timelapses are forgotten, since the cached file says that they're done.
+    pub fn run(self) {
+        for request in self.receiver.iter() {
+            let id = request.id();
+            match self.render(&request) {
+                Ok(_) => {
+                    self.jobs.write().unwrap().remove(&id);
+                }
+                Err(err) => {
+                    warn!("Could not render timelapse {}: {}", id, err);
+                    self.jobs.write().unwrap().insert(id, JobStatus::Failed(err));
+                }
+            }
+        }
+    }
+
+    /// Renders a timelapse into the cache and returns its path.
+    ///
+    /// The error is a message suitable for showing to whoever requested the timelapse.
+    pub fn render(&self, request: &TimelapseRequest) -> ::std::result::Result<PathBuf, String> {
+        let id = request.id();
+        self.jobs.write().unwrap().insert(id.clone(), JobStatus::Rendering);
+        let &(ref camera, ref selection) = try!(self.cameras
+            .get(&request.camera)
+            .ok_or(format!("unknown camera: {}", request.camera)));
+        let paths = try!(camera.paths_between(&request.start, &request.end)
+            .map_err(|e| e.to_string()));
+        if paths.is_empty() {
+            return Err("no images in the requested range".to_string());
+        }
+        let encoder = try!(self.encoder(&request.format).map_err(|e| e.to_string()));
+        let bytes = try!(Animator::new(camera.clone(), request.config(), encoder)
+            .selection(selection.clone())
+            .between(&request.start, &request.end)
+            .map_err(|e| e.to_string()));
+        let path = self.directory.join(&id);
+        try!(write_atomically(&path, &bytes).map_err(|e| e.to_string()));
+        Ok(path)
+    }
+
+    fn encoder(&self, format: &str) -> Result<Box<AnimationEncoder>> {
+        match format {
+            "gif" => Ok(animation::default_encoder()),
+            _ => {
+                let mut encoder = FfmpegEncoder::new(try!(format.parse::<VideoFormat>()));
+                if let Some(ref ffmpeg) = self.ffmpeg {
+                    encoder = encoder.program(ffmpeg);
+                }
+                Ok(Box::new(encoder))
+            }
+        }
+    }
+}
+
+/// Writes to a temporary file first so readers never see a partial timelapse.
+fn write_atomically(path: &Path, bytes: &[u8]) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        try!(fs::create_dir_all(parent));
+    }
+    let temporary = scratch::temporary_path(path);
+    {
+        let mut file = try!(File::create(&temporary));
+        try!(file.write_all(bytes));
+        try!(file.flush());
+    }
+    try!(fs::rename(&temporary, path));
+    Ok(())
+}
+
+fn is_valid_id(id: &str) -> bool {
+    !id.is_empty() && !id.starts_with('.') &&
+    id.chars().all(|c| c.is_ascii() && (c.is_alphanumeric() || c == '_' || c == '.'))
+}
+
+fn content_type(id: &str) -> &'static str {
+    match Path::new(id).extension().and_then(|e| e.to_str()) {
+        Some("mp4") => VideoFormat::Mp4.content_type(),
+        Some("webm") => VideoFormat::Webm.content_type(),
+        _ => "image/gif",
+    }
+}
+
+/// Returns the datetime of a camera's newest image.
+fn newest_datetime(camera: &Camera) -> ::std::result::Result<DateTime<UTC>, String> {
+    match camera.latest_file_name() {
+        Ok(Some(file_name)) => camera.datetime(&file_name).map_err(|e| e.to_string()),
+        Ok(None) => Err(format!("camera {} has no images", camera.name())),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+/// Builds a timelapse request from query parameters.
+///
+/// The end is cut off just after the camera's newest image.
+fn parse_request(camera: &Camera,
+                 params: &HashMap<String, String>)
+                 -> ::std::result::Result<TimelapseRequest, String> {
+    let start = try!(params.get("start")
+        .and_then(|s| query::parse_bound(s, false))
+        .ok_or("start must be a date (YYYY-MM-DD) or an RFC 3339 datetime".to_string()));
+    let end = try!(params.get("end")
+        .and_then(|s| query::parse_bound(s, true))
+        .ok_or("end must be a date (YYYY-MM-DD) or an RFC 3339 datetime".to_string()));
+    if end <= start {
+        return Err("end must be after start".to_string());
+    }
+    if end - start > Duration::days(MAX_DAYS) {
+        return Err(format!("timelapses can be at most {} days long", MAX_DAYS));
+    }
+    let newest = try!(newest_datetime(camera));
+    let end = if end > newest {
+        newest + Duration::seconds(1)
+    } else {
+        end
+    };
+    if end <= start {
+        return Err("there are no images after start".to_string());
+    }
+    let width = match params.get("width") {
+        Some(s) => try!(s.parse::<u64>().map_err(|_| "width must be a number".to_string())),
+        None => DEFAULT_WIDTH,
+    };
+    if width < 16 || width > MAX_WIDTH {
+        return Err(format!("width must be between 16 and {}", MAX_WIDTH));
+    }
+    let fps = match params.get("fps") {
+        Some(s) => try!(s.parse::<u64>().map_err(|_| "fps must be a number".to_string())),
+        None => DEFAULT_FPS,
+    };
+    if fps < 1 || fps > MAX_FPS {
+        return Err(format!("fps must be between 1 and {}", MAX_FPS));
+    }
+    let format = params.get("format").map(|s| s.as_str()).unwrap_or("gif");
+    if !["gif", "mp4", "webm"].contains(&format) {
+        return Err("format must be gif, mp4, or webm".to_string());
+    }
+    Ok(TimelapseRequest {
+        camera: camera.name().to_ascii_lowercase(),
+        start: start,
+        end: end,
+        width: width,
+        fps: fps,
+        format: format.to_string(),
+    })
+}
+
+fn status_json(id: &str, status: &JobStatus) -> Json {
+    let mut data = BTreeMap::<String, Json>::new();
+    data.insert("id".to_string(), id.to_json());
+    data.insert("status".to_string(), status.name().to_json());
+    data.insert("status_url".to_string(),
+                format!("/timelapses/{}/status", id).to_json());
+    if *status == JobStatus::Done {
+        data.insert("url".to_string(), format!("/timelapses/{}", id).to_json());
+    }
+    if let JobStatus::Failed(ref message) = *status {
+        data.insert("error".to_string(), message.to_json());
+    }
+    Json::Object(data)
+}
+
+fn json_response(code: status::Status, json: Json) -> Response {
+    let content_type = "application/json".parse::<Mime>().unwrap();
+    Response::with((content_type, code, json.to_string()))
+}
+
+/// Iron handler that requests a timelapse of a camera.
+///
+/// The camera is the `name` route parameter. The query parameters are `start` and `end`, each a
+/// `%Y-%m-%d` date or an RFC 3339 datetime (an end date includes that whole day), and optionally
+/// `width`, `fps`, and `format` (`gif`, `mp4`, or `webm`).
+///
+/// If the timelapse is cached, the client is redirected to it. Otherwise the timelapse is queued
+/// and the response is `202 Accepted`, with the status url in the `Location` header and the body.
+#[derive(Debug)]
+pub struct TimelapseHandler {
+    queue: TimelapseQueue,
+}
+
+impl TimelapseHandler {
+    /// Creates a new timelapse handler.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use atlas::timelapse::{TimelapseHandler, TimelapseQueue};
+    /// let (queue, worker) = TimelapseQueue::new("/tmp/timelapses", Vec::new());
+    /// let handler = TimelapseHandler::new(queue);
+    /// ```
+    pub fn new(queue: TimelapseQueue) -> TimelapseHandler {
+        TimelapseHandler { queue: queue }
+    }
+}
+
+impl Handler for TimelapseHandler {
+    fn handle(&self, request: &mut Request) -> IronResult<Response> {
+        let camera = {
+            let name = iexpect!(request.extensions.get::<Router>().and_then(|p| p.find("name")));
+            iexpect!(self.queue.camera(name), status::NotFound).clone()
+        };
+        let timelapse = match parse_request(&camera, &query::params(request)) {
+            Ok(timelapse) => timelapse,
+            Err(message) => return Ok(Response::with((status::BadRequest, message))),
+        };
+        let id = timelapse.id();
+        let job = self.queue.submit(timelapse);
+        if job == JobStatus::Done {
+            let mut response = Response::with(status::SeeOther);
+            response.headers.set(Location(format!("/timelapses/{}", id)));
+            return Ok(response);
+        }
+        let mut response = json_response(status::Accepted, status_json(&id, &job));
+        response.headers.set(Location(format!("/timelapses/{}/status", id)));
+        Ok(response)
+    }
+}
+
+/// Iron handler that serves the JSON status of a timelapse, from the `id` route parameter.
+#[derive(Debug)]
+pub struct TimelapseStatusHandler {
+    queue: TimelapseQueue,
+}
+
+impl TimelapseStatusHandler {
+    /// Creates a new timelapse status handler.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use atlas::timelapse::{TimelapseQueue, TimelapseStatusHandler};
+    /// let (queue, worker) = TimelapseQueue::new("/tmp/timelapses", Vec::new());
+    /// let handler = TimelapseStatusHandler::new(queue);
+    /// ```
+    pub fn new(queue: TimelapseQueue) -> TimelapseStatusHandler {
+        TimelapseStatusHandler { queue: queue }
+    }
+}
+
+impl Handler for TimelapseStatusHandler {
+    fn handle(&self, request: &mut Request) -> IronResult<Response> {
+        let id = iexpect!(request.extensions.get::<Router>().and_then(|p| p.find("id")))
+            .to_string();
+        let job = iexpect!(self.queue.status(&id), status::NotFound);
+        Ok(json_response(status::Ok, status_json(&id, &job)))
+    }
+}
+
+/// Iron handler that serves a rendered timelapse, from the `id` route parameter.
+#[derive(Debug)]
+pub struct TimelapseFileHandler {
+    queue: TimelapseQueue,
+}
+
+impl TimelapseFileHandler {
+    /// Creates a new timelapse file handler.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use atlas::timelapse::{TimelapseFileHandler, TimelapseQueue};
+    /// let (queue, worker) = TimelapseQueue::new("/tmp/timelapses", Vec::new());
+    /// let handler = TimelapseFileHandler::new(queue);
+    /// ```
+    pub fn new(queue: TimelapseQueue) -> TimelapseFileHandler {
+        TimelapseFileHandler { queue: queue }
+    }
+}
+
+impl Handler for TimelapseFileHandler {
+    fn handle(&self, request: &mut Request) -> IronResult<Response> {
+        let id = iexpect!(request.extensions.get::<Router>().and_then(|p| p.find("id")))
+            .to_string();
+        if self.queue.status(&id) != Some(JobStatus::Done) {
+            return Ok(Response::with(status::NotFound));
+        }
+        let mut bytes = Vec::new();
+        itry!(itry!(File::open(self.queue.path(&id))).read_to_end(&mut bytes));
+        let content_type = content_type(&id).parse::<Mime>().unwrap();
+        let mut response = Response::with((content_type, status::Ok, bytes));
+        response.headers.set(CacheControl(vec![CacheDirective::Public,
+                                               CacheDirective::MaxAge(24 * 60 * 60)]));
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::{MAX_JOBS, is_valid_id, parse_request};
+
+    use std::collections::HashMap;
+    use std::env;
+    use std::fs;
+
+    use chrono::{TimeZone, UTC};
+
+    use cam::Camera;
+
+    fn queue(name: &str) -> (TimelapseQueue, TimelapseWorker) {
+        let directory = env::temp_dir().join(format!("atlas-timelapses-{}", name));
+        let _ = fs::remove_dir_all(&directory);
+        TimelapseQueue::new(directory,
+                            vec![(Camera::new("ATLAS_CAM", "data").unwrap(), Default::default())])
+    }
+
+    fn request(end_hour: u32) -> TimelapseRequest {
+        TimelapseRequest {
+            camera: "atlas_cam".to_string(),
+            start: UTC.ymd(2016, 7, 25).and_hms(0, 0, 0),
+            end: UTC.ymd(2016, 7, 25).and_hms(end_hour, 0, 0),
+            width: 64,
+            fps: 10,
+            format: "gif".to_string(),
+        }
+    }
+
+    #[test]
+    fn parse() {
+        let camera = Camera::new("ATLAS_CAM", "data").unwrap();
+        let mut params = HashMap::new();
+        params.insert("start".to_string(), "2016-07-25".to_string());
+        params.insert("end".to_string(), "2016-07-25".to_string());
+        params.insert("fps".to_string(), "10".to_string());
+        let request = parse_request(&camera, &params).unwrap();
+        assert_eq!(UTC.ymd(2016, 7, 25).and_hms(14, 15, 1), request.end);
+        assert_eq!(512, request.width);
+        assert_eq!(10, request.fps);
+        assert_eq!("gif", request.format);
+
+        params.insert("end".to_string(), "2016-07-25T12:00:00Z".to_string());
+        assert_eq!(UTC.ymd(2016, 7, 25).and_hms(12, 0, 0),
+                   parse_request(&camera, &params).unwrap().end);
+        params.insert("end".to_string(), "2016-07-24".to_string());
+        assert!(parse_request(&camera, &params).is_err());
+        params.insert("end".to_string(), "2016-07-26".to_string());
+        params.insert("fps".to_string(), "0".to_string());
+        assert!(parse_request(&camera, &params).is_err());
+    }
+
+    #[test]
+    fn parse_past_the_newest_image() {
+        let camera = Camera::new("ATLAS_CAM", "data").unwrap();
+        let mut params = HashMap::new();
+        params.insert("start".to_string(), "2016-07-25".to_string());
+        params.insert("end".to_string(), "2016-08-01".to_string());
+        let request = parse_request(&camera, &params).unwrap();
+        assert_eq!(UTC.ymd(2016, 7, 25).and_hms(14, 15, 1), request.end);
+        params.insert("end".to_string(), "2016-07-31".to_string());
+        assert_eq!(request.id(), parse_request(&camera, &params).unwrap().id());
+
+        params.insert("start".to_string(), "2016-07-26".to_string());
+        assert!(parse_request(&camera, &params).is_err());
+    }
+
+    #[test]
+    fn run_forgets_finished_jobs() {
+        let (queue, worker) = queue("run");
+        let rendered = request(13);
+        let failed = request(1);
+        queue.submit(rendered.clone());
+        queue.submit(failed.clone());
+        let jobs = queue.jobs.clone();
+        let path = queue.path(&rendered.id());
+        drop(queue);
+        worker.run();
+        let jobs = jobs.read().unwrap();
+        assert!(!jobs.contains_key(&rendered.id()));
+        assert!(jobs.contains_key(&failed.id()));
+        assert!(path.is_file());
+    }
+
+    #[test]
+    fn submit_limits_jobs() {
+        let (queue, _worker) = queue("limit");
+        for i in 0..MAX_JOBS {
+            queue.jobs
+                .write()
+                .unwrap()
+                .insert(format!("failed{}", i), JobStatus::Failed(String::new()));
+        }
+        assert_eq!(JobStatus::Queued, queue.submit(request(13)));
+        assert_eq!(1, queue.jobs.read().unwrap().len());
+
+        for i in 0..MAX_JOBS {
+            queue.jobs.write().unwrap().insert(format!("queued{}", i), JobStatus::Queued);
+        }
+        match queue.submit(request(14)) {
+            JobStatus::Failed(_) => {}
+            status => panic!("Unexpected status: {:?}", status),
+        }
+    }
+
+    #[test]
+    fn valid_ids() {
+        assert!(is_valid_id(&request(13).id()));
+        assert!(!is_valid_id("../config.toml"));
+        assert!(!is_valid_id(".hidden"));
+    }
+
+    #[test]
+    fn submit_and_render() {
+        let (queue, worker) = queue("submit");
+        let request = request(13);
+        let id = request.id();
+        assert_eq!(None, queue.status(&id));
+        assert_eq!(JobStatus::Queued, queue.submit(request.clone()));
+        assert_eq!(JobStatus::Queued, queue.submit(request.clone()));
+        assert_eq!(Some(JobStatus::Queued), queue.status(&id));
+        let path = worker.render(&request).unwrap();
+        assert_eq!(queue.path(&id), path);
+        assert_eq!(Some(JobStatus::Done), queue.status(&id));
+        assert_eq!(JobStatus::Done, queue.submit(request));
+    }
+
+    #[test]
+    fn empty_range() {
+        let (_, worker) = queue("empty");
+        assert!(worker.render(&request(1)).is_err());
+    }
+}
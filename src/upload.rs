@@ -0,0 +1,302 @@
+//! Accept camera images over HTTP.
+//!
+//! Field stations with an IP link can push images straight to the server instead of going through
+//! a separate transfer process. Each upload is checked to be a complete JPEG, named with the
+//! camera's naming scheme, and written atomically into the camera's directory so that watchers
+//! never see a partial file.
+
+use std::ascii::AsciiExt;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use chrono::{DateTime, TimeZone, UTC};
+
+use iron::{Handler, status};
+use iron::prelude::*;
+use iron::mime::Mime;
+
+use router::Router;
+
+use rustc_serialize::json::{Json, ToJson};
+
+use {Error, Result};
+use cam::Camera;
+use jpeg;
+use scratch;
+
+/// The largest image we'll accept, in bytes.
+pub const MAX_UPLOAD_BYTES: u64 = 20 * 1024 * 1024;
+
+/// An image that has been stored in a camera directory.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Stored {
+    /// The file name the image was stored under.
+    pub file_name: String,
+    /// The datetime of the image.
+    pub datetime: DateTime<UTC>,
+    /// False if an identical image was already there.
+    pub created: bool,
+}
+
+/// Stores an uploaded image in a camera's directory.
+///
+/// The image is named with its EXIF capture time, read in the camera's naming timezone, or with
+/// `received` if it has none. Uploading the same image twice is fine, but uploading a different
+/// image with the same name is an error.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate chrono;
+/// # extern crate atlas;
+/// # use chrono::UTC;
+/// # use atlas::cam::Camera;
+/// # use atlas::upload;
+/// # fn main() {
+/// let camera = Camera::new("ATLAS_CAM", "data").unwrap();
+/// assert!(upload::store(&camera, b"not a jpeg", UTC::now()).is_err());
+/// # }
+/// ```
+pub fn store(camera: &Camera, bytes: &[u8], received: DateTime<UTC>) -> Result<Stored> {
+    let info = try!(jpeg::parse(bytes)
+        .map_err(|e| Error::InvalidUpload(format!("not a jpeg: {}", e))));
+    if info.truncated {
+        return Err(Error::InvalidUpload("the jpeg is truncated".to_string()));
+    }
+    if info.dimensions.is_none() {
+        return Err(Error::InvalidUpload("the jpeg has no frame header".to_string()));
+    }
+    let datetime = match info.exif_datetime
+        .and_then(|d| camera.naming().timezone.from_local_datetime(&d).single()) {
+        Some(datetime) => datetime.with_timezone(&UTC),
+        None => received,
+    };
+    let file_name = camera.file_name(&datetime);
+    if let Some(path) = camera.locate(&file_name) {
+        return existing(&path, file_name, datetime, bytes);
+    }
+    let path = camera.path().join(&file_name);
+    // Every upload gets its own temporary file, and the leading dot keeps the camera from picking
+    // it up.
+    let temporary = scratch::temporary_path(&path);
+    {
+        let mut file = try!(File::create(&temporary));
+        try!(file.write_all(bytes));
+        try!(file.sync_all());
+    }
+    // Unlike a rename, a hard link never replaces an existing file, so concurrent uploads with
+    // the same name can't overwrite each other.
+    let linked = fs::hard_link(&temporary, &path);
+    if let Err(err) = fs::remove_file(&temporary) {
+        warn!("Could not remove {}: {}", temporary.to_string_lossy(), err);
+    }
+    match linked {
+        Ok(()) => {
+            Ok(Stored {
+                file_name: file_name,
+                datetime: datetime,
+                created: true,
+            })
+        }
+        Err(ref err) if err.kind() == io::ErrorKind::AlreadyExists => {
+            existing(&path, file_name, datetime, bytes)
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Compares an upload with the image that's already stored under its name.
+fn existing(path: &Path,
+            file_name: String,
+            datetime: DateTime<UTC>,
+            bytes: &[u8])
+            -> Result<Stored> {
+    let mut existing = Vec::new();
+    try!(try!(File::open(path)).read_to_end(&mut existing));
+    if existing == bytes {
+        Ok(Stored {
+            file_name: file_name,
+            datetime: datetime,
+            created: false,
+        })
+    } else {
+        Err(Error::UploadConflict(format!("a different image is already stored as {}",
+                                          file_name)))
+    }
+}
+
+/// Iron handler that accepts images uploaded with `PUT` or `POST`.
+///
+/// The request body is the raw JPEG, and the camera is the `name` route parameter. Requests must
+/// have an `Authorization: Bearer <token>` header with one of the configured tokens.
+#[derive(Debug)]
+pub struct UploadHandler {
+    cameras: HashMap<String, Camera>,
+    tokens: Vec<String>,
+}
+
+impl UploadHandler {
+    /// Creates a new upload handler for the given cameras and tokens.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use atlas::cam::Camera;
+    /// # use atlas::upload::UploadHandler;
+    /// let handler = UploadHandler::new(vec![Camera::new("ATLAS_CAM", "data").unwrap()],
+    ///                                  vec!["secret".to_string()]);
+    /// ```
+    pub fn new(cameras: Vec<Camera>, tokens: Vec<String>) -> UploadHandler {
+        UploadHandler {
+            cameras: cameras.into_iter()
+                .map(|c| (c.name().to_ascii_lowercase(), c))
+                .collect(),
+            tokens: tokens,
+        }
+    }
+
+    fn is_authorized(&self, request: &Request) -> bool {
+        let header = match request.headers.get_raw("Authorization") {
+            Some(values) if values.len() == 1 => String::from_utf8_lossy(&values[0]).into_owned(),
+            _ => return false,
+        };
+        if !header.starts_with("Bearer ") {
+            return false;
+        }
+        let token = header["Bearer ".len()..].trim();
+        self.tokens.iter().any(|t| constant_time_eq(t.as_bytes(), token.as_bytes()))
+    }
+}
+
+/// Compares two byte strings in time that only depends on their lengths.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+impl Handler for UploadHandler {
+    fn handle(&self, request: &mut Request) -> IronResult<Response> {
+        if !self.is_authorized(request) {
+            return Ok(Response::with((status::Unauthorized, "Missing or invalid token.")));
+        }
+        let camera = {
+            let name = iexpect!(request.extensions.get::<Router>().and_then(|p| p.find("name")));
+            iexpect!(self.cameras.get(&name.to_ascii_lowercase()), status::NotFound).clone()
+        };
+        let mut bytes = Vec::new();
+        itry!(request.body.by_ref().take(MAX_UPLOAD_BYTES + 1).read_to_end(&mut bytes));
+        if bytes.len() as u64 > MAX_UPLOAD_BYTES {
+            return Ok(Response::with((status::PayloadTooLarge, "Image is too large.")));
+        }
+        let stored = match store(&camera, &bytes, UTC::now()) {
+            Ok(stored) => stored,
+            Err(Error::InvalidUpload(message)) => {
+                return Ok(Response::with((status::BadRequest, message)))
+            }
+            Err(Error::UploadConflict(message)) => {
+                return Ok(Response::with((status::Conflict, message)))
+            }
+            Err(err) => return Err(IronError::new(err, status::InternalServerError)),
+        };
+        info!("Stored upload {} for {}", stored.file_name, camera.name());
+        let mut data = BTreeMap::<String, Json>::new();
+        data.insert("camera".to_string(), camera.name().to_json());
+        data.insert("file_name".to_string(), stored.file_name.to_json());
+        data.insert("datetime".to_string(), stored.datetime.to_rfc3339().to_json());
+        let content_type = "application/json".parse::<Mime>().unwrap();
+        let code = if stored.created {
+            status::Created
+        } else {
+            status::Ok
+        };
+        Ok(Response::with((content_type, code, data.to_json().to_string())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::constant_time_eq;
+
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Read;
+    use std::thread;
+
+    use chrono::{TimeZone, UTC};
+
+    use Error;
+    use cam::Camera;
+
+    fn bytes(path: &str) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        File::open(path).unwrap().read_to_end(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn stores() {
+        let directory = env::temp_dir().join("atlas-upload");
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        let camera = Camera::new("ATLAS_CAM", &directory).unwrap();
+        let received = UTC.ymd(2016, 7, 25).and_hms(12, 15, 0);
+        let image = bytes("data/ATLAS_CAM_20160725_121500.jpg");
+
+        let stored = store(&camera, &image, received).unwrap();
+        assert_eq!("ATLAS_CAM_20160725_121500.jpg", stored.file_name);
+        assert!(stored.created);
+        assert_eq!(vec![directory.join("ATLAS_CAM_20160725_121500.jpg")],
+                   camera.paths().unwrap());
+
+        assert!(!store(&camera, &image, received).unwrap().created);
+        let other = bytes("data/ATLAS_CAM_20160725_141500.jpg");
+        assert!(store(&camera, &other, received).is_err());
+        assert!(store(&camera, &image[..image.len() / 2], received).is_err());
+    }
+
+    #[test]
+    fn concurrent() {
+        let directory = env::temp_dir().join("atlas-upload-concurrent");
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        let camera = Camera::new("ATLAS_CAM", &directory).unwrap();
+        let received = UTC.ymd(2016, 7, 25).and_hms(12, 15, 0);
+        let images = vec![bytes("data/ATLAS_CAM_20160725_121500.jpg"),
+                          bytes("data/ATLAS_CAM_20160725_141500.jpg")];
+        let threads = (0..8)
+            .map(|i| {
+                let camera = camera.clone();
+                let image = images[i % 2].clone();
+                thread::spawn(move || (store(&camera, &image, received), image))
+            })
+            .collect::<Vec<_>>();
+        let mut winner = None;
+        for thread in threads {
+            match thread.join().unwrap() {
+                (Ok(stored), image) => {
+                    if stored.created {
+                        assert!(winner.is_none());
+                        winner = Some(image);
+                    }
+                }
+                (Err(Error::UploadConflict(_)), _) => {}
+                (Err(err), _) => panic!("unexpected error: {}", err),
+            }
+        }
+        let stored = directory.join("ATLAS_CAM_20160725_121500.jpg");
+        assert_eq!(winner.unwrap(), bytes(&stored.to_string_lossy()));
+        assert_eq!(1, fs::read_dir(&directory).unwrap().count());
+    }
+
+    #[test]
+    fn tokens() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secrets"));
+    }
+}
@@ -0,0 +1,255 @@
+//! Timelapse videos made with a local `ffmpeg` binary.
+//!
+//! Gifs are limited to 256 colors and get very large for week-long sequences. Videos are much
+//! smaller and look better, but we don't want to link against a video codec, so we drive the
+//! `ffmpeg` command-line tool instead.
+
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+
+use {Error, Result};
+use animation::{AnimationEncoder, GifConfig};
+use scratch::ScratchDir;
+
+/// A video container and codec.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VideoFormat {
+    /// H.264 in an MP4 container.
+    Mp4,
+    /// VP9 in a WebM container.
+    Webm,
+}
+
+impl VideoFormat {
+    /// Returns the file extension for this format.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use atlas::video::VideoFormat;
+    /// assert_eq!("mp4", VideoFormat::Mp4.extension());
+    /// ```
+    pub fn extension(&self) -> &'static str {
+        match *self {
+            VideoFormat::Mp4 => "mp4",
+            VideoFormat::Webm => "webm",
+        }
+    }
+
+    /// Returns the content type for this format.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use atlas::video::VideoFormat;
+    /// assert_eq!("video/webm", VideoFormat::Webm.content_type());
+    /// ```
+    pub fn content_type(&self) -> &'static str {
+        match *self {
+            VideoFormat::Mp4 => "video/mp4",
+            VideoFormat::Webm => "video/webm",
+        }
+    }
+
+    fn codec_args(&self) -> &'static [&'static str] {
+        match *self {
+            VideoFormat::Mp4 => {
+                &["-c:v", "libx264", "-preset", "slow", "-crf", "23", "-movflags", "+faststart"]
+            }
+            VideoFormat::Webm => &["-c:v", "libvpx-vp9", "-b:v", "0", "-crf", "33"],
+        }
+    }
+}
+
+impl FromStr for VideoFormat {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<VideoFormat> {
+        match s {
+            "mp4" => Ok(VideoFormat::Mp4),
+            "webm" => Ok(VideoFormat::Webm),
+            _ => Err(Error::InvalidEncoder(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for VideoFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.extension())
+    }
+}
+
+/// An animation encoder that makes videos with `ffmpeg`.
+///
+/// Each image is shown for the configured delay, scaled to fit inside the configured size and
+/// padded with black to fill it.
+#[derive(Clone, Debug)]
+pub struct FfmpegEncoder {
+    format: VideoFormat,
+    program: PathBuf,
+}
+
+impl FfmpegEncoder {
+    /// Creates a new encoder that uses the `ffmpeg` on the `PATH`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use atlas::video::{FfmpegEncoder, VideoFormat};
+    /// let encoder = FfmpegEncoder::new(VideoFormat::Mp4);
+    /// ```
+    pub fn new(format: VideoFormat) -> FfmpegEncoder {
+        FfmpegEncoder {
+            format: format,
+            program: PathBuf::from("ffmpeg"),
+        }
+    }
+
+    /// Sets the path to the `ffmpeg` binary.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use atlas::video::{FfmpegEncoder, VideoFormat};
+    /// let encoder = FfmpegEncoder::new(VideoFormat::Mp4).program("/usr/local/bin/ffmpeg");
+    /// ```
+    pub fn program<P: AsRef<Path>>(mut self, program: P) -> FfmpegEncoder {
+        self.program = program.as_ref().to_path_buf();
+        self
+    }
+
+    fn args(&self, list: &Path, output: &Path, config: &GifConfig) -> Vec<String> {
+        // yuv420p, which every player supports, needs even dimensions.
+        let width = config.width - config.width % 2;
+        let height = config.height - config.height % 2;
+        let mut args = vec!["-y".to_string(),
+                            "-loglevel".to_string(),
+                            "error".to_string(),
+                            "-f".to_string(),
+                            "concat".to_string(),
+                            "-safe".to_string(),
+                            "0".to_string(),
+                            "-i".to_string(),
+                            list.to_string_lossy().into_owned(),
+                            "-vf".to_string(),
+                            format!("scale={w}:{h}:force_original_aspect_ratio=decrease,\
+                                     pad={w}:{h}:(ow-iw)/2:(oh-ih)/2",
+                                    w = width,
+                                    h = height),
+                            "-pix_fmt".to_string(),
+                            "yuv420p".to_string()];
+        args.extend(self.format.codec_args().iter().map(|s| s.to_string()));
+        args.push(output.to_string_lossy().into_owned());
+        args
+    }
+
+    fn encode_in(&self,
+                 directory: &Path,
+                 paths: &[PathBuf],
+                 config: &GifConfig)
+                 -> Result<Vec<u8>> {
+        let list = directory.join("frames.txt");
+        try!(concat_list(try!(File::create(&list)),
+                         paths,
+                         config.delay.num_milliseconds() as f64 / 1000.0));
+        let output = directory.join(format!("timelapse.{}", self.format.extension()));
+        let args = self.args(&list, &output, config);
+        let result = try!(Command::new(&self.program).args(&args[..]).output());
+        if !result.status.success() {
+            return Err(Error::Ffmpeg(String::from_utf8_lossy(&result.stderr).trim().to_string()));
+        }
+        let mut bytes = Vec::new();
+        try!(try!(File::open(&output)).read_to_end(&mut bytes));
+        Ok(bytes)
+    }
+}
+
+/// Writes an ffmpeg concat list that shows each image for `seconds`.
+fn concat_list<W: Write>(mut write: W, paths: &[PathBuf], seconds: f64) -> Result<()> {
+    for path in paths {
+        let path = try!(fs::canonicalize(path));
+        try!(writeln!(write,
+                      "file '{}'",
+                      path.to_string_lossy().replace("'", "'\\''")));
+        try!(writeln!(write, "duration {}", seconds));
+    }
+    // The concat demuxer ignores the duration of the last file unless it is listed again.
+    if let Some(path) = paths.last() {
+        let path = try!(fs::canonicalize(path));
+        try!(writeln!(write,
+                      "file '{}'",
+                      path.to_string_lossy().replace("'", "'\\''")));
+    }
+    Ok(())
+}
+
+impl AnimationEncoder for FfmpegEncoder {
+    fn encode(&self, paths: &[PathBuf], config: &GifConfig) -> Result<Vec<u8>> {
+        if paths.is_empty() {
+            return Ok(Vec::new());
+        }
+        let directory = try!(ScratchDir::new("atlas-ffmpeg"));
+        self.encode_in(directory.path(), paths, config)
+    }
+
+    fn content_type(&self) -> &'static str {
+        self.format.content_type()
+    }
+
+    fn extension(&self) -> &'static str {
+        self.format.extension()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::concat_list;
+
+    use std::path::{Path, PathBuf};
+
+    use animation::GifConfig;
+
+    #[test]
+    fn format_from_str() {
+        assert_eq!(VideoFormat::Mp4, "mp4".parse().unwrap());
+        assert_eq!(VideoFormat::Webm, "webm".parse().unwrap());
+        assert!("avi".parse::<VideoFormat>().is_err());
+    }
+
+    #[test]
+    fn list() {
+        let mut list = Vec::new();
+        concat_list(&mut list,
+                    &[PathBuf::from("data/ATLAS_CAM_20160725_121500.jpg"),
+                      PathBuf::from("data/ATLAS_CAM_20160725_141500.jpg")],
+                    0.5)
+            .unwrap();
+        let list = String::from_utf8(list).unwrap();
+        let lines = list.lines().collect::<Vec<_>>();
+        assert_eq!(5, lines.len());
+        assert!(lines[0].starts_with("file '/"));
+        assert!(lines[0].ends_with("/data/ATLAS_CAM_20160725_121500.jpg'"));
+        assert_eq!("duration 0.5", lines[1]);
+        assert_eq!(lines[2], lines[4]);
+    }
+
+    #[test]
+    fn even_dimensions() {
+        let encoder = FfmpegEncoder::new(VideoFormat::Mp4);
+        let args = encoder.args(Path::new("list.txt"),
+                                Path::new("out.mp4"),
+                                &GifConfig {
+                                    width: 513,
+                                    height: 385,
+                                    ..Default::default()
+                                });
+        assert!(args.contains(&"scale=512:384:force_original_aspect_ratio=decrease,\
+                                pad=512:384:(ow-iw)/2:(oh-ih)/2"
+            .to_string()));
+        assert_eq!("out.mp4", args.last().unwrap());
+    }
+}
@@ -2,11 +2,15 @@
 //!
 //! E.g. watch a directory to trigger a re-read of the heartbeat messages.
 
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
 use std::io;
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, RwLock};
-use std::sync::mpsc::channel;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{RecvTimeoutError, channel};
+use std::thread;
+use std::time::{Duration, SystemTime};
 
 use chrono::UTC;
 
@@ -16,10 +20,30 @@ use sbd::storage::FilesystemStorage;
 
 use Result;
 use heartbeat::{HeartbeatV1, IntoHeartbeats};
+use sutron::{self, Reader, Record};
+
+/// The default time a directory must be quiet before a watcher refreshes, in milliseconds.
+pub const DEFAULT_DEBOUNCE_MS: u64 = 2000;
+
+/// The most times we'll wait for a file to stop growing before refreshing anyways.
+const MAX_WRITE_WAITS: usize = 30;
+
+/// The most debounce periods we'll wait for a directory to go quiet before refreshing anyways.
+const MAX_DEBOUNCE_WAITS: usize = 10;
+
+/// How often an idle watcher checks whether it's been stopped, in milliseconds.
+const STOP_CHECK_MS: u64 = 1000;
 
 /// A trait that can be used to watch a directory.
 ///
 /// This restarts the watcher if we get a new directory, to pick up on new files.
+///
+/// A single new file creates several filesystem events, so events are coalesced: after the first
+/// event, the watcher waits until the directory has been quiet for the debounce period and until
+/// every changed file has stopped growing, and then refreshes once. A directory that never goes
+/// quiet is refreshed anyways after `MAX_DEBOUNCE_WAITS` debounce periods, so it can't go stale.
+/// While the directory is idle, the watcher checks every `STOP_CHECK_MS` whether it's been
+/// stopped.
 pub trait DirectoryWatcher {
     /// Enter the infinite watching loop.
     fn watch(&mut self) -> Result<()> {
@@ -27,60 +51,203 @@ pub trait DirectoryWatcher {
         let mut watcher: RecommendedWatcher = try!(Watcher::new(tx));
         try!(watcher.watch(&self.directory()));
         loop {
-            match rx.recv() {
-                Ok(notify::Event { path: Some(path), op: Ok(_) }) => {
-                    match path.metadata() {
-                        Ok(metadata) => {
-                            if metadata.is_dir() {
-                                try!(watcher.unwatch(&self.directory()));
-                                try!(watcher.watch(&self.directory()));
-                                info!("Watcher on {} restarted due to activity at {}",
-                                      self.directory().to_string_lossy(),
-                                      path.to_string_lossy());
-                            }
-                            match self.refresh() {
-                                Ok(()) => info!("Refresh: {}", path.to_string_lossy()),
-                                Err(err) => {
-                                    error!("Error while refreshing in {}: {}",
-                                           self.directory().to_string_lossy(),
-                                           err)
-                                }
-                            }
+            let mut paths = BTreeSet::new();
+            match rx.recv_timeout(Duration::from_millis(STOP_CHECK_MS)) {
+                Ok(event) => add_event_path(&mut paths, event),
+                Err(RecvTimeoutError::Timeout) => {
+                    if self.stopped() {
+                        info!("Watcher on {} stopped", self.directory().to_string_lossy());
+                        return Ok(());
+                    }
+                    continue;
+                }
+                Err(e) => {
+                    error!("Error while receiving notify message: {}", e);
+                    continue;
+                }
+            }
+            for _ in 0..MAX_DEBOUNCE_WAITS {
+                thread::sleep(self.debounce());
+                let mut quiet = true;
+                while let Ok(event) = rx.try_recv() {
+                    add_event_path(&mut paths, event);
+                    quiet = false;
+                }
+                if quiet {
+                    break;
+                }
+            }
+            wait_for_writes(&paths, self.debounce());
+            if self.stopped() {
+                info!("Watcher on {} stopped", self.directory().to_string_lossy());
+                return Ok(());
+            }
+
+            let mut changed = false;
+            for path in paths.iter() {
+                match path.metadata() {
+                    Ok(metadata) => {
+                        if metadata.is_dir() {
+                            try!(watcher.unwatch(&self.directory()));
+                            try!(watcher.watch(&self.directory()));
+                            info!("Watcher on {} restarted due to activity at {}",
+                                  self.directory().to_string_lossy(),
+                                  path.to_string_lossy());
                         }
-                        Err(err) => {
-                            match err.kind() {
-                                io::ErrorKind::NotFound => {}
-                                _ => {
-                                    error!("Error while retrieving path metadata for {}: {}",
-                                           path.to_string_lossy(),
-                                           err)
-                                }
+                        changed = true;
+                    }
+                    Err(err) => {
+                        match err.kind() {
+                            io::ErrorKind::NotFound => {}
+                            _ => {
+                                error!("Error while retrieving path metadata for {}: {}",
+                                       path.to_string_lossy(),
+                                       err)
                             }
                         }
                     }
                 }
-                Err(e) => error!("Error while receiving notify message: {}", e),
-                _ => (),
+            }
+            if !changed {
+                continue;
+            }
+            match self.refresh() {
+                Ok(()) => {
+                    info!("Refresh: {} ({} changed paths)",
+                          self.directory().to_string_lossy(),
+                          paths.len())
+                }
+                Err(err) => {
+                    error!("Error while refreshing in {}: {}",
+                           self.directory().to_string_lossy(),
+                           err)
+                }
             }
         }
     }
 
+    /// Returns how long the directory must be quiet before `refresh` is called.
+    fn debounce(&self) -> Duration {
+        Duration::from_millis(DEFAULT_DEBOUNCE_MS)
+    }
+
     /// Returns the path of the directory to be watched.
     fn directory(&self) -> &Path;
 
     /// Called whenever changes happen in the watched directory.
     fn refresh(&mut self) -> Result<()>;
+
+    /// Returns true if the watching loop should end.
+    ///
+    /// This is checked while the directory is idle, and whenever there is activity in the
+    /// directory, before refreshing.
+    fn stopped(&self) -> bool {
+        false
+    }
+}
+
+/// Wraps a directory watcher so that it can be stopped from another thread.
+///
+/// A stopped watcher exits without refreshing, even if nothing happens in the directory.
+#[derive(Debug)]
+pub struct Stoppable<W> {
+    watcher: W,
+    stop: Arc<AtomicBool>,
+}
+
+impl<W: DirectoryWatcher> Stoppable<W> {
+    /// Wraps a watcher that stops once `stop` is set.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use std::sync::{Arc, RwLock};
+    /// # use std::sync::atomic::{AtomicBool, Ordering};
+    /// # use atlas::watch::{DirectoryWatcher, HeartbeatWatcher, Stoppable};
+    /// let heartbeats = Arc::new(RwLock::new(Vec::new()));
+    /// let watcher = HeartbeatWatcher::new("data", Vec::new(), heartbeats);
+    /// let stop = Arc::new(AtomicBool::new(false));
+    /// let watcher = Stoppable::new(watcher, stop.clone());
+    /// stop.store(true, Ordering::SeqCst);
+    /// assert!(watcher.stopped());
+    /// ```
+    pub fn new(watcher: W, stop: Arc<AtomicBool>) -> Stoppable<W> {
+        Stoppable {
+            watcher: watcher,
+            stop: stop,
+        }
+    }
+}
+
+impl<W: DirectoryWatcher> DirectoryWatcher for Stoppable<W> {
+    fn debounce(&self) -> Duration {
+        self.watcher.debounce()
+    }
+
+    fn directory(&self) -> &Path {
+        self.watcher.directory()
+    }
+
+    fn refresh(&mut self) -> Result<()> {
+        self.watcher.refresh()
+    }
+
+    fn stopped(&self) -> bool {
+        self.stop.load(Ordering::SeqCst) || self.watcher.stopped()
+    }
+}
+
+fn add_event_path(paths: &mut BTreeSet<PathBuf>, event: notify::Event) {
+    if let notify::Event { path: Some(path), op: Ok(_) } = event {
+        paths.insert(path);
+    }
+}
+
+/// Waits until none of the files are growing, checking every `interval`.
+///
+/// Gives up after a while, so that a file that never stops growing can't stall the watcher.
+fn wait_for_writes(paths: &BTreeSet<PathBuf>, interval: Duration) {
+    let mut sizes = file_sizes(paths);
+    for _ in 0..MAX_WRITE_WAITS {
+        if !is_being_written(paths, &sizes, interval) {
+            return;
+        }
+        thread::sleep(interval);
+        sizes = file_sizes(paths);
+    }
+    warn!("Files were still being written after {} checks, refreshing anyways",
+          MAX_WRITE_WAITS);
+}
+
+fn file_sizes(paths: &BTreeSet<PathBuf>) -> Vec<Option<u64>> {
+    paths.iter().map(|p| fs::metadata(p).ok().map(|m| m.len())).collect()
+}
+
+/// Returns true if any of the files has changed size since `sizes` were taken, or was modified
+/// more recently than `interval` ago.
+fn is_being_written(paths: &BTreeSet<PathBuf>, sizes: &[Option<u64>], interval: Duration) -> bool {
+    if file_sizes(paths) != sizes {
+        return true;
+    }
+    paths.iter().any(|path| {
+        fs::metadata(path)
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+            .map(|age| age < interval)
+            .unwrap_or(false)
+    })
 }
 
 /// Watches a directory and refreshes a vector of heartbeats in a thread-safe way.
 ///
 /// Use this watcher to get a `Arc<RwLock<Vec<HeartbeatV1>>>>` that you can trust will be
-/// up-to-date.
+/// up-to-date. One watcher can refresh the heartbeats of several stations, each from its own
+/// modems, while only reading the directory once.
 #[derive(Debug)]
 pub struct HeartbeatWatcher {
     directory: PathBuf,
-    imeis: Vec<String>,
-    heartbeats: Arc<RwLock<Vec<HeartbeatV1>>>,
+    stations: Vec<(Vec<String>, Arc<RwLock<Vec<HeartbeatV1>>>)>,
 }
 
 impl HeartbeatWatcher {
@@ -102,10 +269,29 @@ impl HeartbeatWatcher {
                                -> HeartbeatWatcher {
         HeartbeatWatcher {
             directory: directory.as_ref().to_path_buf(),
-            imeis: imeis,
-            heartbeats: heartbeats,
+            stations: vec![(imeis, heartbeats)],
         }
     }
+
+    /// Also refreshes the heartbeats from another station's modems.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use std::sync::{Arc, RwLock};
+    /// # use atlas::watch::HeartbeatWatcher;
+    /// let watcher = HeartbeatWatcher::new("data",
+    ///                                     vec!["300234063909200".to_string()],
+    ///                                     Arc::new(RwLock::new(Vec::new())))
+    ///     .station(vec!["300234064737720".to_string()], Arc::new(RwLock::new(Vec::new())));
+    /// ```
+    pub fn station(mut self,
+                   imeis: Vec<String>,
+                   heartbeats: Arc<RwLock<Vec<HeartbeatV1>>>)
+                   -> HeartbeatWatcher {
+        self.stations.push((imeis, heartbeats));
+        self
+    }
 }
 
 impl DirectoryWatcher for HeartbeatWatcher {
@@ -121,20 +307,140 @@ impl DirectoryWatcher for HeartbeatWatcher {
             let entry = messages.entry(message.imei().to_string()).or_insert(Vec::new());
             entry.push(message);
         }
-        let mut heartbeats = self.heartbeats.write().unwrap();
-        heartbeats.clear();
-        for (_, mut messages) in messages {
-            messages.sort();
-            heartbeats.extend(try!(messages.into_heartbeats())
-                .into_iter()
-                .filter_map(|h| h.ok()));
+        for &(ref imeis, ref station_heartbeats) in &self.stations {
+            let mut heartbeats = Vec::new();
+            for imei in imeis {
+                if let Some(mut messages) = messages.remove(imei) {
+                    messages.sort();
+                    heartbeats.extend(try!(messages.into_heartbeats())
+                        .into_iter()
+                        .filter_map(|h| h.ok()));
+                }
+            }
+            heartbeats.sort_by_key(|h| {
+                h.messages
+                    .get(0)
+                    .map(|m| m.time_of_session())
+                    .unwrap_or(UTC::now())
+            });
+            *station_heartbeats.write().unwrap() = heartbeats;
         }
-        heartbeats.sort_by_key(|h| {
-            h.messages
-                .get(0)
-                .map(|m| m.time_of_session())
-                .unwrap_or(UTC::now())
-        });
         Ok(())
     }
 }
+
+/// Watches a directory of Sutron log files and refreshes a vector of records.
+///
+/// Every file in the directory is read as a Sutron log. Logs are read leniently, so corrupted
+/// lines are skipped rather than failing the whole log.
+#[derive(Debug)]
+pub struct SutronWatcher {
+    directory: PathBuf,
+    records: Arc<RwLock<Vec<Record>>>,
+}
+
+impl SutronWatcher {
+    /// Creates a new watcher for a directory of Sutron logs.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use std::sync::{Arc, RwLock};
+    /// # use atlas::watch::SutronWatcher;
+    /// let records = Arc::new(RwLock::new(Vec::new()));
+    /// let watcher = SutronWatcher::new("data", records);
+    /// ```
+    pub fn new<P: AsRef<Path>>(directory: P, records: Arc<RwLock<Vec<Record>>>) -> SutronWatcher {
+        SutronWatcher {
+            directory: directory.as_ref().to_path_buf(),
+            records: records,
+        }
+    }
+}
+
+impl DirectoryWatcher for SutronWatcher {
+    fn directory(&self) -> &Path {
+        self.directory.as_path()
+    }
+
+    fn refresh(&mut self) -> Result<()> {
+        let mut new_records = Vec::new();
+        for entry in try!(fs::read_dir(&self.directory)) {
+            let path = try!(entry).path();
+            if !path.is_file() {
+                continue;
+            }
+            let reader = match Reader::from_path(&path) {
+                Ok(reader) => reader.lenient(true),
+                Err(err) => {
+                    warn!("Could not open sutron log {}: {}", path.to_string_lossy(), err);
+                    continue;
+                }
+            };
+            for entry in reader {
+                match entry {
+                    Ok(entry) => new_records.push(entry.record),
+                    Err(err) => {
+                        warn!("Error while reading sutron log {}: {}",
+                              path.to_string_lossy(),
+                              err);
+                        break;
+                    }
+                }
+            }
+        }
+        // Logs from different site visits overlap, so we remove the duplicate records.
+        sutron::sort_records(&mut new_records);
+        let mut records = self.records.write().unwrap();
+        *records = new_records;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DirectoryWatcher, HeartbeatWatcher, Stoppable, file_sizes, is_being_written};
+
+    use std::collections::BTreeSet;
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Write;
+    use std::path::PathBuf;
+    use std::sync::{Arc, RwLock};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::mpsc::channel;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn being_written() {
+        let path = env::temp_dir().join("atlas-being-written.jpg");
+        let mut file = File::create(&path).unwrap();
+        file.write_all(b"partial").unwrap();
+        let mut paths = BTreeSet::new();
+        paths.insert(path);
+        let sizes = file_sizes(&paths);
+        assert!(is_being_written(&paths, &sizes, Duration::from_secs(60)));
+        file.write_all(b" and more").unwrap();
+        assert!(is_being_written(&paths, &sizes, Duration::from_millis(0)));
+
+        let mut paths = BTreeSet::new();
+        paths.insert(PathBuf::from("data/ATLAS_CAM_20160725_121500.jpg"));
+        let sizes = file_sizes(&paths);
+        assert!(!is_being_written(&paths, &sizes, Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn stop_while_idle() {
+        let directory = env::temp_dir().join("atlas-stop-while-idle");
+        fs::create_dir_all(&directory).unwrap();
+        let heartbeats = Arc::new(RwLock::new(Vec::new()));
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut watcher = Stoppable::new(HeartbeatWatcher::new(&directory, Vec::new(), heartbeats),
+                                         stop.clone());
+        let (tx, rx) = channel();
+        thread::spawn(move || tx.send(watcher.watch().is_ok()).unwrap());
+        stop.store(true, Ordering::SeqCst);
+        assert!(rx.recv_timeout(Duration::from_secs(10)).unwrap());
+    }
+}
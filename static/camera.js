@@ -0,0 +1,147 @@
+var camera = $("#camera").data("camera").toLowerCase();
+var base = "/cameras/" + camera;
+var days = {};
+var month = null;
+
+function pad(n) {
+    return n < 10 ? "0" + n : "" + n;
+}
+
+function isoDate(date) {
+    return date.getUTCFullYear() + "-" + pad(date.getUTCMonth() + 1) + "-" + pad(date.getUTCDate());
+}
+
+function drawCalendar() {
+    var first = new Date(Date.UTC(month.getUTCFullYear(), month.getUTCMonth(), 1));
+    var body = $("#calendar-days").empty();
+    var row = $("<tr>");
+    for (var i = 0; i < first.getUTCDay(); i++) {
+        row.append("<td></td>");
+    }
+    var date = first;
+    while (date.getUTCMonth() == first.getUTCMonth()) {
+        var key = isoDate(date);
+        var cell = $("<td>");
+        if (days[key]) {
+            cell.append($("<a>").attr("href", "#" + key).attr("title", days[key] + " images").text(date.getUTCDate()));
+        } else {
+            cell.addClass("text-muted").text(date.getUTCDate());
+        }
+        row.append(cell);
+        if (date.getUTCDay() == 6) {
+            body.append(row);
+            row = $("<tr>");
+        }
+        date = new Date(date.getTime() + 24 * 60 * 60 * 1000);
+    }
+    body.append(row);
+    $("#calendar-month").text(first.toISOString().substring(0, 7));
+}
+
+function showDay(date, page) {
+    var params = { page: page || 1 };
+    if (date) {
+        params.date = date;
+    }
+    $.getJSON(base + "/images.json", params, function(day) {
+        $("#day-date").text(day.date);
+        $("#day-total").text("(" + day.total + " images)");
+        $("#day-previous").attr("href", day.previous_date ? "#" + day.previous_date : "#")
+            .parent().toggleClass("disabled", !day.previous_date);
+        $("#day-next").attr("href", day.next_date ? "#" + day.next_date : "#")
+            .parent().toggleClass("disabled", !day.next_date);
+
+        var images = $("#images").empty();
+        $.each(day.images, function(i, image) {
+            var thumbnail = $("<div class=\"thumbnail\">")
+                .append($("<a>").attr("href", image.url).append($("<img>").attr("src", image.url).attr("alt", image.file_name)))
+                .append($("<div class=\"caption\">").text(image.datetime + (image.corrupted ? " (corrupted)" : "")));
+            images.append($("<div class=\"col-xs-6 col-sm-4\">").append(thumbnail));
+        });
+
+        var pages = $("#pages").empty();
+        for (var p = 1; p <= day.pages; p++) {
+            var link = $("<a href=\"#\">").text(p).data("page", p).click(function(event) {
+                event.preventDefault();
+                showDay(day.date, $(this).data("page"));
+            });
+            pages.append($("<li>").toggleClass("active", p == day.page).append(link));
+        }
+
+        month = new Date(day.date + "T00:00:00Z");
+        drawCalendar();
+    });
+}
+
+$("#calendar-previous").click(function(event) {
+    event.preventDefault();
+    month = new Date(Date.UTC(month.getUTCFullYear(), month.getUTCMonth() - 1, 1));
+    drawCalendar();
+});
+
+$("#calendar-next").click(function(event) {
+    event.preventDefault();
+    month = new Date(Date.UTC(month.getUTCFullYear(), month.getUTCMonth() + 1, 1));
+    drawCalendar();
+});
+
+$(window).on("hashchange", function() {
+    showDay(window.location.hash.substring(1));
+});
+
+$.getJSON(base + "/calendar.json", function(calendar) {
+    days = calendar.days;
+    showDay(window.location.hash.substring(1));
+});
+
+$.get(base + "/change.csv", function(csv) {
+    var data = [];
+    var flagged = $("#change-flagged").empty();
+    $.each(csv.trim().split("\n").slice(1), function(i, line) {
+        var fields = line.split(",");
+        var datetime = new Date(fields[0]);
+        data.push([datetime, parseFloat(fields[1]), parseFloat(fields[2])]);
+        if (fields[3] == "true") {
+            var date = fields[0].substring(0, 10);
+            flagged.append($("<li>").append($("<a>").attr("href", "#" + date).text(fields[4])));
+        }
+    });
+    if (data.length == 0) {
+        return;
+    }
+    $("#change-csv").attr("href", base + "/change.csv");
+    $("#change").removeClass("hidden");
+    new Dygraph(document.getElementById("fig-change"), data, {
+        labels: ["Datetime", "Score", "Threshold"],
+        labelsUTC: true,
+        showRangeSelector: true,
+        height: 300,
+        series: {
+            Threshold: { strokePattern: Dygraph.DASHED_LINE }
+        }
+    });
+});
+
+$.get(base + "/quality.csv", function(csv) {
+    var brightness = [];
+    var sharpness = [];
+    $.each(csv.trim().split("\n").slice(1), function(i, line) {
+        var fields = line.split(",");
+        var datetime = new Date(fields[0]);
+        brightness.push([datetime, parseFloat(fields[1]), parseFloat(fields[3])]);
+        sharpness.push([datetime, parseFloat(fields[2])]);
+    });
+    if (brightness.length == 0) {
+        return;
+    }
+    $("#quality-csv").attr("href", base + "/quality.csv");
+    $("#quality").removeClass("hidden");
+    var options = {
+        labelsUTC: true,
+        height: 200
+    };
+    new Dygraph(document.getElementById("fig-brightness"), brightness,
+        $.extend({ labels: ["Datetime", "Brightness", "Saturated (%)"] }, options));
+    new Dygraph(document.getElementById("fig-sharpness"), sharpness,
+        $.extend({ labels: ["Datetime", "Sharpness"], showRangeSelector: true }, options));
+});